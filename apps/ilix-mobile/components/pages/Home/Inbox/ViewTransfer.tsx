@@ -120,7 +120,7 @@ const ViewTransfer: React.FC<ViewTransferNavigationProps> = ({ navigation, route
   };
 
   const fetchFilesInfo = async (refresh = false) => {
-    if (transfer === undefined) return;
+    if (transfer === undefined || pool_key_phrase === undefined) return;
     type CachedFilesInfo = { fi: FileInfo[]; exp: number };
 
     let success = false;
@@ -135,7 +135,7 @@ const ViewTransfer: React.FC<ViewTransferNavigationProps> = ({ navigation, route
           {
             files_ids: transfer.files_id,
           },
-          undefined
+          { pool_kp: pool_key_phrase }
         );
 
         success = apiSuccess;
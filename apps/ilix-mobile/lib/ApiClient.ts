@@ -16,7 +16,10 @@ type GetPath<T extends GetRoutes> = T extends "/file-transfer/{device_id}/all"
 type GetQuery<T extends GetRoutes> = T extends "/files/info?files_ids={files_ids}"
   ? { files_ids: string[] }
   : undefined;
-type GetAuth<T extends GetRoutes> = T extends "/pool" | "/file-transfer/{device_id}/all"
+type GetAuth<T extends GetRoutes> = T extends
+  | "/pool"
+  | "/file-transfer/{device_id}/all"
+  | "/files/info?files_ids={files_ids}"
   ? { pool_kp: string }
   : undefined;
 type GetReturns<T extends GetRoutes> = T extends "/pool"
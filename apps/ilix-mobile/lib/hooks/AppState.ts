@@ -179,6 +179,22 @@ const useAppState = (): AppStateShape => {
           return [l, s, newTransfers, refresh];
         });
       });
+      sse_handler.addEventListener("on_file_added", (transfer_id, files_id) => {
+        // the server only sends the new files, not the whole transfer
+        setTransferState(([l, s, transfers, refresh]) => {
+          const update_index = transfers.findIndex(({ _id }) => _id === transfer_id);
+          if (update_index === -1) return [l, s, transfers, refresh];
+
+          const newTransfers = [...transfers];
+          const { files_id: old_files_id } = newTransfers[update_index];
+          newTransfers[update_index] = {
+            ...newTransfers[update_index],
+            files_id: [...old_files_id, ...files_id.filter((id) => !old_files_id.includes(id))],
+          };
+
+          return [l, s, newTransfers, refresh];
+        });
+      });
       sse_handler.addEventListener("on_logout", () => {
         pushToast("This pool has been deleted");
         logOut();
@@ -8,11 +8,13 @@ import { SERVER_BASE_URL } from "./ApiClient";
 /**
  * @type the different events that the user can listen to
  */
-type Events = "on_pool" | "on_transfer" | "on_logout" | "onerror" | "onclosed";
+type Events = "on_pool" | "on_transfer" | "on_file_added" | "on_logout" | "onerror" | "onclosed";
 type callbackFn<T extends Events> = T extends "on_pool"
   ? (pool: DevicesPool) => void
   : T extends "on_transfer"
   ? (transfer: FilePoolTransfer) => void
+  : T extends "on_file_added"
+  ? (transfer_id: string, files_id: string[]) => void
   : T extends "on_logout" | "onclosed"
   ? () => void
   : T extends "onerror"
@@ -31,7 +33,7 @@ type SSEData<T extends DevicesPool | FilePoolTransfer> = T extends DevicesPool
 /**
  * @type the different events that the sse server send
  */
-type CustomEvents = "connected" | "pool" | "transfer" | "logout";
+type CustomEvents = "connected" | "pool" | "transfer" | "file_added" | "logout";
 
 /**
  * @class Handle the client part of an SSE connection
@@ -50,6 +52,7 @@ export default class SSEClient {
 
   private poolListeners: { [K in string]: callbackFn<"on_pool"> } = {};
   private transferListeners: { [K in string]: callbackFn<"on_transfer"> } = {};
+  private fileAddedListeners: { [K in string]: callbackFn<"on_file_added"> } = {};
   private logoutListeners: { [K in string]: callbackFn<"on_logout"> } = {};
   private errorListeners: { [K in string]: callbackFn<"onerror"> } = {};
   private closeListeners: { [K in string]: callbackFn<"onclosed"> } = {};
@@ -145,6 +148,20 @@ export default class SSEClient {
         Object.values(this.transferListeners).forEach((cb) => cb(transfer));
       } catch {}
     });
+    this.sse_connection.addEventListener("file_added", (msg) => {
+      if (msg.type !== "file_added" || typeof msg.data !== "string") return;
+
+      try {
+        const resp_data: { FileAdded?: { transfer_id: string; files_id: string[] } } = JSON.parse(
+          msg.data
+        );
+        const added = resp_data.FileAdded;
+        if (added === undefined || !("transfer_id" in added) || !("files_id" in added)) return;
+        Object.values(this.fileAddedListeners).forEach((cb) =>
+          cb(added.transfer_id, added.files_id)
+        );
+      } catch {}
+    });
     this.sse_connection.addEventListener("logout", (msg) => {
       if (msg.type !== "logout" || typeof msg.data !== "string") return;
       Object.values(this.logoutListeners).forEach((cb) => cb());
@@ -173,6 +190,9 @@ export default class SSEClient {
       case "on_transfer":
         this.transferListeners[id] = cb as callbackFn<"on_transfer">;
         break;
+      case "on_file_added":
+        this.fileAddedListeners[id] = cb as callbackFn<"on_file_added">;
+        break;
       case "on_logout":
         this.logoutListeners[id] = cb as callbackFn<"on_logout">;
         break;
@@ -201,6 +221,9 @@ export default class SSEClient {
       case "on_transfer":
         delete this.transferListeners[id];
         break;
+      case "on_file_added":
+        delete this.fileAddedListeners[id];
+        break;
       case "on_logout":
         delete this.logoutListeners[id];
         break;
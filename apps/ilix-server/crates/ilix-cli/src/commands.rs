@@ -0,0 +1,398 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context as _};
+use futures_util::StreamExt;
+use ilix_sdk::{Client, DevicesPool, SSEData, Upload};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::config::Config;
+
+pub struct Context {
+    pub config: Config,
+    pub config_path: PathBuf,
+    /// print json instead of text
+    pub json: bool,
+}
+
+impl Context {
+    fn client(&self) -> anyhow::Result<Client> {
+        Ok(Client::new(&self.config.server).with_key_phrase(self.config.key_phrase()?))
+    }
+
+    /// prints `value` as json, or `text` for humans
+    fn print<T: Serialize>(&self, value: &T, text: impl FnOnce() -> String) -> anyhow::Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string(value)?);
+        } else {
+            println!("{}", text());
+        }
+        Ok(())
+    }
+}
+
+// pool
+
+pub async fn pool_create(
+    ctx: &mut Context,
+    name: &str,
+    device_name: Option<String>,
+) -> anyhow::Result<()> {
+    if let Some(device_name) = device_name {
+        ctx.config.device_name = device_name;
+    }
+
+    let client = Client::new(&ctx.config.server);
+    let key_phrase = client
+        .create_pool(name, &ctx.config.device_id, &ctx.config.device_name)
+        .await?;
+    ctx.config.key_phrase = Some(key_phrase.clone());
+    ctx.config.save(&ctx.config_path)?;
+
+    ctx.print(&json!({ "key_phrase": key_phrase }), || {
+        format!("created the pool \"{name}\", join it from your other devices with: {key_phrase}")
+    })
+}
+
+pub async fn pool_join(
+    ctx: &mut Context,
+    key_phrase: String,
+    device_name: Option<String>,
+) -> anyhow::Result<()> {
+    if let Some(device_name) = device_name {
+        ctx.config.device_name = device_name;
+    }
+
+    let client = Client::new(&ctx.config.server).with_key_phrase(&key_phrase);
+    let pool = client
+        .join_pool(&ctx.config.device_id, &ctx.config.device_name)
+        .await?;
+    ctx.config.key_phrase = Some(key_phrase);
+    ctx.config.save(&ctx.config_path)?;
+
+    ctx.print(&pool, || format!("joined the pool \"{}\"", pool.pool_name))
+}
+
+pub async fn pool_leave(ctx: &mut Context) -> anyhow::Result<()> {
+    ctx.client()?.leave_pool(&ctx.config.device_id).await?;
+    ctx.config.key_phrase = None;
+    ctx.config.save(&ctx.config_path)?;
+
+    ctx.print(&json!({}), || "left the pool".to_string())
+}
+
+pub async fn pool_show(ctx: &Context) -> anyhow::Result<()> {
+    let pool = ctx.client()?.get_pool().await?;
+    ctx.print(&pool, || {
+        let mut text = format!(
+            "{}\nkey phrase: {}\ndevices:",
+            pool.pool_name,
+            ctx.config.key_phrase.as_deref().unwrap_or_default()
+        );
+        for device_id in &pool.devices_id {
+            let name = pool
+                .devices_id_to_name
+                .get(device_id)
+                .map_or("?", String::as_str);
+            let online = if pool.devices_online.contains(device_id) {
+                " (online)"
+            } else {
+                ""
+            };
+            let this = if device_id == &ctx.config.device_id {
+                " (this device)"
+            } else {
+                ""
+            };
+            text.push_str(&format!("\n  {name} [{device_id}]{online}{this}"));
+        }
+        text
+    })
+}
+
+// transfers
+
+pub async fn send(ctx: &Context, device: &str, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let client = ctx.client()?;
+    let pool = client.get_pool().await?;
+    let to = resolve_device(&pool, device)?;
+
+    let mut uploads = Vec::with_capacity(files.len());
+    for path in &files {
+        let upload = Upload::from_path(path)
+            .await
+            .with_context(|| format!("can't read {}", path.display()))?;
+        uploads.push(upload);
+    }
+    let transfer_id = client
+        .create_transfer(&ctx.config.device_id, &to, uploads)
+        .await?;
+
+    ctx.print(&json!({ "transfer_id": transfer_id }), || {
+        format!(
+            "sent {} file(s) to {device} (transfer {transfer_id})",
+            files.len()
+        )
+    })
+}
+
+pub async fn inbox(ctx: &Context) -> anyhow::Result<()> {
+    let client = ctx.client()?;
+    let transfers = client.transfers(&ctx.config.device_id).await?;
+    if ctx.json {
+        return ctx.print(&transfers, String::new);
+    }
+    if transfers.is_empty() {
+        println!("no transfers");
+        return Ok(());
+    }
+
+    let pool = client.get_pool().await?;
+    for transfer in &transfers {
+        let from = pool
+            .devices_id_to_name
+            .get(&transfer.from)
+            .unwrap_or(&transfer.from);
+        println!("{} from {from}", transfer._id);
+        if transfer.files_id.is_empty() {
+            continue;
+        }
+        for file in client.files_info(&transfer.files_id).await? {
+            println!(
+                "  {} {} ({} bytes)",
+                file._id.to_hex(),
+                file.filename,
+                file.length
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn get(ctx: &Context, transfer_id: &str, out: &Path) -> anyhow::Result<()> {
+    let client = ctx.client()?;
+    let transfer = client
+        .transfers(&ctx.config.device_id)
+        .await?
+        .into_iter()
+        .find(|transfer| transfer._id == transfer_id)
+        .ok_or_else(|| anyhow!("no transfer {transfer_id} sent to this device"))?;
+
+    let saved = download_all(&client, &transfer.files_id, out).await?;
+    ctx.print(
+        &json!({ "transfer_id": transfer_id, "files": saved }),
+        || {
+            saved
+                .iter()
+                .map(|path| format!("saved {}", path.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        },
+    )
+}
+
+/// tails the pool events, the transfers sent to this device are downloaded into `out`
+pub async fn watch(ctx: &Context, out: &Path) -> anyhow::Result<()> {
+    let client = ctx.client()?;
+    let device_id = &ctx.config.device_id;
+
+    let mut last_event_id = None;
+    loop {
+        let mut events = match client.events(device_id, last_event_id).await {
+            Ok(events) => events,
+            Err(err) if err.kind().is_some() => return Err(err.into()),
+            Err(err) => {
+                eprintln!("can't reach the server ({err}), retrying...");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    eprintln!("lost the connection ({err}), reconnecting...");
+                    break;
+                }
+            };
+            last_event_id = event.id.or(last_event_id);
+
+            let files_id = match &event.data {
+                SSEData::Transfer(transfer) if &transfer.to == device_id => {
+                    transfer.files_id.clone()
+                }
+                SSEData::FileAdded(added) => added.files_id.clone(),
+                _ => vec![],
+            };
+            let saved = download_all(&client, &files_id, out).await?;
+
+            ctx.print(&json!({ "event": event.data, "saved": saved }), || {
+                let mut text = format!(
+                    "{}: {}",
+                    event.data.event_name(),
+                    serde_json::to_string(&event.data).unwrap_or_default()
+                );
+                for path in &saved {
+                    text.push_str(&format!("\n  saved {}", path.display()));
+                }
+                text
+            })?;
+
+            if let SSEData::Logout = event.data {
+                return Ok(());
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+pub async fn rm(ctx: &Context, ids: &[String], files: bool) -> anyhow::Result<()> {
+    let client = ctx.client()?;
+    for id in ids {
+        if files {
+            client.delete_file(id).await?;
+        } else {
+            client.delete_transfer(&ctx.config.device_id, id).await?;
+        }
+    }
+    ctx.print(&json!({ "deleted": ids }), || {
+        format!("deleted {}", ids.join(", "))
+    })
+}
+
+// helpers
+
+/// `device` is either the id of a device of the pool or its name
+fn resolve_device(pool: &DevicesPool, device: &str) -> anyhow::Result<String> {
+    if pool.devices_id.iter().any(|id| id == device) {
+        return Ok(device.to_string());
+    }
+
+    let matches = pool
+        .devices_id_to_name
+        .iter()
+        .filter(|(_, name)| name.as_str() == device)
+        .map(|(id, _)| id.clone())
+        .collect::<Vec<_>>();
+    match matches.as_slice() {
+        [id] => Ok(id.clone()),
+        [] => bail!("no device \"{device}\" in the pool"),
+        _ => bail!("several devices are named \"{device}\", use its id (see `ilix pool show`)"),
+    }
+}
+
+/// streams the files into `out`, returns where they were saved
+async fn download_all(
+    client: &Client,
+    files_id: &[String],
+    out: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if !files_id.is_empty() {
+        fs::create_dir_all(out).await?;
+    }
+
+    let mut saved = Vec::with_capacity(files_id.len());
+    for file_id in files_id {
+        let download = client.download_file(file_id).await?;
+        let filename = download.filename.clone().unwrap_or_else(|| file_id.clone());
+        let path = free_path(out, &filename).await;
+
+        let mut file = fs::File::create(&path)
+            .await
+            .with_context(|| format!("can't create {}", path.display()))?;
+        let mut chunks = download.into_stream();
+        while let Some(chunk) = chunks.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        saved.push(path);
+    }
+    Ok(saved)
+}
+
+/// `out/filename`, with a " (n)" suffix if it already exists. The filename comes from the sender so only its last
+/// component is kept
+async fn free_path(out: &Path, filename: &str) -> PathBuf {
+    let filename = Path::new(filename)
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("file"));
+    let stem = filename
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let ext = filename
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()));
+
+    let mut path = out.join(&filename);
+    let mut n = 1;
+    while fs::try_exists(&path).await.unwrap_or(false) {
+        path = out.join(format!(
+            "{stem} ({n}){}",
+            ext.as_deref().unwrap_or_default()
+        ));
+        n += 1;
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use ilix_sdk::DevicesPool;
+
+    use super::{free_path, resolve_device};
+
+    #[test]
+    fn resolve_device_test() {
+        let pool = DevicesPool {
+            pool_name: "home".to_string(),
+            devices_id: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            devices_id_to_name: HashMap::from([
+                ("a".to_string(), "laptop".to_string()),
+                ("b".to_string(), "phone".to_string()),
+                ("c".to_string(), "phone".to_string()),
+            ]),
+            devices_last_seen: HashMap::new(),
+            devices_online: vec![],
+            hashed_key_phrase: String::new(),
+            schema_version: DevicesPool::SCHEMA_VERSION,
+            created_at: 0,
+            version: 0,
+        };
+
+        assert_eq!(resolve_device(&pool, "a").unwrap(), "a");
+        assert_eq!(resolve_device(&pool, "laptop").unwrap(), "a");
+        assert!(resolve_device(&pool, "phone").is_err()); // ambiguous
+        assert!(resolve_device(&pool, "tv").is_err());
+    }
+
+    #[tokio::test]
+    async fn free_path_test() {
+        let out = std::env::temp_dir().join(format!("ilix-cli-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&out).unwrap();
+
+        assert_eq!(
+            free_path(&out, "../../etc/passwd").await,
+            out.join("passwd")
+        );
+        assert_eq!(free_path(&out, "photo.jpg").await, out.join("photo.jpg"));
+        std::fs::write(out.join("photo.jpg"), b"").unwrap();
+        assert_eq!(
+            free_path(&out, "photo.jpg").await,
+            out.join("photo (1).jpg")
+        );
+        assert_eq!(free_path(&out, "..").await, out.join("file"));
+        assert_eq!(free_path(Path::new(&out), "notes").await, out.join("notes"));
+
+        std::fs::remove_dir_all(out).unwrap();
+    }
+}
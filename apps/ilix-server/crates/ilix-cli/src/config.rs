@@ -0,0 +1,141 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_SERVER: &str = "https://ilix-api.fly.dev";
+
+/// What the cli remembers between runs, stored as toml in `<config dir>/ilix/config.toml`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Config {
+    #[serde(default = "default_server")]
+    pub server: String,
+    /// the id of this device in its pool, generated on the first run
+    #[serde(default = "new_device_id")]
+    pub device_id: String,
+    #[serde(default = "default_device_name")]
+    pub device_name: String,
+    /// the pool this device is in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_phrase: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: default_server(),
+            device_id: new_device_id(),
+            device_name: default_device_name(),
+            key_phrase: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn default_path() -> anyhow::Result<PathBuf> {
+        let dir = dirs::config_dir()
+            .ok_or_else(|| anyhow!("no config directory on this os, use --config"))?;
+        Ok(dir.join("ilix").join("config.toml"))
+    }
+
+    /// a missing file is the default config (with a new device id)
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(raw) => toml::from_str(&raw)
+                .with_context(|| format!("invalid config file {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// the key phrase is the pool secret (and its encryption key), so only the user can read the file
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            let mut builder = fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder
+                .create(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+
+        let write = || {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(path)?;
+            // the mode only applies to new files, an older one may be readable by others
+            #[cfg(unix)]
+            file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+            file.write_all(toml::to_string(self)?.as_bytes())?;
+            anyhow::Ok(())
+        };
+        write().with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn key_phrase(&self) -> anyhow::Result<&str> {
+        self.key_phrase.as_deref().ok_or_else(|| {
+            anyhow!("this device isn't in a pool, run `ilix pool create` or `ilix pool join` first")
+        })
+    }
+}
+
+fn default_server() -> String {
+    DEFAULT_SERVER.to_string()
+}
+
+fn new_device_id() -> String {
+    format!("cli-{}", uuid::Uuid::new_v4())
+}
+
+fn default_device_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .map(|user| format!("{user}'s terminal"))
+        .unwrap_or("terminal".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn config_test() {
+        let dir = std::env::temp_dir().join(format!("ilix-cli-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("config.toml");
+
+        // first run
+        let mut config = Config::load(&path).unwrap();
+        assert!(config.device_id.starts_with("cli-"));
+        assert!(config.key_phrase().is_err());
+
+        config.key_phrase = Some("sasaki-miyano".to_string());
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = |path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!((mode(&dir), mode(&path)), (0o700, 0o600));
+            // written before the file was private
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            config.save(&path).unwrap();
+            assert_eq!(mode(&path), 0o600);
+        }
+
+        // the missing fields get their default
+        std::fs::write(&path, "device_id = \"laptop\"\n").unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.device_id, "laptop");
+        assert_eq!(config.server, "https://ilix-api.fly.dev");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
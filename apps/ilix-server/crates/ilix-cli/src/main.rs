@@ -0,0 +1,133 @@
+//! `ilix`: the command line client of an ilix pool
+
+mod commands;
+mod config;
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use config::Config;
+
+#[derive(Parser)]
+#[command(
+    name = "ilix",
+    version,
+    about = "Send files to the devices of your ilix pool"
+)]
+struct Cli {
+    /// print the results as json, for scripts
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// the config file, where the key phrase and this device are stored
+    #[arg(long, global = true, env = "ILIX_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// the server url, instead of the one in the config
+    #[arg(long, global = true, env = "ILIX_SERVER")]
+    server: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// manage the pool of this device
+    #[command(subcommand)]
+    Pool(PoolCommand),
+    /// send files to a device of the pool (by id or name)
+    Send {
+        device: String,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// list the transfers sent to this device
+    Inbox,
+    /// download the files of a transfer
+    Get {
+        transfer: String,
+        /// where to save the files
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// print the pool events and download the incoming transfers as they arrive
+    Watch {
+        /// where to save the files
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// delete transfers sent to this device (or only some of their files with --files)
+    Rm {
+        #[arg(required = true)]
+        ids: Vec<String>,
+        /// the ids are files ids, not transfers ids
+        #[arg(long)]
+        files: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PoolCommand {
+    /// create a pool with this device in it
+    Create {
+        name: String,
+        /// the name of this device in the pool
+        #[arg(long)]
+        device_name: Option<String>,
+    },
+    /// join a pool with its key phrase
+    Join {
+        key_phrase: String,
+        /// the name of this device in the pool
+        #[arg(long)]
+        device_name: Option<String>,
+    },
+    /// leave the pool, the transfers sent to this device are deleted
+    Leave,
+    /// the pool devices and key phrase
+    Show,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        eprintln!("error: {err:#}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let config_path = match cli.config {
+        Some(path) => path,
+        None => Config::default_path()?,
+    };
+    let mut config = Config::load(&config_path)?;
+    if let Some(server) = cli.server {
+        config.server = server;
+    }
+
+    let mut ctx = commands::Context {
+        config,
+        config_path,
+        json: cli.json,
+    };
+    match cli.command {
+        Command::Pool(PoolCommand::Create { name, device_name }) => {
+            commands::pool_create(&mut ctx, &name, device_name).await
+        }
+        Command::Pool(PoolCommand::Join {
+            key_phrase,
+            device_name,
+        }) => commands::pool_join(&mut ctx, key_phrase, device_name).await,
+        Command::Pool(PoolCommand::Leave) => commands::pool_leave(&mut ctx).await,
+        Command::Pool(PoolCommand::Show) => commands::pool_show(&ctx).await,
+        Command::Send { device, files } => commands::send(&ctx, &device, files).await,
+        Command::Inbox => commands::inbox(&ctx).await,
+        Command::Get { transfer, out } => commands::get(&ctx, &transfer, &out).await,
+        Command::Watch { out } => commands::watch(&ctx, &out).await,
+        Command::Rm { ids, files } => commands::rm(&ctx, &ids, files).await,
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{Read, Write};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::errors::{ServerError, ServerErrorContext, ServerErrors};
+
+/// how many bytes of the file are compressed to guess if the whole file is worth compressing
+const SAMPLE_SIZE: usize = 64 * 1024;
+/// under this size, the compression overhead is bigger than what we can win
+const MIN_COMPRESS_SIZE: usize = 256;
+/// the sample must shrink to at least 90% of its size for the file to be compressed
+const MAX_COMPRESSION_RATIO: f64 = 0.9;
+const ZSTD_LEVEL: i32 = 3;
+
+/// mime types (or prefixes) of formats that are already compressed
+const COMPRESSED_MIME_TYPES: [&str; 11] = [
+    "video/",
+    "audio/",
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/heif",
+    "application/zip",
+    "application/gzip",
+    "application/vnd.android.package-archive",
+];
+/// extensions of formats that are already compressed, compressing them again is a waste of cpu
+const COMPRESSED_EXTENSIONS: [&str; 24] = [
+    "jpg", "jpeg", "png", "gif", "webp", "avif", "heic", "mp3", "mp4", "m4a", "mkv", "mov", "webm",
+    "ogg", "opus", "flac", "zip", "gz", "tgz", "xz", "zst", "7z", "rar", "apk",
+];
+
+/// Codec used to compress a file **before** its encryption.
+///
+/// It is stored in the file metadata, a file without codec was uploaded before compression existed, thus it's `None`
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    None,
+    Zstd,
+    Deflate,
+}
+
+impl Codec {
+    /// pick the best codec for this file, either by its content type or by how well a sample of it compresses
+    pub fn pick(filename: &str, mime_type: &str, datas: &[u8]) -> Self {
+        if datas.len() < MIN_COMPRESS_SIZE || is_compressed_format(filename, mime_type) {
+            return Self::None;
+        }
+
+        let sample = &datas[..datas.len().min(SAMPLE_SIZE)];
+        let candidates = [Self::Zstd, Self::Deflate]
+            .into_iter()
+            .filter_map(|codec| Some((codec, codec.compress(sample).ok()?.len())));
+
+        match candidates.min_by_key(|(_, len)| *len) {
+            Some((codec, len)) if (len as f64) <= sample.len() as f64 * MAX_COMPRESSION_RATIO => {
+                codec
+            }
+            _ => Self::None,
+        }
+    }
+
+    pub fn compress(&self, datas: &[u8]) -> Result<Vec<u8>, ServerError> {
+        match self {
+            Self::None => Ok(datas.to_vec()),
+            Self::Zstd => {
+                zstd::encode_all(datas, ZSTD_LEVEL).server_err(ServerErrors::CompressionError)
+            }
+            Self::Deflate => {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                encoder
+                    .write_all(datas)
+                    .server_err(ServerErrors::CompressionError)?;
+                encoder.finish().server_err(ServerErrors::CompressionError)
+            }
+        }
+    }
+
+    pub fn decompress(&self, datas: &[u8]) -> Result<Vec<u8>, ServerError> {
+        match self {
+            Self::None => Ok(datas.to_vec()),
+            Self::Zstd => zstd::decode_all(datas).server_err(ServerErrors::DecompressionError),
+            Self::Deflate => {
+                let mut decompressed = vec![];
+                DeflateDecoder::new(datas)
+                    .read_to_end(&mut decompressed)
+                    .server_err(ServerErrors::DecompressionError)?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+fn is_compressed_format(filename: &str, mime_type: &str) -> bool {
+    if COMPRESSED_MIME_TYPES
+        .iter()
+        .any(|compressed| mime_type.starts_with(compressed))
+    {
+        return true;
+    }
+
+    match filename.rsplit_once('.') {
+        Some((_, ext)) => COMPRESSED_EXTENSIONS.contains(&ext.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rand::RngCore;
+
+    use super::Codec;
+
+    #[test]
+    fn codec_roundtrip_test() {
+        let file_data = fs::read("../../Assets/english_dictionary_words.txt").unwrap();
+
+        for codec in [Codec::None, Codec::Zstd, Codec::Deflate] {
+            let compressed = codec.compress(&file_data).unwrap();
+            assert_eq!(codec.decompress(&compressed).unwrap(), file_data);
+            if codec != Codec::None {
+                assert!(compressed.len() < file_data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn codec_pick_test() {
+        let text = fs::read("../../Assets/english_dictionary_words.txt").unwrap();
+        assert_ne!(Codec::pick("words.txt", "text/plain", &text), Codec::None);
+        assert_eq!(Codec::pick("words.zip", "text/plain", &text), Codec::None); // trust the extension
+        assert_eq!(Codec::pick("words", "video/mp4", &text), Codec::None); // and the content type
+        assert_eq!(
+            Codec::pick("tiny.txt", "text/plain", b"sasaki"),
+            Codec::None
+        );
+
+        let mut noise = vec![0; 128 * 1024];
+        rand::thread_rng().fill_bytes(&mut noise);
+        assert_eq!(
+            Codec::pick("noise.bin", "application/octet-stream", &noise),
+            Codec::None
+        );
+    }
+}
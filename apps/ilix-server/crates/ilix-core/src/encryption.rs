@@ -2,7 +2,10 @@ use anyhow::Result;
 use chacha20poly1305::{aead::Aead, AeadCore, Key, KeyInit, XChaCha20Poly1305};
 use rand::rngs::OsRng;
 
-use super::{errors::ServerErrors, hash};
+use super::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    hash,
+};
 
 fn hash_key(key: &str) -> String {
     let hashed_key = hash(key);
@@ -10,7 +13,7 @@ fn hash_key(key: &str) -> String {
 }
 
 /// return the encrypted datas (nonce + encrypted datas)
-pub fn encrypt_datas(key: &str, datas: &[u8]) -> Result<Vec<u8>, ServerErrors> {
+pub fn encrypt_datas(key: &str, datas: &[u8]) -> Result<Vec<u8>, ServerError> {
     let valid_key = hash_key(key);
     let key = Key::from_slice(valid_key.as_bytes());
 
@@ -19,7 +22,7 @@ pub fn encrypt_datas(key: &str, datas: &[u8]) -> Result<Vec<u8>, ServerErrors> {
 
     let encrypted_datas = cipher
         .encrypt(&nonce, datas.as_ref())
-        .map_err(|_| ServerErrors::EncryptionError)?;
+        .server_err(ServerErrors::EncryptionError)?;
 
     let mut encrypted_datas_with_nonce = nonce.to_vec();
     encrypted_datas_with_nonce.extend(encrypted_datas);
@@ -28,7 +31,7 @@ pub fn encrypt_datas(key: &str, datas: &[u8]) -> Result<Vec<u8>, ServerErrors> {
 }
 
 /// return the decrypted datas (the encrypted datas must contains the nonce)
-pub fn decrypt_datas(key: &str, enc_datas: &[u8]) -> Result<Vec<u8>, ServerErrors> {
+pub fn decrypt_datas(key: &str, enc_datas: &[u8]) -> Result<Vec<u8>, ServerError> {
     let valid_key = hash_key(key);
     let key = Key::from_slice(valid_key.as_bytes());
     let cipher = XChaCha20Poly1305::new(key);
@@ -36,7 +39,7 @@ pub fn decrypt_datas(key: &str, enc_datas: &[u8]) -> Result<Vec<u8>, ServerError
     let (nonce_bytes, encrypted_data) = enc_datas.split_at(24);
     let decrypted_data = cipher
         .decrypt(nonce_bytes.into(), encrypted_data.as_ref())
-        .map_err(|_| ServerErrors::DecryptionError)?;
+        .server_err(ServerErrors::DecryptionError)?;
 
     Ok(decrypted_data)
 }
@@ -45,7 +48,7 @@ pub fn decrypt_datas(key: &str, enc_datas: &[u8]) -> Result<Vec<u8>, ServerError
 mod tests {
     use std::fs;
 
-    use crate::utils::encryption::decrypt_datas;
+    use crate::encryption::decrypt_datas;
 
     use super::encrypt_datas;
 
@@ -65,7 +68,7 @@ mod tests {
 
     #[test]
     fn encryption_big_test() {
-        let file_data = fs::read("./Assets/english_dictionary_words.txt").unwrap();
+        let file_data = fs::read("../../Assets/english_dictionary_words.txt").unwrap();
 
         let encrypted_datas = encrypt_datas(SECRET_KEY, &file_data).unwrap();
         assert_ne!(encrypted_datas, file_data);
@@ -0,0 +1,239 @@
+use std::{error::Error, fmt::Display, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use http::StatusCode;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ServerErrors {
+    MongoError,
+    DictionnaryNotFound,
+    InvalidObjectId,
+    PoolNotFound,
+    TransferNotFound,
+    AlreadyInPool,
+    NotInPool,
+    NotInTransfer,
+    EnvVarNotFound,
+    ParseError,
+    InvalidKeyPhrase,
+    EncryptionError,
+    DecryptionError,
+    CompressionError,
+    DecompressionError,
+    ThumbnailError,
+    FileNotFound,
+    ThumbnailNotFound,
+    HashError,
+    SseFailedToSend,
+    BadArgs,
+    InvalidFile,
+    MissingAuthorization,
+    FileSendFailed,
+    ShuttingDown,
+    VersionConflict,
+}
+
+impl ServerErrors {
+    /// the inverse of `to_string`, e.g: to read the `reason` of an error response
+    pub fn parse(input: &str) -> Result<Self> {
+        match input {
+            "MongoError" => Ok(Self::MongoError),
+            "DictionnaryNotFound" => Ok(Self::DictionnaryNotFound),
+            "InvalidObjectId" => Ok(Self::InvalidObjectId),
+            "PoolNotFound" => Ok(Self::PoolNotFound),
+            "TransferNotFound" => Ok(Self::TransferNotFound),
+            "AlreadyInPool" => Ok(Self::AlreadyInPool),
+            "NotInPool" => Ok(Self::NotInPool),
+            "NotInTransfer" => Ok(Self::NotInTransfer),
+            "EnvVarNotFound" => Ok(Self::EnvVarNotFound),
+            "ParseError" => Ok(Self::ParseError),
+            "InvalidKeyPhrase" => Ok(Self::InvalidKeyPhrase),
+            "EncryptionError" => Ok(Self::EncryptionError),
+            "DecryptionError" => Ok(Self::DecryptionError),
+            "CompressionError" => Ok(Self::CompressionError),
+            "DecompressionError" => Ok(Self::DecompressionError),
+            "ThumbnailError" => Ok(Self::ThumbnailError),
+            "FileNotFound" => Ok(Self::FileNotFound),
+            "ThumbnailNotFound" => Ok(Self::ThumbnailNotFound),
+            "HashError" => Ok(Self::HashError),
+            "SseFailedToSend" => Ok(Self::SseFailedToSend),
+            "BadArgs" => Ok(Self::BadArgs),
+            "InvalidFile" => Ok(Self::InvalidFile),
+            "MissingAuthorization" => Ok(Self::MissingAuthorization),
+            "FileSendFailed" => Ok(Self::FileSendFailed),
+            "ShuttingDown" => Ok(Self::ShuttingDown),
+            "VersionConflict" => Ok(Self::VersionConflict),
+            _ => Err(anyhow!("")),
+        }
+    }
+
+    /// the http status of this error, it's the same whatever the endpoint
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidObjectId | Self::ParseError | Self::BadArgs | Self::InvalidFile => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::InvalidKeyPhrase | Self::MissingAuthorization => StatusCode::UNAUTHORIZED,
+            Self::PoolNotFound
+            | Self::TransferNotFound
+            | Self::NotInPool
+            | Self::NotInTransfer
+            | Self::FileNotFound
+            | Self::ThumbnailNotFound => StatusCode::NOT_FOUND,
+            Self::AlreadyInPool | Self::VersionConflict => StatusCode::CONFLICT,
+            Self::MongoError
+            | Self::DictionnaryNotFound
+            | Self::EnvVarNotFound
+            | Self::EncryptionError
+            | Self::DecryptionError
+            | Self::CompressionError
+            | Self::DecompressionError
+            | Self::ThumbnailError
+            | Self::HashError
+            | Self::SseFailedToSend
+            | Self::FileSendFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// human readable explanation of this error, the variant name is the stable code to match on
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::MongoError => "the database request failed",
+            Self::DictionnaryNotFound => "the key phrase dictionary couldn't be loaded",
+            Self::InvalidObjectId => "an id is not a valid object id",
+            Self::PoolNotFound => "no pool matches this key phrase",
+            Self::TransferNotFound => "this transfer doesn't exist",
+            Self::AlreadyInPool => "this device is already in the pool",
+            Self::NotInPool => "this device is not in the pool",
+            Self::NotInTransfer => "this file is not in the transfer",
+            Self::EnvVarNotFound => "the server is misconfigured",
+            Self::ParseError => "a value couldn't be parsed",
+            Self::InvalidKeyPhrase => "the key phrase is invalid",
+            Self::EncryptionError => "the file couldn't be encrypted",
+            Self::DecryptionError => "the file couldn't be decrypted",
+            Self::CompressionError => "the file couldn't be compressed",
+            Self::DecompressionError => "the file couldn't be decompressed",
+            Self::ThumbnailError => "the thumbnail couldn't be generated",
+            Self::FileNotFound => "this file doesn't exist",
+            Self::ThumbnailNotFound => "this file has no thumbnail",
+            Self::HashError => "the key phrase couldn't be hashed",
+            Self::SseFailedToSend => "the event couldn't be sent",
+            Self::BadArgs => "the request arguments are missing or invalid",
+            Self::InvalidFile => "the uploaded files couldn't be parsed",
+            Self::MissingAuthorization => "the 'Authorization' header is missing",
+            Self::FileSendFailed => "the file couldn't be sent",
+            Self::ShuttingDown => "the server is shutting down, retry later",
+            Self::VersionConflict => "the pool changed since this version, reload it and retry",
+        }
+    }
+}
+
+impl Display for ServerErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// A [`ServerErrors`] with the error that caused it (e.g: the mongodb driver error).
+///
+/// The cause is only logged, clients only see the `kind`
+#[derive(Debug, Clone)]
+pub struct ServerError {
+    pub kind: ServerErrors,
+    source: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl ServerError {
+    pub fn new(kind: ServerErrors, source: impl Error + Send + Sync + 'static) -> Self {
+        Self {
+            kind,
+            source: Some(Arc::new(source)),
+        }
+    }
+
+    /// the whole cause chain, e.g: "Kind: cause: cause of the cause"
+    pub fn chain(&self) -> String {
+        let mut chain = self.kind.to_string();
+        let mut source = Error::source(self);
+        while let Some(cause) = source {
+            chain += &format!(": {cause}");
+            source = cause.source();
+        }
+        chain
+    }
+}
+
+impl From<ServerErrors> for ServerError {
+    fn from(kind: ServerErrors) -> Self {
+        Self { kind, source: None }
+    }
+}
+
+impl PartialEq<ServerErrors> for ServerError {
+    fn eq(&self, other: &ServerErrors) -> bool {
+        self.kind == *other
+    }
+}
+
+/// only the kind, so that it can't leak to clients by mistake, see [`ServerError::chain`]
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn Error + 'static))
+    }
+}
+
+/// `.map_err(|_| ServerErrors::MongoError)` without throwing away the cause
+pub trait ServerErrorContext<T> {
+    fn server_err(self, kind: ServerErrors) -> Result<T, ServerError>;
+}
+
+impl<T, E> ServerErrorContext<T> for Result<T, E>
+where
+    E: Error + Send + Sync + 'static,
+{
+    fn server_err(self, kind: ServerErrors) -> Result<T, ServerError> {
+        self.map_err(|err| ServerError::new(kind, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, num::ParseIntError};
+
+    use super::{ServerErrorContext, ServerErrors};
+
+    #[test]
+    fn server_errors_parse_test() {
+        for err in [
+            ServerErrors::PoolNotFound,
+            ServerErrors::NotInPool,
+            ServerErrors::BadArgs,
+            ServerErrors::FileSendFailed,
+            ServerErrors::ShuttingDown,
+            ServerErrors::VersionConflict,
+        ] {
+            assert_eq!(ServerErrors::parse(&err.to_string()).unwrap(), err);
+            assert!(!err.message().is_empty());
+        }
+        assert!(ServerErrors::parse("Sasaki").is_err());
+    }
+
+    #[test]
+    fn server_error_chain_test() {
+        let parsed: Result<u8, ParseIntError> = "sasaki".parse();
+        let err = parsed.server_err(ServerErrors::ParseError).unwrap_err();
+        assert_eq!(err, ServerErrors::ParseError);
+        assert_eq!(err.to_string(), "ParseError"); // nothing leaks
+        assert_eq!(err.chain(), "ParseError: invalid digit found in string");
+        assert!(Error::source(&err).is_some());
+    }
+}
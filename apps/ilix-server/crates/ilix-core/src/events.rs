@@ -0,0 +1,194 @@
+//! The events sent to the devices of a pool, over sse or websocket
+
+use std::collections::HashMap;
+
+use crate::models::{DevicesPool, FilePoolTransferExt};
+
+/// Version of the events json schema, it's sent to the clients when they connect.
+///
+/// Within a version, fields and events can only be added: a field is never removed, renamed nor changes its type.
+/// Otherwise this version is bumped
+pub const SSE_SCHEMA_VERSION: u32 = 1;
+
+/// The events sent to the clients, the event name is [`SSEData::event_name`] and its data is the variant serialized as
+/// `{"<Variant>": <payload>}` (`"Logout"` for logout).
+///
+/// `Pool` and `Transfer` carry the full state, the other events are incremental updates. The clients that connect
+/// without their `schema_version` get `Pool` instead of `DeviceJoined`, `DeviceLeft` and `PoolRenamed`
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub enum SSEData {
+    Pool(DevicesPool),
+    Transfer(FilePoolTransferExt),
+    TransferAck(TransferAck),
+    TransferDeleted(TransferDeleted),
+    FileAdded(FileAdded),
+    FileRemoved(FileRemoved),
+    DeviceJoined(DeviceJoined),
+    DeviceLeft(DeviceLeft),
+    PoolRenamed(PoolRenamed),
+    Presence(Presence),
+    Logout,
+}
+
+impl SSEData {
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            SSEData::Pool(_) => "pool",
+            SSEData::Transfer(_) => "transfer",
+            SSEData::TransferAck(_) => "transfer_ack",
+            SSEData::TransferDeleted(_) => "transfer_deleted",
+            SSEData::FileAdded(_) => "file_added",
+            SSEData::FileRemoved(_) => "file_removed",
+            SSEData::DeviceJoined(_) => "device_joined",
+            SSEData::DeviceLeft(_) => "device_left",
+            SSEData::PoolRenamed(_) => "pool_renamed",
+            SSEData::Presence(_) => "presence",
+            SSEData::Logout => "logout",
+        }
+    }
+}
+
+/// sent to the recipient of a transfer when it was deleted, with all its files
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct TransferDeleted {
+    pub transfer_id: String,
+}
+
+/// sent to the recipient of a transfer when files were attached to it
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct FileAdded {
+    pub transfer_id: String,
+    pub files_id: Vec<String>,
+    /// file_id -> thumbnail_id, only for the files that have one
+    #[serde(default)]
+    pub thumbnails_id: HashMap<String, String>,
+}
+
+/// sent to the recipient of a transfer when one of its files was deleted
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct FileRemoved {
+    pub transfer_id: String,
+    pub file_id: String,
+}
+
+/// sent to the devices of a pool when a device joined it
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct DeviceJoined {
+    pub device_id: String,
+    pub device_name: String,
+}
+
+/// sent to the devices left in a pool when a device left it
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct DeviceLeft {
+    pub device_id: String,
+}
+
+/// sent to the devices of a pool when it was renamed
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct PoolRenamed {
+    pub pool_name: String,
+}
+
+/// sent to the sender of a transfer when its recipient acknowledged it
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct TransferAck {
+    pub transfer_id: String,
+    /// the device that acknowledged the transfer
+    pub device_id: String,
+}
+
+/// sent to the connected devices of a pool when one of them connects or disconnects
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, utoipa::ToSchema)]
+pub struct Presence {
+    pub device_id: String,
+    pub online: bool,
+    /// unix timestamp in milliseconds
+    pub last_seen: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        DeviceJoined, DeviceLeft, FileAdded, FileRemoved, PoolRenamed, Presence, SSEData,
+        TransferAck, TransferDeleted,
+    };
+
+    /// the json schema of the events is stable, see `SSE_SCHEMA_VERSION`
+    #[test]
+    fn sse_schema_test() {
+        let events = [
+            (
+                SSEData::TransferAck(TransferAck {
+                    transfer_id: "t1".to_string(),
+                    device_id: "miyano".to_string(),
+                }),
+                "transfer_ack",
+                r#"{"TransferAck":{"transfer_id":"t1","device_id":"miyano"}}"#,
+            ),
+            (
+                SSEData::TransferDeleted(TransferDeleted {
+                    transfer_id: "t1".to_string(),
+                }),
+                "transfer_deleted",
+                r#"{"TransferDeleted":{"transfer_id":"t1"}}"#,
+            ),
+            (
+                SSEData::FileAdded(FileAdded {
+                    transfer_id: "t1".to_string(),
+                    files_id: vec!["f1".to_string()],
+                    thumbnails_id: HashMap::from([("f1".to_string(), "th1".to_string())]),
+                }),
+                "file_added",
+                r#"{"FileAdded":{"transfer_id":"t1","files_id":["f1"],"thumbnails_id":{"f1":"th1"}}}"#,
+            ),
+            (
+                SSEData::FileRemoved(FileRemoved {
+                    transfer_id: "t1".to_string(),
+                    file_id: "f1".to_string(),
+                }),
+                "file_removed",
+                r#"{"FileRemoved":{"transfer_id":"t1","file_id":"f1"}}"#,
+            ),
+            (
+                SSEData::DeviceJoined(DeviceJoined {
+                    device_id: "sasaki".to_string(),
+                    device_name: "Sasaki's phone".to_string(),
+                }),
+                "device_joined",
+                r#"{"DeviceJoined":{"device_id":"sasaki","device_name":"Sasaki's phone"}}"#,
+            ),
+            (
+                SSEData::DeviceLeft(DeviceLeft {
+                    device_id: "miyano".to_string(),
+                }),
+                "device_left",
+                r#"{"DeviceLeft":{"device_id":"miyano"}}"#,
+            ),
+            (
+                SSEData::PoolRenamed(PoolRenamed {
+                    pool_name: "bl".to_string(),
+                }),
+                "pool_renamed",
+                r#"{"PoolRenamed":{"pool_name":"bl"}}"#,
+            ),
+            (
+                SSEData::Presence(Presence {
+                    device_id: "sasaki".to_string(),
+                    online: true,
+                    last_seen: 1700000000000,
+                }),
+                "presence",
+                r#"{"Presence":{"device_id":"sasaki","online":true,"last_seen":1700000000000}}"#,
+            ),
+            (SSEData::Logout, "logout", r#""Logout""#),
+        ];
+
+        for (data, event_name, json) in events {
+            assert_eq!(data.event_name(), event_name);
+            assert_eq!(serde_json::to_string(&data).unwrap(), json);
+        }
+    }
+}
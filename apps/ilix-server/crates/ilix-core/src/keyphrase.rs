@@ -0,0 +1,190 @@
+use anyhow::Result;
+use std::{fmt, fs};
+
+use rand::Rng;
+
+use super::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    hash,
+};
+
+pub const KEY_PHRASE_LEN: usize = 20;
+/// the words of the key phrases, relative to the server working directory
+pub const DICTIONARY_PATH: &str = "./Assets/english_dictionary_words.txt";
+/// under this, the hash is too cheap to slow down a brute force
+pub const MIN_HASH_ROUND: usize = 5;
+
+/// The secret parameters of [`KeyPhrase::hash`], the server reads them once from its config.
+///
+/// The stored pools are found by their hash: once pools exist, changing them makes all of them unreachable
+#[derive(Clone)]
+pub struct HashParams {
+    rounds: usize,
+    salt: String,
+}
+
+impl HashParams {
+    /// `HashError` if there are less than [`MIN_HASH_ROUND`] rounds
+    pub fn new(rounds: usize, salt: impl Into<String>) -> Result<Self, ServerErrors> {
+        if rounds < MIN_HASH_ROUND {
+            return Err(ServerErrors::HashError);
+        }
+        Ok(Self {
+            rounds,
+            salt: salt.into(),
+        })
+    }
+}
+
+/// the salt is a secret, it never ends up in the logs
+impl fmt::Debug for HashParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashParams")
+            .field("rounds", &self.rounds)
+            .field("salt", &"***")
+            .finish()
+    }
+}
+
+/// check if the keyphrase is valid, **it does not** check if this keyphrase is linked to a pool
+fn is_key_phrase(str: &str) -> bool {
+    str.split('-').count() == KEY_PHRASE_LEN
+}
+
+/// Utility struct to manage `key phrase` in this application, which are the common and unique identifier and password of a pool
+///
+/// The server extracts it from the "Autorization" header of the requests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyPhrase(pub String);
+
+impl TryFrom<&str> for KeyPhrase {
+    type Error = ServerErrors;
+
+    fn try_from(key_phrase: &str) -> Result<Self, ServerErrors> {
+        if !is_key_phrase(key_phrase) {
+            return Err(ServerErrors::InvalidKeyPhrase);
+        }
+
+        Ok(KeyPhrase(key_phrase.to_string()))
+    }
+}
+
+impl TryFrom<String> for KeyPhrase {
+    type Error = ServerErrors;
+
+    fn try_from(key_phrase: String) -> Result<Self, ServerErrors> {
+        if !is_key_phrase(&key_phrase) {
+            return Err(ServerErrors::InvalidKeyPhrase);
+        }
+
+        Ok(KeyPhrase(key_phrase))
+    }
+}
+
+impl KeyPhrase {
+    /// generate an unique key phrase.
+    ///
+    /// words_number determine how many words will be in the final key phrase, the bigger it is, the more secure and unique the final hash is
+    ///
+    /// e.g:
+    ///     - for `words_number=5`; there are approx **1e26** unique possibilities
+    ///     - for `words_number=20`; there are approx **1e105** unique possibilities
+    ///     - more globally there are: **178187^words_number** unique possibilities
+    ///
+    /// the words are read from [`DICTIONARY_PATH`]
+    pub fn new(words_number: usize) -> Result<Self, ServerError> {
+        let dictionary =
+            fs::read_to_string(DICTIONARY_PATH).server_err(ServerErrors::DictionnaryNotFound)?;
+        Self::from_dictionary(&dictionary, words_number)
+    }
+
+    /// same as [`KeyPhrase::new`] with the words of `dictionary`, one per line
+    pub fn from_dictionary(dictionary: &str, words_number: usize) -> Result<Self, ServerError> {
+        let words = dictionary.lines().collect::<Vec<_>>();
+        if words.is_empty() {
+            return Err(ServerErrors::DictionnaryNotFound.into());
+        }
+
+        let mut rng = rand::thread_rng();
+
+        let key_phrase = (0..words_number)
+            .map(|_| {
+                let idx = rng.gen_range(0..words.len());
+                words[idx]
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self(key_phrase.join("-")))
+    }
+
+    /// **Hash** the plain text key phrase to be able to securely stores it in a db.
+    ///
+    /// Key phrase are both pool unique identifier and password, thus this can be hashed with "Argon2" for exemple
+    ///
+    /// This is a security drawback that is partially patched with a secret amount of hash round and a secret server key
+    /// acting as a unique salt (not as good as rng salt, but I can't do more)
+    pub fn hash(&self, params: &HashParams) -> String {
+        let mut result = format!("{}{}", params.salt, self.0);
+        for _ in 0..params.rounds {
+            result = hash(result);
+        }
+        result
+    }
+
+    /// It return if the user provided key phrase: `kp_to_verify`, match the right key phrase in db: `right_hashed_kp`
+    ///
+    /// However in pratice this is never called throughout the application because key phrase are also the unique identifier
+    /// of a pool. Thus this check is done while searching pool in db corresponding the the user key phrase.
+    pub fn verify(right_hashed_kp: String, kp_to_verify: &str, params: &HashParams) -> bool {
+        match KeyPhrase::try_from(kp_to_verify) {
+            Ok(kp) => kp.hash(params) == right_hashed_kp,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{errors::ServerErrors, keyphrase::is_key_phrase};
+
+    use super::{HashParams, KeyPhrase};
+
+    #[test]
+    fn key_phrase_test() {
+        let params = HashParams::new(10, "sasamiya").unwrap();
+
+        const N_WORDS: usize = 20;
+        let dictionary = fs::read_to_string("../../Assets/english_dictionary_words.txt").unwrap();
+        let kp = KeyPhrase::from_dictionary(&dictionary, N_WORDS).unwrap();
+
+        assert!(is_key_phrase(&kp.0));
+        let kp = KeyPhrase::try_from(kp.0).unwrap();
+
+        let hashed_kp = kp.hash(&params);
+        assert!(KeyPhrase::verify(hashed_kp.clone(), &kp.0, &params));
+        assert!(!KeyPhrase::verify(
+            hashed_kp.clone(),
+            "sasaki-miyano",
+            &params
+        ));
+
+        // the salt and the rounds are part of the hash
+        let other_salt = HashParams::new(10, "miyasasa").unwrap();
+        assert!(!KeyPhrase::verify(hashed_kp.clone(), &kp.0, &other_salt));
+        let other_rounds = HashParams::new(11, "sasamiya").unwrap();
+        assert!(!KeyPhrase::verify(hashed_kp, &kp.0, &other_rounds));
+        assert_eq!(
+            HashParams::new(4, "sasamiya").unwrap_err(),
+            ServerErrors::HashError
+        );
+        assert!(!format!("{params:?}").contains("sasamiya"));
+
+        assert_eq!(
+            KeyPhrase::try_from("sasaki-miyano").unwrap_err(),
+            ServerErrors::InvalidKeyPhrase
+        );
+        assert!(KeyPhrase::from_dictionary("", N_WORDS).is_err());
+    }
+}
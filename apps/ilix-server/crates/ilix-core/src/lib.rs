@@ -0,0 +1,61 @@
+//! The core of ilix: pools of devices sharing encrypted files.
+//!
+//! It has everything that isn't http: the models stored in db and sent to the clients, the pools key phrases, the
+//! files encryption and compression, the errors, the events of the pools and the storage traits. The server is built on
+//! it, and so can any client (the sdk, the cli...)
+
+pub mod compression;
+pub mod encryption;
+pub mod errors;
+pub mod events;
+pub mod keyphrase;
+pub mod models;
+pub mod storage;
+
+use hex_string::HexString;
+use sha2::Sha256;
+use sha3::{Digest, Sha3_256};
+
+/// return the 256 bytes **sha3 hash** of the `msg` param
+pub fn hash<T: Into<String>>(msg: T) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(msg.into().as_bytes());
+    let result_buf = hasher.finalize().to_vec();
+    HexString::from_bytes(&result_buf).as_string()
+}
+
+/// return the hex encoded **sha256 hash** of the `datas` param
+pub fn sha256(datas: &[u8]) -> String {
+    let result_buf = Sha256::digest(datas).to_vec();
+    HexString::from_bytes(&result_buf).as_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, sha256};
+
+    #[test]
+    fn hash_test() {
+        assert_eq!(
+            hash("sasaki and miyano"),
+            "ebbdf07f1121359452ec4ee91ade8f68e7bd750b018f7efa723e08486b09577e"
+        );
+
+        let mut result = "sasaki and miyano".to_string();
+        for _ in 0..10 {
+            result = hash(result);
+        }
+        assert_eq!(
+            result,
+            "aad2003ae46a3fcb907568a8f05d1486df27c1f8b79c7b77f5a5355d7d2e0a57"
+        )
+    }
+
+    #[test]
+    fn sha256_test() {
+        assert_eq!(
+            sha256(b"sasaki and miyano"),
+            "89b92d4d4cbe29558672ce9c97dbe7feaa787294fbe4b249d75fe2f4ee4d4ef4"
+        );
+    }
+}
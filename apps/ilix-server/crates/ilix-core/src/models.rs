@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use bson::{oid::ObjectId, spec::BinarySubtype, Binary, DateTime};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    compression::Codec,
+    encryption::{decrypt_datas, encrypt_datas},
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    keyphrase::KeyPhrase,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct DevicesPool {
+    pub pool_name: String,
+    pub devices_id: Vec<String>,
+    pub devices_id_to_name: HashMap<String, String>,
+    /// when each device was last connected to the event stream, unix timestamp in milliseconds
+    #[serde(default)]
+    pub devices_last_seen: HashMap<String, i64>,
+    /// devices currently connected to the event stream (of any server instance), it's never stored in the pool, only
+    /// filled in responses
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices_online: Vec<String>,
+    /// never sent to the clients
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    #[schema(read_only)]
+    pub hashed_key_phrase: String,
+    /// the format of this document, older documents are brought up to date by `ilix_server migrate`
+    #[serde(default)]
+    #[schema(read_only)]
+    pub schema_version: u32,
+    /// unix timestamp in milliseconds
+    #[serde(default)]
+    pub created_at: i64,
+    /// bumped by every change of the devices (or the name), so that clients can tell two states of the pool apart. A
+    /// rename can be made conditional on it, so that it doesn't overwrite a change the client hasn't seen
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl DevicesPool {
+    /// the `schema_version` of the pools written by this version
+    pub const SCHEMA_VERSION: u32 = 2;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FilePoolTransfer {
+    #[serde(skip_serializing)]
+    pub _id: ObjectId,
+    pub pool_hashed_key_phrase: String, // pointer to DevicesPool kp index
+    pub to: String,                     // device id
+    pub from: String,                   // device id
+    pub files_id: Vec<String>,          // _id pointer reference
+    #[serde(default)]
+    pub thumbnails_id: HashMap<String, String>, // file_id -> thumbnail file _id
+    /// see [`DevicesPool::schema_version`]
+    #[serde(default)]
+    pub schema_version: u32,
+    /// unix timestamp in milliseconds
+    #[serde(default)]
+    pub created_at: i64,
+}
+
+impl FilePoolTransfer {
+    /// the `schema_version` of the transfers written by this version
+    pub const SCHEMA_VERSION: u32 = 1;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct FilePoolTransferExt {
+    pub _id: String,
+    #[serde(default, skip_serializing)]
+    pub pool_hashed_key_phrase: String, // pointer to DevicesPool kp index
+    pub to: String,            // device id
+    pub from: String,          // device id
+    pub files_id: Vec<String>, // _id pointer reference
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub thumbnails_id: HashMap<String, String>, // file_id -> thumbnail file _id
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct FileInfo {
+    /// extended json: `{"$oid": "..."}`
+    #[schema(value_type = Object)]
+    pub _id: ObjectId,
+    pub filename: String,
+    pub chunkSize: usize,
+    pub length: usize,
+    #[serde(default, skip_serializing)]
+    pub md5: String,
+    /// extended json: `{"$date": {"$numberLong": "..."}}`
+    #[schema(value_type = Object)]
+    pub uploadDate: DateTime,
+    #[serde(default)]
+    pub metadata: FileMetadata,
+}
+
+/// stored in the gridfs `metadata` field of each file, files uploaded before it existed get the default values
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct FileMetadata {
+    #[serde(default)]
+    pub codec: Codec,
+    /// detected from the file magic bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// plaintext size, `length` is the size of the encrypted (and maybe compressed) datas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+    /// sha256 of the plaintext, for clients to check the file integrity. Stored in `sealed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// the encrypted preview of this file, only images have one (no video frames)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_id: Option<String>,
+    /// whether this file is itself the thumbnail of another file
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_thumbnail: bool,
+    /// stored in `sealed`
+    #[serde(flatten)]
+    pub client: ClientFileMetadata,
+    /// `sha256` and `client`, encrypted with the pool key phrase: whoever reads the db can't fingerprint the files nor
+    /// read what the clients wrote about them. Never sent to the clients, see [`FileMetadata::open`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<u8>>, read_only)]
+    pub sealed: Option<Binary>,
+}
+
+/// what [`FileMetadata::seal`] encrypts
+#[derive(Deserialize, Serialize)]
+struct SealedFileMetadata {
+    sha256: Option<String>,
+    #[serde(flatten)]
+    client: ClientFileMetadata,
+}
+
+impl FileMetadata {
+    /// encrypts `sha256` and `client` into `sealed`, before storing it
+    pub fn seal(&mut self, key_phrase: &KeyPhrase) -> Result<(), ServerError> {
+        let sealed = SealedFileMetadata {
+            sha256: self.sha256.take(),
+            client: std::mem::take(&mut self.client),
+        };
+        let plain = bson::to_vec(&sealed).server_err(ServerErrors::ParseError)?;
+        let encrypted = encrypt_datas(&key_phrase.0, &plain)?;
+        self.sealed = Some(Binary {
+            subtype: BinarySubtype::Generic,
+            bytes: encrypted,
+        });
+        Ok(())
+    }
+
+    /// decrypts `sealed` back into `sha256` and `client`, `DecryptionError` if it's not the key phrase of the file's
+    /// pool. Files stored before it existed have nothing sealed
+    pub fn open(&mut self, key_phrase: &KeyPhrase) -> Result<(), ServerError> {
+        let Some(sealed) = self.sealed.take() else {
+            return Ok(());
+        };
+        let plain = decrypt_datas(&key_phrase.0, &sealed.bytes)?;
+        let sealed: SealedFileMetadata =
+            bson::from_slice(&plain).server_err(ServerErrors::ParseError)?;
+        self.sha256 = sealed.sha256;
+        self.client = sealed.client;
+        Ok(())
+    }
+}
+
+/// optional metadata the client can send along with a file
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct ClientFileMetadata {
+    /// unix timestamp in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+}
+
+/// the body of `POST /pool/new`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct NewPoolPayload {
+    pub name: String,
+    pub device_id: String,
+    pub device_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use crate::{compression::Codec, errors::ServerErrors, keyphrase::KeyPhrase};
+
+    use super::{ClientFileMetadata, DevicesPool, FileInfo, FileMetadata};
+
+    #[test]
+    fn models_serde_test() {
+        let pool = DevicesPool {
+            pool_name: "bl".to_string(),
+            devices_id: vec!["sasaki".to_string()],
+            devices_id_to_name: HashMap::from([(
+                "sasaki".to_string(),
+                "Sasaki's phone".to_string(),
+            )]),
+            devices_last_seen: HashMap::new(),
+            devices_online: vec![],
+            hashed_key_phrase: String::new(),
+            schema_version: DevicesPool::SCHEMA_VERSION,
+            created_at: 1688663025000,
+            version: 3,
+        };
+        let sent = serde_json::to_value(&pool).unwrap();
+        assert_eq!(
+            sent,
+            json!({
+                "pool_name": "bl",
+                "devices_id": ["sasaki"],
+                "devices_id_to_name": { "sasaki": "Sasaki's phone" },
+                "devices_last_seen": {},
+                "schema_version": 2,
+                "created_at": 1688663025000i64,
+                "version": 3,
+            })
+        );
+        assert_eq!(serde_json::from_value::<DevicesPool>(sent).unwrap(), pool);
+
+        // stored before the migrations existed
+        let old_pool: DevicesPool = serde_json::from_value(json!({
+            "pool_name": "bl",
+            "devices_id": [],
+            "devices_id_to_name": {},
+        }))
+        .unwrap();
+        assert_eq!(
+            (
+                old_pool.schema_version,
+                old_pool.created_at,
+                old_pool.version
+            ),
+            (0, 0, 0)
+        );
+
+        // as the clients receive it: extended json, no md5, and files uploaded before the metadata existed
+        let file: FileInfo = serde_json::from_value(json!({
+            "_id": { "$oid": "64a6f3f1c2a4e1b2c3d4e5f6" },
+            "filename": "miyano.txt",
+            "chunkSize": 261120,
+            "length": 17,
+            "uploadDate": { "$date": { "$numberLong": "1688663025000" } },
+        }))
+        .unwrap();
+        assert_eq!(file._id.to_hex(), "64a6f3f1c2a4e1b2c3d4e5f6");
+        assert_eq!(file.metadata.codec, Codec::None);
+        assert!(!file.metadata.is_thumbnail);
+    }
+
+    #[test]
+    fn file_metadata_seal_test() {
+        let metadata = FileMetadata {
+            size: Some(17),
+            sha256: Some("89b92d4d4cbe2955".to_string()),
+            client: ClientFileMetadata {
+                last_modified: Some(1688663025000),
+                caption: Some("sasaki".to_string()),
+            },
+            ..Default::default()
+        };
+        let kp = KeyPhrase("sasaki-miyano".to_string());
+
+        let mut sealed = metadata.clone();
+        sealed.seal(&kp).unwrap();
+        let stored = bson::to_document(&sealed).unwrap();
+        assert!(["sha256", "caption", "last_modified"]
+            .iter()
+            .all(|key| !stored.contains_key(key)));
+        assert!(stored.contains_key("sealed"));
+
+        let mut wrong_pool = sealed.clone();
+        let err = wrong_pool
+            .open(&KeyPhrase("hirano-kagiura".to_string()))
+            .unwrap_err();
+        assert_eq!(err, ServerErrors::DecryptionError);
+
+        sealed.open(&kp).unwrap();
+        assert_eq!(sealed, metadata);
+        // stored before it existed
+        let mut legacy = FileMetadata::default();
+        legacy.open(&kp).unwrap();
+        assert_eq!(legacy, FileMetadata::default());
+    }
+}
@@ -0,0 +1,152 @@
+//! What the server needs from its database, it's implemented for the mongodb client by the server.
+//!
+//! Every method takes the pool key phrase (or its hash), a pool never sees the datas of another one
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{
+    errors::ServerError,
+    keyphrase::KeyPhrase,
+    models::{ClientFileMetadata, DevicesPool, FileInfo, FilePoolTransferExt, NewPoolPayload},
+};
+
+/// a file received from a client, before being stored
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UploadedFile {
+    pub filename: String,
+    pub datas: Vec<u8>,
+    /// the content type declared by the client, only used when it can't be detected
+    pub content_type: Option<String>,
+    pub client_metadata: ClientFileMetadata,
+}
+
+/// The pools and their devices
+#[async_trait]
+pub trait DevicePoolsCollection {
+    /// the pool of this key phrase, `PoolNotFound` if there is none
+    async fn get_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerError>;
+    /// creates a pool with a new key phrase and the device in it, it returns the plain text key phrase
+    async fn create_pool(&self, args: NewPoolPayload) -> Result<String, ServerError>;
+    /// `AlreadyInPool` if this device id is taken, it returns the updated pool
+    async fn join_pool(
+        &self,
+        key_phrase: &KeyPhrase,
+        device_id: &str,
+        device_name: &str,
+    ) -> Result<DevicesPool, ServerError>;
+    /// *this will also delete all the remaining user transfers and files*
+    ///
+    /// if nobody left in the pool, the pool is deleted (unless a device joined meanwhile). It returns the updated pool
+    /// and the ids of the deleted transfers
+    async fn leave_pool(
+        &self,
+        key_phrase: &KeyPhrase,
+        device_id: &str,
+    ) -> Result<(DevicesPool, Vec<String>), ServerError>;
+    /// `VersionConflict` if `expected_version` is given and the pool changed since (see [`DevicesPool::version`]), it
+    /// returns the updated pool
+    async fn rename_pool(
+        &self,
+        key_phrase: &KeyPhrase,
+        pool_name: &str,
+        expected_version: Option<u64>,
+    ) -> Result<DevicesPool, ServerError>;
+    /// deletes everything, the pool, all its corresponding transfers and files
+    async fn delete_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerError>;
+    /// `hashed_key_phrase` is taken directly since it's called by the broadcaster, which only knows the pool by its hash
+    async fn update_last_seen(
+        &self,
+        hashed_key_phrase: &str,
+        device_id: &str,
+        last_seen: i64,
+    ) -> Result<(), ServerError>;
+    /// run at startup, pools are looked up by their hashed key phrase
+    async fn create_pool_hashed_kp_index(&self) -> Result<()>;
+}
+
+/// The transfers of files between the devices of a pool
+#[async_trait]
+pub trait FilePoolTransferCollection {
+    /// the transfers sent to this device
+    async fn find_transfers(
+        &self,
+        key_phrase: &KeyPhrase,
+        device_id: &str,
+    ) -> Result<Vec<FilePoolTransferExt>, ServerError>;
+    /// this only creates the transfer in db, files must be added to the db before calling this,
+    /// files are mendatory to call this.
+    async fn create_transfer(
+        &self,
+        key_phrase: &KeyPhrase,
+        from: &str,
+        to: &str,
+        files_id: &[String],
+        thumbnails_id: &HashMap<String, String>,
+    ) -> Result<FilePoolTransferExt, ServerError>;
+    /// the files must be added to the db before calling this, it returns the updated transfer
+    async fn add_files_to_transfer(
+        &self,
+        files_id: &[String],
+        thumbnails_id: &HashMap<String, String>,
+        transfer_id: &str,
+        key_phrase: &KeyPhrase,
+    ) -> Result<FilePoolTransferExt, ServerError>;
+    /// if no files left in the transfer, this'll remove the transfer.
+    ///
+    /// It returns the updated transfer, its `files_id` is empty if it was removed
+    async fn remove_transfer_file(
+        &self,
+        file_id: &str,
+        key_phrase: &KeyPhrase,
+    ) -> Result<FilePoolTransferExt, ServerError>;
+    /// **this only delete the transfer, not the files linked to it**,
+    /// it returns the transfer's files_ids
+    ///
+    /// *I know it's dumb, it's a development mistake, but I'm too lazy to changes it, because I don't want to break anything*
+    async fn delete_transfer(
+        &self,
+        key_phrase: &KeyPhrase,
+        device_id: &str,
+        transfer_id: &str,
+    ) -> Result<Vec<String>, ServerError>;
+    /// run at startup, transfers are looked up by their pool hashed key phrase
+    async fn create_transfer_hashed_kp_index(&self) -> Result<()>;
+}
+
+/// The files of the transfers, encrypted with the key phrase of their pool
+#[async_trait]
+pub trait FileStorageGridFS {
+    /// the stored infos of these files, with their metadata decrypted. `FileNotFound` if one of them doesn't exist or
+    /// isn't in a transfer of this pool
+    async fn get_files_info(
+        &self,
+        files_ids: &[String],
+        key_phrase: &KeyPhrase,
+    ) -> Result<Vec<FileInfo>, ServerError>;
+    /// decrypts, decompresses and download file from db
+    async fn get_file(
+        &self,
+        file_id: &str,
+        key_phrase: &KeyPhrase,
+    ) -> Result<(FileInfo, Vec<u8>), ServerError>;
+    /// decrypts and download the thumbnail of a file
+    async fn get_thumbnail(
+        &self,
+        file_id: &str,
+        key_phrase: &KeyPhrase,
+    ) -> Result<Vec<u8>, ServerError>;
+    /// detects the files metadata, compresses (if worth it), encrypts and add files to db.
+    ///
+    /// Images also get an encrypted thumbnail, it returns the files ids and a map of file_id -> thumbnail_id.
+    /// If one of the files can't be added, the others are deleted
+    async fn add_files(
+        &self,
+        files: Vec<UploadedFile>,
+        key_phrase: &KeyPhrase,
+    ) -> Result<(Vec<String>, HashMap<String, String>), ServerError>;
+    /// this also deletes the files thumbnails
+    async fn delete_files(&self, files_ids: &[String]) -> Result<(), ServerError>;
+}
@@ -0,0 +1,108 @@
+use std::fmt::Display;
+
+use ilix_core::errors::ServerErrors;
+use serde::Deserialize;
+
+/// Everything that can go wrong with a call to the api
+#[derive(Debug)]
+pub enum Error {
+    /// the server answered with an error
+    Api(ApiError),
+    /// the request couldn't be sent, or its response read
+    Http(reqwest::Error),
+    /// the response isn't what this version of the sdk expects
+    Decode(serde_json::Error),
+    /// a local file couldn't be read
+    Io(std::io::Error),
+    /// this route needs the key phrase of the pool, see [`crate::Client::with_key_phrase`]
+    MissingKeyPhrase,
+}
+
+impl Error {
+    /// the server error code, if it's an api error
+    pub fn kind(&self) -> Option<ServerErrors> {
+        match self {
+            Error::Api(err) => err.kind(),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Api(err) => write!(f, "{err}"),
+            Error::Http(err) => write!(f, "http error: {err}"),
+            Error::Decode(err) => write!(f, "unexpected response: {err}"),
+            Error::Io(err) => write!(f, "io error: {err}"),
+            Error::MissingKeyPhrase => write!(f, "this route needs the key phrase of the pool"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Api(_) | Error::MissingKeyPhrase => None,
+            Error::Http(err) => Some(err),
+            Error::Decode(err) => Some(err),
+            Error::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Decode(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// An error response of the api
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ApiError {
+    pub status_code: u16,
+    /// the stable error code, e.g: "PoolNotFound". `None` when the error doesn't come from the api (e.g: a proxy)
+    pub reason: Option<String>,
+    pub message: Option<String>,
+    pub details: Option<String>,
+    /// give it to the server operator to find what happened in the logs
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    /// the parsed error code, `None` if this sdk doesn't know it
+    pub fn kind(&self) -> Option<ServerErrors> {
+        ServerErrors::parse(self.reason.as_deref()?).ok()
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.status_code)?;
+        if let Some(reason) = &self.reason {
+            write!(f, " {reason}")?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        if let Some(details) = &self.details {
+            write!(f, " ({details})")?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " [request {request_id}]")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{stream::BoxStream, Stream, StreamExt};
+use ilix_core::events::SSEData;
+
+use crate::Error;
+
+/// An event of the pool, as sent to this device
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// to resume the stream from here, see [`crate::Client::events`]
+    pub id: Option<u64>,
+    pub data: SSEData,
+}
+
+/// The events of the pool, it ends when the server closes the connection
+pub struct EventStream {
+    inner: BoxStream<'static, Result<Event, Error>>,
+}
+
+impl EventStream {
+    pub(crate) fn new(res: reqwest::Response) -> Self {
+        let inner = res
+            .bytes_stream()
+            .scan(SseParser::default(), |parser, chunk| {
+                let events = match chunk {
+                    Ok(chunk) => parser.feed(&chunk).into_iter().map(Ok).collect(),
+                    Err(err) => vec![Err(Error::Http(err))],
+                };
+                futures_util::future::ready(Some(futures_util::stream::iter(events)))
+            })
+            .flatten()
+            .filter_map(|raw| futures_util::future::ready(raw.map(RawEvent::decode).transpose()))
+            .boxed();
+        Self { inner }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// an event as written on the wire
+#[derive(Debug, Default, PartialEq)]
+struct RawEvent {
+    event: Option<String>,
+    id: Option<String>,
+    data: String,
+}
+
+impl RawEvent {
+    /// `None` for the events that aren't pool events ("connected") and the ones this sdk doesn't know yet (the server
+    /// can add events without bumping its schema version)
+    fn decode(self) -> Option<Event> {
+        if self.event.as_deref() == Some("connected") {
+            return None;
+        }
+        let data = serde_json::from_str::<SSEData>(&self.data).ok()?;
+        Some(Event {
+            id: self.id.and_then(|id| id.parse().ok()),
+            data,
+        })
+    }
+}
+
+/// splits the `text/event-stream` body into events, whatever how it's chunked
+#[derive(Default)]
+struct SseParser {
+    buf: Vec<u8>,
+}
+
+impl SseParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<RawEvent> {
+        // the json datas never contain a raw `\r`, the line endings can be normalized
+        self.buf.extend(chunk.iter().filter(|b| **b != b'\r'));
+
+        let mut events = vec![];
+        while let Some(end) = self.buf.windows(2).position(|w| w == b"\n\n") {
+            let block = self.buf.drain(..end + 2).collect::<Vec<_>>();
+            if let Some(event) = Self::parse_block(&String::from_utf8_lossy(&block)) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn parse_block(block: &str) -> Option<RawEvent> {
+        let mut event = RawEvent::default();
+        let mut data_lines = vec![];
+        for line in block.lines() {
+            // lines starting with ':' are comments (keep alive)
+            let (field, value) = match line.split_once(':') {
+                Some(("", _)) | None => continue,
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            };
+            match field {
+                "event" => event.event = Some(value.to_string()),
+                "id" => event.id = Some(value.to_string()),
+                "data" => data_lines.push(value),
+                _ => {}
+            }
+        }
+
+        if data_lines.is_empty() {
+            return None;
+        }
+        event.data = data_lines.join("\n");
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ilix_core::events::SSEData;
+
+    use super::{RawEvent, SseParser};
+
+    #[test]
+    fn sse_parser_test() {
+        let mut parser = SseParser::default();
+        assert!(parser
+            .feed(b"event: connected\ndata: {\"schema_version\":1}\n")
+            .is_empty());
+
+        let events = parser.feed(b"\n: keep-alive\n\nevent: logout\nid: 4");
+        assert_eq!(
+            events,
+            vec![RawEvent {
+                event: Some("connected".to_string()),
+                id: None,
+                data: "{\"schema_version\":1}".to_string()
+            }]
+        );
+        assert!(events.into_iter().next().unwrap().decode().is_none());
+
+        let events =
+            parser.feed(b"2\r\ndata: \"Logout\"\r\n\r\nevent: new_event\ndata: {\"New\":{}}\n\n");
+        assert_eq!(events.len(), 2);
+        let mut events = events.into_iter().filter_map(RawEvent::decode);
+        let logout = events.next().unwrap();
+        assert_eq!(logout.id, Some(42));
+        assert!(matches!(logout.data, SSEData::Logout));
+        assert!(events.next().is_none()); // unknown events are skipped
+    }
+}
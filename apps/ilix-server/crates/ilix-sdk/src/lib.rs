@@ -0,0 +1,478 @@
+//! Rust client of the ilix api, built on the types of `ilix-core`.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), ilix_sdk::Error> {
+//! use ilix_sdk::{Client, Upload};
+//!
+//! let client = Client::new("http://localhost:3000");
+//! let key_phrase = client.create_pool("ilovecat", "laptop", "My laptop").await?;
+//!
+//! let client = client.with_key_phrase(key_phrase);
+//! let pool = client.get_pool().await?;
+//! let files = vec![Upload::from_path("./cat.jpg").await?];
+//! client.create_transfer("laptop", &pool.devices_id[0], files).await?;
+//! # Ok(()) }
+//! ```
+
+mod error;
+mod events;
+
+use bytes::Bytes;
+use futures_util::{Stream, TryStreamExt};
+use reqwest::{
+    header::{self, HeaderMap},
+    multipart::{Form, Part},
+    Body, Method, RequestBuilder, Response,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::path::Path;
+use tokio_util::io::ReaderStream;
+
+pub use error::{ApiError, Error};
+pub use events::{Event, EventStream};
+pub use ilix_core::{
+    errors::ServerErrors,
+    events::{
+        DeviceJoined, DeviceLeft, FileAdded, FileRemoved, PoolRenamed, Presence, SSEData,
+        TransferAck, TransferDeleted, SSE_SCHEMA_VERSION,
+    },
+    models::{ClientFileMetadata, DevicesPool, FileInfo, FileMetadata, FilePoolTransferExt},
+};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// the v2 routes are used, their `data` is a json value
+const API_PREFIX: &str = "/v2";
+
+/// A client of one ilix server. It's cheap to clone, the clones share their connection pool
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    key_phrase: Option<String>,
+}
+
+impl Client {
+    /// `base_url` is the server root, e.g: "https://ilix-api.fly.dev"
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// to configure the timeouts, proxies, ...
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            key_phrase: None,
+        }
+    }
+
+    /// a client of the pool with this key phrase, every route but [`Client::create_pool`] needs one
+    pub fn with_key_phrase(&self, key_phrase: impl Into<String>) -> Self {
+        Self {
+            key_phrase: Some(key_phrase.into()),
+            ..self.clone()
+        }
+    }
+
+    pub fn key_phrase(&self) -> Option<&str> {
+        self.key_phrase.as_deref()
+    }
+
+    /// whether the server is up
+    pub async fn ping(&self) -> Result<()> {
+        self.http
+            .get(format!("{}/ping", self.base_url))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // pool
+
+    /// creates a pool with this device in it, returns its key phrase
+    pub async fn create_pool(
+        &self,
+        name: &str,
+        device_id: &str,
+        device_name: &str,
+    ) -> Result<String> {
+        let req = self
+            .request(Method::POST, "/pool/new")
+            .json(&json!({ "name": name, "device_id": device_id, "device_name": device_name }));
+        self.send(req).await
+    }
+
+    /// the pool, with its devices currently online
+    pub async fn get_pool(&self) -> Result<DevicesPool> {
+        self.send(self.authed(Method::GET, "/pool")?).await
+    }
+
+    pub async fn join_pool(&self, device_id: &str, device_name: &str) -> Result<DevicesPool> {
+        let req = self
+            .authed(Method::PUT, "/pool/join")?
+            .json(&json!({ "device_id": device_id, "device_name": device_name }));
+        self.send(req).await
+    }
+
+    /// the transfers of this device are deleted, the pool is deleted with its last device
+    pub async fn leave_pool(&self, device_id: &str) -> Result<()> {
+        let req = self
+            .authed(Method::DELETE, "/pool/leave")?
+            .json(&json!({ "device_id": device_id }));
+        self.send_empty(req).await
+    }
+
+    /// with a `version`, it's refused (`VersionConflict`) if the pool changed since this version
+    pub async fn rename_pool(&self, name: &str, version: Option<u64>) -> Result<DevicesPool> {
+        let req = self
+            .authed(Method::PUT, "/pool/rename")?
+            .json(&json!({ "name": name, "version": version }));
+        self.send(req).await
+    }
+
+    /// deletes the pool with all its transfers and files
+    pub async fn delete_pool(&self) -> Result<()> {
+        self.send_empty(self.authed(Method::DELETE, "/pool")?).await
+    }
+
+    // transfers
+
+    /// the transfers sent to this device
+    pub async fn transfers(&self, device_id: &str) -> Result<Vec<FilePoolTransferExt>> {
+        let req = self.authed(Method::GET, &format!("/file-transfer/{device_id}/all"))?;
+        self.send(req).await
+    }
+
+    /// uploads the files and sends them to the `to` device, returns the transfer id
+    pub async fn create_transfer(
+        &self,
+        from: &str,
+        to: &str,
+        files: Vec<Upload>,
+    ) -> Result<String> {
+        let req = self
+            .authed(Method::POST, "/file-transfer")?
+            .query(&[("from", from), ("to", to)])
+            .multipart(Upload::form(files)?);
+        self.send(req).await
+    }
+
+    /// adds files to a transfer, returns their ids
+    pub async fn add_files(&self, transfer_id: &str, files: Vec<Upload>) -> Result<Vec<String>> {
+        let req = self
+            .authed(
+                Method::POST,
+                &format!("/file-transfer/{transfer_id}/add_files"),
+            )?
+            .multipart(Upload::form(files)?);
+        self.send(req).await
+    }
+
+    /// `device_id` is the recipient of the transfer
+    pub async fn delete_transfer(&self, device_id: &str, transfer_id: &str) -> Result<()> {
+        let req = self.authed(
+            Method::DELETE,
+            &format!("/file-transfer/{device_id}/{transfer_id}"),
+        )?;
+        self.send_empty(req).await
+    }
+
+    // files
+
+    /// the infos of files sent in this pool, with their decrypted metadata
+    pub async fn files_info(&self, files_ids: &[String]) -> Result<Vec<FileInfo>> {
+        let req = self
+            .authed(Method::GET, "/files/info")?
+            .query(&[("files_ids", files_ids.join(","))]);
+        self.send(req).await
+    }
+
+    /// the decrypted file, streamed
+    pub async fn download_file(&self, file_id: &str) -> Result<Download> {
+        let res = self
+            .authed(Method::GET, &format!("/file/{file_id}"))?
+            .send()
+            .await?;
+        let res = Self::check_status(res).await?;
+        Ok(Download::new(res))
+    }
+
+    /// the jpeg preview of an image, `ThumbnailNotFound` if the file has none
+    pub async fn thumbnail(&self, file_id: &str) -> Result<Bytes> {
+        let res = self
+            .authed(Method::GET, &format!("/file/{file_id}/thumbnail"))?
+            .send()
+            .await?;
+        Ok(Self::check_status(res).await?.bytes().await?)
+    }
+
+    /// removes the file from its transfer, the transfer is deleted with its last file
+    pub async fn delete_file(&self, file_id: &str) -> Result<()> {
+        self.send_empty(self.authed(Method::DELETE, &format!("/file/{file_id}"))?)
+            .await
+    }
+
+    // events
+
+    /// The events of the pool sent to this device.
+    ///
+    /// Give the id of the last event received to get the ones missed while disconnected (if the server still has them).
+    /// The pool changes come as incremental events (`DeviceJoined`, `DeviceLeft`, `PoolRenamed`), not the whole pool
+    pub async fn events(&self, device_id: &str, last_event_id: Option<u64>) -> Result<EventStream> {
+        let schema_version = SSE_SCHEMA_VERSION.to_string();
+        let mut req = self
+            .authed(Method::GET, "/events")?
+            .query(&[
+                ("device_id", device_id),
+                ("schema_version", &schema_version),
+            ])
+            .header(header::ACCEPT, "text/event-stream");
+        if let Some(id) = last_event_id {
+            req = req.header("Last-Event-ID", id.to_string());
+        }
+        let res = Self::check_status(req.send().await?).await?;
+        Ok(EventStream::new(res))
+    }
+
+    // helpers
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{API_PREFIX}{path}", self.base_url))
+    }
+
+    /// a request to a route that needs the key phrase
+    fn authed(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        let key_phrase = self.key_phrase.as_deref().ok_or(Error::MissingKeyPhrase)?;
+        Ok(self
+            .request(method, path)
+            .header(header::AUTHORIZATION, key_phrase))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, req: RequestBuilder) -> Result<T> {
+        let envelope = Self::envelope::<T>(req).await?;
+        // a successful response without data would be a server bug
+        envelope
+            .data
+            .ok_or_else(|| Error::Decode(serde::de::Error::missing_field("data")))
+    }
+
+    /// for the routes without data
+    async fn send_empty(&self, req: RequestBuilder) -> Result<()> {
+        Self::envelope::<serde_json::Value>(req).await?;
+        Ok(())
+    }
+
+    async fn envelope<T: DeserializeOwned>(req: RequestBuilder) -> Result<Envelope<T>> {
+        let res = Self::check_status(req.send().await?).await?;
+        let envelope = serde_json::from_slice::<Envelope<T>>(&res.bytes().await?)?;
+        match envelope.success {
+            true => Ok(envelope),
+            false => Err(Error::Api(ApiError {
+                status_code: envelope.status_code,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// turns the error responses into [`Error::Api`]
+    async fn check_status(res: Response) -> Result<Response> {
+        if res.status().is_success() {
+            return Ok(res);
+        }
+
+        let status_code = res.status().as_u16();
+        let body = res.bytes().await?;
+        let err = serde_json::from_slice::<ApiError>(&body).unwrap_or(ApiError {
+            status_code,
+            // not an api error, e.g: the route doesn't exist (on an older server)
+            details: Some(String::from_utf8_lossy(&body).to_string())
+                .filter(|body| !body.is_empty()),
+            ..Default::default()
+        });
+        Err(Error::Api(err))
+    }
+}
+
+/// the v2 response envelope
+#[derive(Deserialize)]
+struct Envelope<T> {
+    success: bool,
+    status_code: u16,
+    data: Option<T>,
+}
+
+/// A file to upload
+pub struct Upload {
+    filename: String,
+    body: Body,
+    len: Option<u64>,
+    mime: Option<String>,
+    metadata: Option<ClientFileMetadata>,
+}
+
+impl Upload {
+    pub fn bytes(filename: impl Into<String>, datas: impl Into<Bytes>) -> Self {
+        let datas: Bytes = datas.into();
+        Self {
+            filename: filename.into(),
+            len: Some(datas.len() as u64),
+            body: Body::from(datas),
+            mime: None,
+            metadata: None,
+        }
+    }
+
+    /// streams the file from the disk, it's never loaded in memory
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "file".to_string());
+        Ok(Self {
+            filename,
+            body: Body::wrap_stream(ReaderStream::new(file)),
+            len: Some(len),
+            mime: None,
+            metadata: None,
+        })
+    }
+
+    /// otherwise the server detects it
+    pub fn with_mime(mut self, mime: impl Into<String>) -> Self {
+        self.mime = Some(mime.into());
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: ClientFileMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    fn form(files: Vec<Upload>) -> Result<Form> {
+        let mut form = Form::new();
+        for (i, file) in files.into_iter().enumerate() {
+            let field = format!("file-{i}");
+            if let Some(metadata) = &file.metadata {
+                form = form.text(
+                    format!("{field}.metadata"),
+                    serde_json::to_string(metadata)?,
+                );
+            }
+
+            let mut part = match file.len {
+                Some(len) => Part::stream_with_length(file.body, len),
+                None => Part::stream(file.body),
+            }
+            .file_name(file.filename);
+            if let Some(mime) = file.mime {
+                part = part.mime_str(&mime)?;
+            }
+            form = form.part(field, part);
+        }
+        Ok(form)
+    }
+}
+
+/// A downloaded file, its content is streamed
+pub struct Download {
+    /// the original filename
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub len: Option<u64>,
+    res: Response,
+}
+
+impl Download {
+    fn new(res: Response) -> Self {
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            filename: parse_filename(res.headers()),
+            content_type: header(header::CONTENT_TYPE),
+            len: res.content_length(),
+            res,
+        }
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes>> {
+        self.res.bytes_stream().map_err(Error::Http)
+    }
+
+    /// the whole file, in memory
+    pub async fn bytes(self) -> Result<Bytes> {
+        Ok(self.res.bytes().await?)
+    }
+}
+
+/// the filename of the `Content-Disposition` header, `filename*` (utf8) first
+fn parse_filename(headers: &HeaderMap) -> Option<String> {
+    let disposition = headers.get(header::CONTENT_DISPOSITION)?.to_str().ok()?;
+    let params = disposition
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .collect::<Vec<_>>();
+
+    let extended = params
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("filename*"))
+        .and_then(|(_, value)| value.split_once("''"))
+        .and_then(|(_, encoded)| percent_decode(encoded));
+    extended.or_else(|| {
+        params
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("filename"))
+            .map(|(_, value)| value.trim_matches('"').to_string())
+    })
+}
+
+fn percent_decode(encoded: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut chars = encoded.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'%' => {
+                let hex = [chars.next()?, chars.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue, CONTENT_DISPOSITION};
+
+    use super::parse_filename;
+
+    #[test]
+    fn parse_filename_test() {
+        let filename = |disposition: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_DISPOSITION, HeaderValue::from_static(disposition));
+            parse_filename(&headers)
+        };
+
+        assert_eq!(
+            filename("attachment; filename=\"test1.jpg\"").as_deref(),
+            Some("test1.jpg")
+        );
+        assert_eq!(
+            filename("inline; filename=\"caf_.txt\"; filename*=UTF-8''caf%C3%A9.txt").as_deref(),
+            Some("café.txt")
+        );
+        assert_eq!(filename("inline"), None);
+    }
+}
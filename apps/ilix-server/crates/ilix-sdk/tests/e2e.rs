@@ -0,0 +1,419 @@
+//! End to end tests of the api through the sdk, they need a running server (and its database).
+//!
+//! The server url is read from `ILIX_URL`, "http://localhost:3000" by default
+
+use std::env;
+
+use futures_util::future;
+use ilix_core::keyphrase::{KeyPhrase, KEY_PHRASE_LEN};
+use ilix_sdk::{
+    Client, ClientFileMetadata, Error, FileInfo, FilePoolTransferExt, ServerErrors, Upload,
+};
+use tokio::join;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// shared with the server unit tests
+const ASSETS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../tests/Assets");
+/// the words of the server key phrases
+const DICTIONARY: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../Assets/english_dictionary_words.txt"
+);
+
+#[tokio::test]
+async fn e2e_full_api() {
+    let client = Client::new(env::var("ILIX_URL").unwrap_or("http://localhost:3000".to_string()));
+    client
+        .ping()
+        .await
+        .expect("This server should be launched before tests");
+
+    // InvalidKeyPhrase tests
+    {
+        // behind evevy route the "InvalidKeyPhrase" error comes from the same extractor (the same code is executed) so if it work for one it'll work for all the other
+        let res = client.with_key_phrase("not valid kp").get_pool().await;
+        assert_api_err(res, ServerErrors::InvalidKeyPhrase);
+    }
+
+    // PoolNotFound tests
+    {
+        let fake_pool = client.with_key_phrase(fake_key_phrase());
+        assert_api_err(fake_pool.get_pool().await, ServerErrors::PoolNotFound);
+        assert_api_err(
+            fake_pool.join_pool("bliwox", "bliwox1").await,
+            ServerErrors::PoolNotFound,
+        );
+        assert_api_err(
+            fake_pool.leave_pool("bliwox").await,
+            ServerErrors::PoolNotFound,
+        );
+        assert_api_err(fake_pool.delete_pool().await, ServerErrors::PoolNotFound);
+    }
+
+    // TransferNotFound tests
+    {
+        let fake_pool = client.with_key_phrase(fake_key_phrase());
+        assert_api_err(
+            fake_pool.delete_file("64ca5c14b2d5be5721421a84").await,
+            ServerErrors::TransferNotFound,
+        );
+        assert_api_err(
+            fake_pool
+                .delete_transfer("ilingu", "64ca5c14b2d5be5721421a84")
+                .await,
+            ServerErrors::TransferNotFound,
+        );
+    }
+
+    // check that no transfer exists
+    {
+        let fake_pool = client.with_key_phrase(fake_key_phrase());
+        exec_get_all_transfer(&fake_pool, true).await;
+    }
+
+    // check that no file input is an error
+    {
+        let fake_pool = client.with_key_phrase(fake_key_phrase());
+        exec_get_files_info(&fake_pool, &["64ca5c14b2d5be5721421a84".to_string()], true).await;
+        exec_get_files(&fake_pool, &[], true).await;
+    }
+
+    let pool = exec_new_pool(&client).await; // new pool test, must create a pool for next tests
+
+    // get pool test
+    {
+        let devices_pool = pool.get_pool().await.unwrap();
+        assert_eq!(devices_pool.devices_id, vec!["ilingu"]);
+        assert_eq!(devices_pool.pool_name, "ilovecat");
+    }
+
+    // rename
+    {
+        let renamed = pool.rename_pool("ilovedog", None).await.unwrap();
+        assert_eq!(renamed.pool_name, "ilovedog");
+        assert_api_err(pool.rename_pool("", None).await, ServerErrors::BadArgs);
+        // the pool changed since the version this client knows
+        assert_api_err(
+            pool.rename_pool("ilovebird", Some(renamed.version - 1))
+                .await,
+            ServerErrors::VersionConflict,
+        );
+        // the next tests expect the original name
+        pool.rename_pool("ilovecat", Some(renamed.version))
+            .await
+            .unwrap();
+    }
+
+    // join
+    {
+        exec_join_pool(&pool).await; // join pool test
+                                     // AlreadyInPool pool test
+        assert_api_err(
+            pool.join_pool("bliwox", "bliwox1").await,
+            ServerErrors::AlreadyInPool,
+        );
+    }
+
+    // leave
+    {
+        pool.leave_pool("bliwox").await.unwrap(); // leave pool test
+        assert_api_err(pool.leave_pool("bliwox").await, ServerErrors::NotInPool);
+        // NotInPool pool test
+    }
+
+    exec_join_pool(&pool).await; // must have two user in pool for next tests
+
+    let transfer_id = exec_create_transfer(&pool).await;
+    {
+        let transfers = exec_get_all_transfer(&pool, false).await;
+
+        assert_eq!(transfers.len(), 1);
+        assert!(transfers.iter().all(|t| !t.files_id.is_empty()));
+        assert_eq!(transfers[0].from, "bliwox");
+        assert_eq!(transfers[0].to, "ilingu");
+        assert_eq!(transfers[0].thumbnails_id.len(), 1); // only test1.jpg is an image
+    }
+
+    let file3 = Upload::from_path(format!("{ASSETS}/test3.mp3"))
+        .await
+        .unwrap()
+        .with_mime("audio/mpeg");
+    let added_files_ids = pool.add_files(&transfer_id, vec![file3]).await.unwrap();
+    assert!(!added_files_ids.is_empty());
+
+    let transfers = exec_get_all_transfer(&pool, false).await;
+    assert_eq!(transfers.len(), 1);
+    assert!(transfers.iter().all(|t| !t.files_id.is_empty()));
+
+    let added_transfer = &transfers[0];
+    assert!(added_files_ids
+        .iter()
+        .all(|file_id| added_transfer.files_id.contains(file_id)));
+    assert_eq!(added_transfer.files_id.len(), 3);
+
+    // test file getters
+    {
+        exec_get_files_info(&pool, &added_transfer.files_id, false).await;
+        {
+            let fake_pool = client.with_key_phrase(fake_key_phrase());
+            // another pool doesn't see them
+            exec_get_files_info(&fake_pool, &added_transfer.files_id, true).await;
+            exec_get_files(&fake_pool, &added_transfer.files_id, true).await;
+            // decryption error
+        }
+        exec_get_files(&pool, &added_transfer.files_id, false).await;
+    }
+
+    // test delete file
+    let deleted_file_id = added_transfer.files_id[0].clone();
+    exec_delete_file(&pool, &deleted_file_id).await;
+
+    let transfers = exec_get_all_transfer(&pool, false).await;
+    assert_eq!(transfers.len(), 1);
+
+    let added_transfer = &transfers[0];
+
+    // check that file has also been deleted of the transfer
+    assert!(!added_transfer.files_id.contains(&deleted_file_id));
+
+    // test delete transfer
+    pool.delete_transfer("ilingu", &transfer_id).await.unwrap();
+
+    // check that transfer really deleted and no files left
+    exec_get_all_transfer(&pool, true).await;
+    exec_get_files_info(&pool, &added_transfer.files_id, true).await;
+
+    // delete everyone in pool should delete pool
+    {
+        pool.leave_pool("bliwox").await.unwrap();
+        pool.leave_pool("ilingu").await.unwrap();
+
+        assert_api_err(pool.get_pool().await, ServerErrors::PoolNotFound); // check that pool has been deleted
+    }
+
+    // test delete pool with files and transfer left in pool
+    {
+        // recreate a new pool to test if delete pool works
+        let pool = exec_new_pool(&client).await;
+        exec_join_pool(&pool).await;
+
+        exec_create_transfer(&pool).await;
+        let transfers = exec_get_all_transfer(&pool, false).await;
+        assert_eq!(transfers.len(), 1);
+        assert!(transfers.iter().all(|t| !t.files_id.is_empty()));
+
+        let added_transfer = &transfers[0];
+        assert_eq!(added_transfer.files_id.len(), 2);
+        exec_get_files_info(&pool, &added_transfer.files_id, false).await;
+
+        // test delete pool
+        pool.delete_pool().await.unwrap();
+
+        // check that nor pool nor transfer nor files are left
+        assert_api_err(pool.get_pool().await, ServerErrors::PoolNotFound);
+        exec_get_all_transfer(&pool, true).await;
+        exec_get_files_info(&pool, &added_transfer.files_id, true).await;
+    }
+
+    // test leave pool with files and transfer left in pool
+    {
+        // recreate a new pool to test if leave pool works and delete remaining transfer and files
+        let pool = exec_new_pool(&client).await;
+        exec_join_pool(&pool).await;
+
+        exec_create_transfer(&pool).await;
+        let transfers = exec_get_all_transfer(&pool, false).await;
+        assert_eq!(transfers.len(), 1);
+        assert!(transfers.iter().all(|t| !t.files_id.is_empty()));
+
+        let added_transfer = &transfers[0];
+        assert_eq!(added_transfer.files_id.len(), 2);
+        exec_get_files_info(&pool, &added_transfer.files_id, false).await;
+
+        // should delete transfer+files
+        pool.leave_pool("ilingu").await.unwrap();
+
+        // check that nor transfer nor files are left
+        exec_get_all_transfer(&pool, true).await;
+        exec_get_files_info(&pool, &added_transfer.files_id, true).await;
+
+        // delete pool
+        pool.delete_pool().await.unwrap();
+        assert_api_err(pool.get_pool().await, ServerErrors::PoolNotFound); // check that pool has been deleted
+    }
+
+    // test delete all file in transfer
+    {
+        // recreate a new pool to test delete all file in transfer
+        let pool = exec_new_pool(&client).await;
+        exec_join_pool(&pool).await;
+
+        exec_create_transfer(&pool).await;
+        let transfers = exec_get_all_transfer(&pool, false).await;
+        assert_eq!(transfers.len(), 1);
+        assert!(transfers.iter().all(|t| !t.files_id.is_empty()));
+
+        let added_transfer = &transfers[0];
+        assert_eq!(added_transfer.files_id.len(), 2);
+        exec_get_files_info(&pool, &added_transfer.files_id, false).await;
+
+        // should delete transfer
+        let tasks = added_transfer
+            .files_id
+            .iter()
+            .map(|file_id| exec_delete_file(&pool, file_id));
+        future::join_all(tasks).await;
+
+        // check that nor transfer nor files are left
+        exec_get_all_transfer(&pool, true).await;
+        exec_get_files_info(&pool, &added_transfer.files_id, true).await;
+
+        // delete pool
+        pool.delete_pool().await.unwrap();
+        assert_api_err(pool.get_pool().await, ServerErrors::PoolNotFound); // check that pool has been deleted
+    }
+
+    println!("->> all tests succeed");
+}
+
+fn fake_key_phrase() -> String {
+    let dictionary = std::fs::read_to_string(DICTIONARY).unwrap();
+    KeyPhrase::from_dictionary(&dictionary, KEY_PHRASE_LEN)
+        .unwrap()
+        .0
+}
+
+/// the status and message are fixed by the error, whatever the endpoint
+fn assert_api_err<T: std::fmt::Debug>(res: Result<T, Error>, expected: ServerErrors) {
+    match res {
+        Err(Error::Api(err)) => {
+            assert_eq!(err.kind(), Some(expected), "{err}");
+            assert_eq!(err.status_code, expected.status_code().as_u16());
+            assert_eq!(err.message.as_deref(), Some(expected.message()));
+        }
+        res => panic!("expected {expected}, got {res:?}"),
+    }
+}
+
+/// returns a client of the new pool
+async fn exec_new_pool(client: &Client) -> Client {
+    let pool_key_phrase = client
+        .create_pool("ilovecat", "ilingu", "ilingu1")
+        .await
+        .unwrap();
+    assert!(pool_key_phrase.split('-').count() == KEY_PHRASE_LEN);
+
+    println!("->> Pool created: {pool_key_phrase}");
+    client.with_key_phrase(pool_key_phrase)
+}
+
+async fn exec_join_pool(pool: &Client) {
+    let devices_pool = pool.join_pool("bliwox", "bliwox1").await.unwrap();
+    assert!(devices_pool.devices_id.contains(&"bliwox".to_string()));
+    assert_eq!(devices_pool.pool_name, "ilovecat");
+
+    println!("->> 'bliwox' joined the pool");
+}
+
+async fn exec_get_all_transfer(pool: &Client, should_be_empty: bool) -> Vec<FilePoolTransferExt> {
+    let transfers = pool.transfers("ilingu").await.unwrap();
+    assert_eq!(transfers.is_empty(), should_be_empty);
+
+    println!("->> Transfers fetched");
+    transfers
+}
+
+async fn exec_create_transfer(pool: &Client) -> String {
+    let (file1, file2) = join!(
+        Upload::from_path(format!("{ASSETS}/test1.jpg")),
+        Upload::from_path(format!("{ASSETS}/test2.txt"))
+    );
+    let file1 = file1
+        .unwrap()
+        .with_mime("image/jpeg")
+        .with_metadata(ClientFileMetadata {
+            caption: Some("sasaki".to_string()),
+            last_modified: Some(1690000000000),
+        });
+    let file2 = file2.unwrap().with_mime("text/plain");
+
+    let transfer_id = pool
+        .create_transfer("bliwox", "ilingu", vec![file1, file2])
+        .await
+        .unwrap();
+    assert!(!transfer_id.is_empty());
+
+    println!("->> Transfers created: {transfer_id}");
+    transfer_id
+}
+
+async fn exec_get_files_info(client: &Client, files_ids: &[String], should_error: bool) {
+    let files_info = client.files_info(files_ids).await;
+    if should_error {
+        assert_api_err(files_info, ServerErrors::FileNotFound);
+        return;
+    }
+
+    let files_info: Vec<FileInfo> = files_info.unwrap();
+    assert_eq!(files_info.len(), files_ids.len());
+    assert!(files_info.iter().all(|info| info.filename == "test1.jpg"
+        || info.filename == "test2.txt"
+        || info.filename == "test3.mp3"));
+    assert!(files_info.iter().all(|info| info.metadata.size.is_some()
+        && info.metadata.sha256.is_some()
+        && info.metadata.mime_type.is_some()));
+    if let Some(jpg_info) = files_info.iter().find(|info| info.filename == "test1.jpg") {
+        assert_eq!(jpg_info.metadata.mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(jpg_info.metadata.client.caption.as_deref(), Some("sasaki"));
+    }
+
+    println!("->> Files info fetched");
+}
+
+async fn exec_get_files(pool: &Client, files_ids: &[String], should_error: bool) {
+    let (file1, file2, file3) = join!(
+        tokio::fs::read(format!("{ASSETS}/test1.jpg")),
+        tokio::fs::read(format!("{ASSETS}/test2.txt")),
+        tokio::fs::read(format!("{ASSETS}/test3.mp3"))
+    );
+    let right_hashes = [
+        xxh3_64(&file1.unwrap()),
+        xxh3_64(&file2.unwrap()),
+        xxh3_64(&file3.unwrap()),
+    ];
+
+    let tasks = files_ids.iter().map(|file_id| async move {
+        let file_buf = pool.download_file(file_id).await?.bytes().await?;
+        Ok::<_, Error>(
+            tokio::task::spawn_blocking(move || xxh3_64(&file_buf))
+                .await
+                .unwrap(),
+        )
+    });
+
+    for file_hash in future::join_all(tasks).await {
+        let file_integrity = file_hash.is_ok_and(|hash| right_hashes.contains(&hash));
+        match should_error {
+            true => {
+                assert!(!file_integrity);
+                return;
+            }
+            false => assert!(file_integrity),
+        };
+    }
+
+    println!("->> File fetched without loss");
+}
+
+async fn exec_delete_file(pool: &Client, file_id: &str) {
+    pool.delete_file(file_id).await.unwrap();
+
+    // check if file really deleted
+    let res = pool
+        .download_file(file_id)
+        .await
+        .map(|download| download.filename);
+    assert_api_err(res, ServerErrors::MongoError);
+
+    println!("->> File deleted successfully.");
+}
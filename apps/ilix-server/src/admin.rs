@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use mongodb::bson::DateTime;
+
+use ilix_core::{
+    errors::ServerError,
+    keyphrase::KeyPhrase,
+    sha256,
+    storage::{DevicePoolsCollection, FilePoolTransferCollection, FileStorageGridFS},
+};
+
+use crate::{
+    config::Config,
+    db::{migrations::latest_schema_version, IlixDB},
+};
+
+/// Maintenance commands, run against the configured database instead of starting the server
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// counts the pools, devices, transfers and files stored
+    Stats,
+    /// finishes the deletions that stopped midway, finds the transfers whose pool is gone and the files of no
+    /// transfer, and deletes them
+    Gc {
+        /// only reports what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+        /// in seconds, the files uploaded more recently are kept since they may belong to an upload in progress
+        #[arg(long, default_value_t = 3600)]
+        min_age: u64,
+    },
+    /// deletes a pool, its transfers and their files
+    PurgePool {
+        /// the `hashed_key_phrase` of the pool document
+        #[arg(long)]
+        hashed_kp: String,
+    },
+    /// checks that every file of a pool decrypts with its key phrase, and matches its checksum
+    VerifyFiles {
+        /// the files are encrypted with the key phrase of their pool, the server only stores its hash
+        #[arg(long, env = "ILIX_KEY_PHRASE")]
+        key_phrase: String,
+    },
+    /// creates the indexes and applies the pending schema migrations
+    Migrate {
+        /// only reports the pending migrations and how many documents they would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// runs the command and prints its report, the error is printed with its causes by `{:#}`
+pub async fn run(command: AdminCommand, db: &IlixDB, config: &Config) -> Result<()> {
+    match command {
+        AdminCommand::Stats => stats(db).await,
+        AdminCommand::Gc { dry_run, min_age } => {
+            gc(db, dry_run, min_age, config.timeouts.max_upload_duration).await
+        }
+        AdminCommand::PurgePool { hashed_kp } => purge_pool(db, &hashed_kp).await,
+        AdminCommand::VerifyFiles { key_phrase } => verify_files(db, key_phrase).await,
+        AdminCommand::Migrate { dry_run } => migrate(db, dry_run).await,
+    }
+}
+
+async fn stats(db: &IlixDB) -> Result<()> {
+    let stats = db.stats().await?;
+    println!("pools:       {}", stats.pools);
+    println!("devices:     {}", stats.devices);
+    println!("transfers:   {}", stats.transfers);
+    println!("files:       {}", stats.files);
+    println!("thumbnails:  {}", stats.thumbnails);
+    println!("stored:      {}", human_bytes(stats.stored_bytes));
+    Ok(())
+}
+
+/// an upload's cleanup is only replayed once it can't be running anymore, the server may be running meanwhile
+async fn gc(db: &IlixDB, dry_run: bool, min_age: u64, max_upload_duration: Duration) -> Result<()> {
+    let now = DateTime::now().to_system_time();
+    let min_age = Duration::from_secs(min_age);
+    let uploaded_before = DateTime::from_system_time(now - min_age);
+    let uploads_started_before = DateTime::from_system_time(now - min_age.max(max_upload_duration));
+    let cleanups = db
+        .pending_cleanups(uploaded_before, uploads_started_before)
+        .await?;
+    println!("{} unfinished deletion(s):", cleanups.len());
+    for cleanup in &cleanups {
+        println!("  {cleanup:?}");
+    }
+    // they'd be reported as orphans otherwise, and they know better what to delete
+    if !dry_run {
+        db.replay_cleanups(uploaded_before, uploads_started_before)
+            .await?;
+    }
+
+    let orphans = db.find_orphans(uploaded_before).await?;
+
+    println!("{} transfer(s) of deleted pools:", orphans.transfers.len());
+    for transfer in &orphans.transfers {
+        println!(
+            "  {} ({} file(s), pool {})",
+            transfer._id.to_hex(),
+            transfer.files_id.len(),
+            transfer.pool_hashed_key_phrase
+        );
+    }
+    let orphaned_bytes = orphans.files.iter().map(|file| file.length as u64).sum();
+    println!(
+        "{} file(s) of no transfer, {}:",
+        orphans.files.len(),
+        human_bytes(orphaned_bytes)
+    );
+    for file in &orphans.files {
+        println!(
+            "  {} {} ({}, uploaded {})",
+            file._id.to_hex(),
+            file.filename,
+            human_bytes(file.length as u64),
+            file.uploadDate
+        );
+    }
+
+    if dry_run {
+        println!("dry run, nothing was deleted");
+        return Ok(());
+    }
+    db.delete_orphans(&orphans).await?;
+    println!(
+        "deleted {} transfer(s) and {} file(s)",
+        orphans.transfers.len(),
+        orphans.files.len()
+    );
+    Ok(())
+}
+
+async fn purge_pool(db: &IlixDB, hashed_kp: &str) -> Result<()> {
+    let purged = db.purge_pool(hashed_kp).await?;
+    match &purged.pool {
+        Some(pool) => println!(
+            "deleted the pool \"{}\" ({} device(s))",
+            pool.pool_name,
+            pool.devices_id.len()
+        ),
+        None => println!("no pool {hashed_kp}"),
+    }
+    println!(
+        "deleted {} transfer(s) and {} file(s)",
+        purged.transfers, purged.files
+    );
+    Ok(())
+}
+
+async fn verify_files(db: &IlixDB, key_phrase: String) -> Result<()> {
+    let key_phrase = KeyPhrase::try_from(key_phrase).map_err(ServerError::from)?;
+    let pool = db.get_pool(&key_phrase).await?;
+    let transfers = db.pool_transfers(&key_phrase.hash(&db.hash_params)).await?;
+    println!(
+        "pool \"{}\": {} transfer(s)",
+        pool.pool_name,
+        transfers.len()
+    );
+
+    let (mut ok, mut failed) = (0, 0);
+    for transfer in &transfers {
+        let files_id = transfer
+            .files_id
+            .iter()
+            .chain(transfer.thumbnails_id.values());
+        for file_id in files_id {
+            match verify_file(db, &key_phrase, file_id).await {
+                Ok(()) => ok += 1,
+                Err(why) => {
+                    failed += 1;
+                    println!("  {file_id} (transfer {}): {why}", transfer._id.to_hex());
+                }
+            }
+        }
+    }
+    println!("{} file(s) checked: {ok} ok, {failed} failed", ok + failed);
+    Ok(())
+}
+
+/// why the file is broken, if it is
+async fn verify_file(db: &IlixDB, key_phrase: &KeyPhrase, file_id: &str) -> Result<(), String> {
+    if db
+        .file_info(file_id)
+        .await
+        .map_err(|err| err.chain())?
+        .is_none()
+    {
+        return Err("missing".to_string());
+    }
+
+    // decrypted and decompressed
+    let (info, datas) = db
+        .get_file(file_id, key_phrase)
+        .await
+        .map_err(|err| err.chain())?;
+    match info.metadata.sha256 {
+        Some(expected) if expected != sha256(&datas) => Err("sha256 mismatch".to_string()),
+        _ => Ok(()),
+    }
+}
+
+async fn migrate(db: &IlixDB, dry_run: bool) -> Result<()> {
+    if !dry_run {
+        db.create_pool_hashed_kp_index().await?;
+        db.create_transfer_hashed_kp_index().await?;
+        db.create_presence_ttl_index().await?;
+        println!("indexes up to date");
+    }
+
+    let current = db.schema_version().await?;
+    let latest = latest_schema_version();
+    if current > latest {
+        bail!("the database schema (v{current}) is newer than this server (v{latest}), update it");
+    }
+    let reports = db.migrate(dry_run).await?;
+    if reports.is_empty() {
+        println!("schema up to date (v{current})");
+        return Ok(());
+    }
+    for report in &reports {
+        let changed = match dry_run {
+            true => "would change",
+            false => "changed",
+        };
+        println!(
+            "v{} {}: {changed} {} document(s)",
+            report.version, report.description, report.changed
+        );
+    }
+    match dry_run {
+        true => println!("dry run, the schema is still v{current}"),
+        false => println!("schema migrated from v{current} to v{latest}"),
+    }
+    Ok(())
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1000.;
+    let mut unit = 0;
+    while value >= 1000. && unit < UNITS.len() - 1 {
+        value /= 1000.;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::human_bytes;
+
+    #[test]
+    fn human_bytes_test() {
+        assert_eq!(human_bytes(12), "12 B");
+        assert_eq!(human_bytes(4_200_000), "4.2 MB");
+        assert_eq!(human_bytes(1_500_000_000_000_000), "1500.0 TB");
+    }
+}
@@ -0,0 +1,513 @@
+use std::{
+    env, fmt, fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use actix_web::http::header::HttpDate;
+use clap::{Parser, ValueEnum};
+use ilix_core::keyphrase::{HashParams, MIN_HASH_ROUND};
+use serde::{Deserialize, Deserializer};
+
+use crate::admin::AdminCommand;
+
+/// The server flags, they override the config file and the env vars
+#[derive(Parser, Default)]
+#[command(version, about = "The ilix api server")]
+pub struct Cli {
+    /// a toml config file, its keys are the config fields (see the README)
+    #[arg(long, env = "ILIX_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, value_enum)]
+    pub mode: Option<AppMode>,
+    /// the address to bind, 127.0.0.1 in dev and 0.0.0.0 in prod by default
+    #[arg(long)]
+    pub host: Option<IpAddr>,
+    #[arg(long, short)]
+    pub port: Option<u16>,
+    /// runs a maintenance command instead of the server
+    #[command(subcommand)]
+    pub command: Option<AdminCommand>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AppMode {
+    #[default]
+    Dev,
+    Prod,
+}
+
+impl FromStr for AppMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "dev" | "development" => Ok(Self::Dev),
+            "prod" | "production" => Ok(Self::Prod),
+            _ => Err(format!(
+                "\"{mode}\" isn't a mode, expected \"dev\" or \"prod\""
+            )),
+        }
+    }
+}
+
+/// where the sse events are shared between the server instances
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PubSubBackend {
+    /// only this instance, enough when there is a single one
+    #[default]
+    Memory,
+    /// every instance connected to the database (it needs a replica set)
+    Mongodb,
+}
+
+impl FromStr for PubSubBackend {
+    type Err = String;
+
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend {
+            "memory" => Ok(Self::Memory),
+            "mongodb" => Ok(Self::Mongodb),
+            _ => Err(format!(
+                "\"{backend}\" isn't a pub/sub backend, expected \"memory\" or \"mongodb\""
+            )),
+        }
+    }
+}
+
+/// The server configuration, it's loaded once at startup by [`Config::load`] and shared with the handlers as
+/// `web::Data<Config>`
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mode: AppMode,
+    /// see [`Config::bind_addr`]
+    pub host: Option<IpAddr>,
+    pub port: u16,
+    pub mongodb_uri: String,
+    /// see [`HashParams`]
+    pub hash_round: usize,
+    pub salt: String,
+    pub pubsub_backend: PubSubBackend,
+    /// http-date (e.g: "Sat, 01 Nov 2025 00:00:00 GMT") after which the unprefixed routes may be removed
+    pub unversioned_sunset: Option<String>,
+    /// applies the pending migrations at startup, otherwise the server refuses to start until `ilix_server migrate` ran
+    pub auto_migrate: bool,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_pool_name_len: usize,
+    pub max_device_name_len: usize,
+    /// how many events are kept per device for replay
+    pub event_log_capacity: usize,
+    /// the sse channel buffer size, without counting the replayed events
+    pub sse_channel_size: usize,
+    /// how many messages a slow in-process subscriber can lag behind before losing some
+    pub pubsub_capacity: usize,
+}
+
+/// in seconds in the config file and the env vars
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// how long an event is kept for replay
+    #[serde(deserialize_with = "secs")]
+    pub event_log_ttl: Duration,
+    /// how often the expired events are removed from the event log
+    #[serde(deserialize_with = "secs")]
+    pub event_log_cleanup: Duration,
+    /// a keep-alive comment is sent on idle sse streams so that proxies don't close them
+    #[serde(deserialize_with = "secs")]
+    pub sse_keep_alive: Duration,
+    /// delay before subscribing again to the pub/sub backend when its connection is lost
+    #[serde(deserialize_with = "secs")]
+    pub pubsub_retry_delay: Duration,
+    /// the published messages are only needed while the instances read them, mongodb removes them after this delay
+    #[serde(deserialize_with = "secs")]
+    pub pubsub_events_ttl: Duration,
+    /// how often the websockets liveness is checked
+    #[serde(deserialize_with = "secs")]
+    pub ws_heartbeat: Duration,
+    /// a websocket is closed if the client sent nothing (not even a heartbeat) for this long
+    #[serde(deserialize_with = "secs")]
+    pub ws_client_timeout: Duration,
+    /// on shutdown, how long the in-flight requests (e.g: uploads) have to finish, the unfinished uploads are rolled back
+    #[serde(deserialize_with = "secs")]
+    pub shutdown_deadline: Duration,
+    /// sent to the event streams clients on shutdown, how long they should wait before reconnecting
+    #[serde(deserialize_with = "secs")]
+    pub reconnect_delay: Duration,
+    /// `/readyz` reports the database (or the storage) down if it didn't answer by then
+    #[serde(deserialize_with = "secs")]
+    pub readiness_timeout: Duration,
+    /// how often the pool deletions (and leaves) that stopped midway are finished, once they're this old
+    #[serde(deserialize_with = "secs")]
+    pub cleanup_replay: Duration,
+    /// an upload still not attached to a transfer after this long is rolled back by the cleanups replay
+    #[serde(deserialize_with = "secs")]
+    pub max_upload_duration: Duration,
+    /// how often each instance stores that its connected devices are still online, for the other instances
+    #[serde(deserialize_with = "secs")]
+    pub presence_refresh: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: AppMode::Dev,
+            host: None,
+            port: 8080,
+            mongodb_uri: String::new(),
+            hash_round: 0,
+            salt: String::new(),
+            pubsub_backend: PubSubBackend::Memory,
+            unversioned_sunset: None,
+            auto_migrate: true,
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_pool_name_len: 50,
+            max_device_name_len: 50,
+            event_log_capacity: 100,
+            sse_channel_size: 10,
+            pubsub_capacity: 1024,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            event_log_ttl: Duration::from_secs(60 * 60),
+            event_log_cleanup: Duration::from_secs(30),
+            sse_keep_alive: Duration::from_secs(30),
+            pubsub_retry_delay: Duration::from_secs(1),
+            pubsub_events_ttl: Duration::from_secs(60),
+            ws_heartbeat: Duration::from_secs(15),
+            ws_client_timeout: Duration::from_secs(60),
+            shutdown_deadline: Duration::from_secs(30),
+            reconnect_delay: Duration::from_secs(5),
+            readiness_timeout: Duration::from_secs(2),
+            cleanup_replay: Duration::from_secs(60),
+            max_upload_duration: Duration::from_secs(60 * 60),
+            presence_refresh: Duration::from_secs(30),
+        }
+    }
+}
+
+fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+fn parse_secs(secs: &str) -> Result<Duration, std::num::ParseIntError> {
+    secs.parse().map(Duration::from_secs)
+}
+
+/// Why the server can't start with this config
+#[derive(Debug)]
+pub enum ConfigError {
+    /// the config file can't be read or isn't valid toml
+    File(PathBuf, String),
+    /// every invalid or missing value, not only the first one
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path, why) => write!(f, "invalid config file {}: {why}", path.display()),
+            Self::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// The config file (if any) is read first, then the env vars and the flags override it, and the result is validated
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        let mut problems = config.apply_env(|name| env::var(name).ok());
+        config.apply_cli(cli);
+        problems.extend(config.validate());
+
+        match problems.is_empty() {
+            true => Ok(config),
+            false => Err(ConfigError::Invalid(problems)),
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path)
+            .map_err(|err| ConfigError::File(path.to_path_buf(), err.to_string()))?;
+        toml::from_str(&raw).map_err(|err| ConfigError::File(path.to_path_buf(), err.to_string()))
+    }
+
+    /// overrides the fields whose env var is set, it returns the env vars that couldn't be parsed
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut problems = vec![];
+        macro_rules! from_env {
+            ($name:literal => $field:expr, $parse:expr) => {
+                if let Some(value) = var($name) {
+                    match $parse(value.as_str()) {
+                        Ok(parsed) => $field = parsed,
+                        Err(why) => problems.push(format!("{}: {why}", $name)),
+                    }
+                }
+            };
+            ($name:literal => $field:expr) => {
+                from_env!($name => $field, |value: &str| value.parse())
+            };
+        }
+        let string = |value: &str| Ok::<_, String>(value.to_string());
+
+        from_env!("APP_MODE" => self.mode);
+        from_env!("HOST" => self.host, |value: &str| value.parse().map(Some));
+        from_env!("PORT" => self.port);
+        from_env!("MONGODB_URI" => self.mongodb_uri, string);
+        from_env!("HASH_ROUND" => self.hash_round);
+        from_env!("SALT" => self.salt, string);
+        from_env!("PUBSUB_BACKEND" => self.pubsub_backend);
+        from_env!("UNVERSIONED_SUNSET" => self.unversioned_sunset, |value: &str| string(value).map(Some));
+        from_env!("AUTO_MIGRATE" => self.auto_migrate);
+
+        from_env!("MAX_POOL_NAME_LEN" => self.limits.max_pool_name_len);
+        from_env!("MAX_DEVICE_NAME_LEN" => self.limits.max_device_name_len);
+        from_env!("EVENT_LOG_CAPACITY" => self.limits.event_log_capacity);
+        from_env!("SSE_CHANNEL_SIZE" => self.limits.sse_channel_size);
+        from_env!("PUBSUB_CAPACITY" => self.limits.pubsub_capacity);
+
+        from_env!("EVENT_LOG_TTL" => self.timeouts.event_log_ttl, parse_secs);
+        from_env!("EVENT_LOG_CLEANUP" => self.timeouts.event_log_cleanup, parse_secs);
+        from_env!("SSE_KEEP_ALIVE" => self.timeouts.sse_keep_alive, parse_secs);
+        from_env!("PUBSUB_RETRY_DELAY" => self.timeouts.pubsub_retry_delay, parse_secs);
+        from_env!("PUBSUB_EVENTS_TTL" => self.timeouts.pubsub_events_ttl, parse_secs);
+        from_env!("WS_HEARTBEAT" => self.timeouts.ws_heartbeat, parse_secs);
+        from_env!("WS_CLIENT_TIMEOUT" => self.timeouts.ws_client_timeout, parse_secs);
+        from_env!("SHUTDOWN_DEADLINE" => self.timeouts.shutdown_deadline, parse_secs);
+        from_env!("RECONNECT_DELAY" => self.timeouts.reconnect_delay, parse_secs);
+        from_env!("READINESS_TIMEOUT" => self.timeouts.readiness_timeout, parse_secs);
+        from_env!("CLEANUP_REPLAY" => self.timeouts.cleanup_replay, parse_secs);
+        from_env!("MAX_UPLOAD_DURATION" => self.timeouts.max_upload_duration, parse_secs);
+        from_env!("PRESENCE_REFRESH" => self.timeouts.presence_refresh, parse_secs);
+
+        problems
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(mode) = cli.mode {
+            self.mode = mode;
+        }
+        if let Some(host) = cli.host {
+            self.host = Some(host);
+        }
+        if let Some(port) = cli.port {
+            self.port = port;
+        }
+    }
+
+    /// returns every problem of this config, it's valid if there is none
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.mongodb_uri.trim().is_empty() {
+            problems.push("MONGODB_URI (mongodb_uri) is missing".to_string());
+        }
+        if self.salt.is_empty() {
+            problems.push("SALT (salt) is missing".to_string());
+        }
+        if self.hash_round < MIN_HASH_ROUND {
+            problems.push(format!(
+                "HASH_ROUND (hash_round) must be at least {MIN_HASH_ROUND}, got {}",
+                self.hash_round
+            ));
+        }
+        if let Some(sunset) = &self.unversioned_sunset {
+            if HttpDate::from_str(sunset).is_err() {
+                problems.push(format!(
+                    "UNVERSIONED_SUNSET (unversioned_sunset) isn't an http-date: \"{sunset}\""
+                ));
+            }
+        }
+
+        let limits = [
+            ("MAX_POOL_NAME_LEN", self.limits.max_pool_name_len),
+            ("MAX_DEVICE_NAME_LEN", self.limits.max_device_name_len),
+            ("EVENT_LOG_CAPACITY", self.limits.event_log_capacity),
+            ("SSE_CHANNEL_SIZE", self.limits.sse_channel_size),
+            ("PUBSUB_CAPACITY", self.limits.pubsub_capacity),
+        ];
+        let timeouts = [
+            ("EVENT_LOG_TTL", self.timeouts.event_log_ttl),
+            ("EVENT_LOG_CLEANUP", self.timeouts.event_log_cleanup),
+            ("SSE_KEEP_ALIVE", self.timeouts.sse_keep_alive),
+            ("PUBSUB_RETRY_DELAY", self.timeouts.pubsub_retry_delay),
+            ("PUBSUB_EVENTS_TTL", self.timeouts.pubsub_events_ttl),
+            ("WS_HEARTBEAT", self.timeouts.ws_heartbeat),
+            ("WS_CLIENT_TIMEOUT", self.timeouts.ws_client_timeout),
+            ("SHUTDOWN_DEADLINE", self.timeouts.shutdown_deadline),
+            ("RECONNECT_DELAY", self.timeouts.reconnect_delay),
+            ("READINESS_TIMEOUT", self.timeouts.readiness_timeout),
+            ("CLEANUP_REPLAY", self.timeouts.cleanup_replay),
+            ("MAX_UPLOAD_DURATION", self.timeouts.max_upload_duration),
+            ("PRESENCE_REFRESH", self.timeouts.presence_refresh),
+        ];
+        for (name, _) in limits.iter().filter(|(_, limit)| *limit == 0) {
+            problems.push(format!("{name} ({}) can't be 0", name.to_lowercase()));
+        }
+        for (name, _) in timeouts.iter().filter(|(_, timeout)| timeout.is_zero()) {
+            problems.push(format!("{name} ({}) can't be 0", name.to_lowercase()));
+        }
+        if self.timeouts.ws_client_timeout <= self.timeouts.ws_heartbeat {
+            problems.push(
+                "WS_CLIENT_TIMEOUT (ws_client_timeout) must be longer than WS_HEARTBEAT (ws_heartbeat)"
+                    .to_string(),
+            );
+        }
+        problems
+    }
+
+    pub fn is_prod(&self) -> bool {
+        self.mode == AppMode::Prod
+    }
+
+    /// `host` if set, otherwise every interface in prod and only the loopback in dev
+    pub fn bind_addr(&self) -> (IpAddr, u16) {
+        let default_host = match self.is_prod() {
+            true => Ipv4Addr::UNSPECIFIED,
+            false => Ipv4Addr::LOCALHOST,
+        };
+        (self.host.unwrap_or(default_host.into()), self.port)
+    }
+
+    /// only call it on a validated config (see [`Config::load`])
+    pub fn hash_params(&self) -> HashParams {
+        HashParams::new(self.hash_round, &self.salt).expect("the config was validated")
+    }
+
+    pub fn unversioned_sunset(&self) -> Option<HttpDate> {
+        HttpDate::from_str(self.unversioned_sunset.as_deref()?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+    use super::{AppMode, Cli, Config, PubSubBackend};
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn config_layers_test() {
+        let mut config: Config = toml::from_str(
+            r#"
+            mode = "prod"
+            port = 3000
+            mongodb_uri = "mongodb://localhost:27017"
+            hash_round = 10
+            salt = "sasamiya"
+
+            [limits]
+            max_pool_name_len = 20
+
+            [timeouts]
+            ws_heartbeat = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.limits.max_pool_name_len, 20);
+        assert_eq!(config.limits.max_device_name_len, 50); // the missing keys get their default
+        assert_eq!(config.timeouts.ws_heartbeat, Duration::from_secs(5));
+        assert_eq!(config.bind_addr(), ("0.0.0.0".parse().unwrap(), 3000));
+
+        // the env vars override the file
+        let problems = config.apply_env(env(&[
+            ("PORT", "4000"),
+            ("PUBSUB_BACKEND", "mongodb"),
+            ("EVENT_LOG_TTL", "120"),
+            ("AUTO_MIGRATE", "false"),
+        ]));
+        assert!(problems.is_empty());
+        assert!(!config.auto_migrate);
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.pubsub_backend, PubSubBackend::Mongodb);
+        assert_eq!(config.timeouts.event_log_ttl, Duration::from_secs(120));
+
+        // and the flags override everything
+        config.apply_cli(&Cli {
+            mode: Some(AppMode::Dev),
+            host: Some("10.0.0.1".parse().unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(
+            config.bind_addr(),
+            ("10.0.0.1".parse::<IpAddr>().unwrap(), 4000)
+        );
+        assert!(config.validate().is_empty());
+        assert!(!config.is_prod());
+
+        // unknown keys are typos
+        assert!(toml::from_str::<Config>("hash_rounds = 10").is_err());
+    }
+
+    #[test]
+    fn config_validation_test() {
+        let mut config = Config::default();
+        let problems = config.apply_env(env(&[
+            ("APP_MODE", "staging"),
+            ("PORT", "http"),
+            ("HASH_ROUND", "3"),
+            ("UNVERSIONED_SUNSET", "tomorrow"),
+            ("SSE_CHANNEL_SIZE", "0"),
+            ("WS_CLIENT_TIMEOUT", "10"),
+        ]));
+        assert_eq!(problems.len(), 2, "{problems:?}"); // APP_MODE and PORT can't be parsed
+        assert!(problems[0].starts_with("APP_MODE"));
+
+        // every problem is reported at once
+        let problems = config.validate();
+        for expected in [
+            "MONGODB_URI",
+            "SALT",
+            "HASH_ROUND",
+            "UNVERSIONED_SUNSET",
+            "SSE_CHANNEL_SIZE",
+            "WS_CLIENT_TIMEOUT",
+        ] {
+            assert!(
+                problems.iter().any(|problem| problem.starts_with(expected)),
+                "{expected} should be invalid: {problems:?}"
+            );
+        }
+        assert_eq!(problems.len(), 6);
+    }
+}
@@ -0,0 +1,386 @@
+use std::{collections::HashSet, time::Duration};
+
+use actix_web::rt::time::interval;
+use futures_util::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime, Document};
+use serde::{Deserialize, Serialize};
+
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    models::DevicesPool,
+};
+
+use crate::utils::console_log;
+
+use super::{
+    membership::{PoolChange, PoolDocuments},
+    IlixDB, CLEANUPS_COLL, DB_NAME, DEVICES_POOL_COLL, FILE_TRANSFER_COLL, GRIDFS_CHUNKS_COLL,
+    GRIDFS_FILES_COLL,
+};
+
+/// A deletion in several steps, logged before it starts so that what a crash (or a failed step) left behind is
+/// deleted later by [`IlixDB::replay_cleanups`].
+///
+/// Each one pivots on a single update: before it nothing is deleted yet and the entry is just dropped, after it the
+/// rest must follow. The files can't be deleted in a transaction (gridfs) and the default
+/// deployment has no replica set anyway, so the rest is retried until it's done instead
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Cleanup {
+    /// the pool document is deleted, then its transfers and their files
+    DeletePool { hashed_kp: String },
+    /// the device is removed from the pool, then the transfers sent to it and their files. The pool is deleted too if
+    /// it's left empty
+    LeavePool {
+        hashed_kp: String,
+        device_id: String,
+    },
+    /// The files stored by an upload, tagged with `upload_id` in their metadata. The transfer update (that points to
+    /// them) is the pivot, but reversed: the files it points to are kept, the others deleted.
+    ///
+    /// Only replayed once the upload can't be running anymore (see `max_upload_duration`)
+    Upload { upload_id: ObjectId },
+}
+
+#[derive(Deserialize, Serialize)]
+struct CleanupEntry {
+    _id: ObjectId,
+    #[serde(flatten)]
+    cleanup: Cleanup,
+    started_at: DateTime,
+}
+
+impl Cleanup {
+    /// the transfers this cleanup deletes, once the pivot happened
+    fn transfers_filter(&self) -> Option<Document> {
+        match self {
+            Self::DeletePool { hashed_kp } => Some(doc! {"pool_hashed_key_phrase": hashed_kp}),
+            Self::LeavePool {
+                hashed_kp,
+                device_id,
+            } => Some(doc! {"pool_hashed_key_phrase": hashed_kp, "to": device_id}),
+            Self::Upload { .. } => None,
+        }
+    }
+
+    /// whether the pool update happened, given the pool as it is now
+    fn pivoted(&self, pool: Option<&DevicesPool>) -> bool {
+        match (self, pool) {
+            (Self::Upload { .. }, _) | (_, None) => true,
+            (Self::DeletePool { .. }, Some(_)) => false,
+            (Self::LeavePool { device_id, .. }, Some(pool)) => !pool.devices_id.contains(device_id),
+        }
+    }
+
+    fn hashed_kp(&self) -> Option<&str> {
+        match self {
+            Self::DeletePool { hashed_kp } | Self::LeavePool { hashed_kp, .. } => Some(hashed_kp),
+            Self::Upload { .. } => None,
+        }
+    }
+}
+
+/// the cleanups started before `started_before`, or before `uploads_started_before` for the uploads (they have to
+/// outlive the longest upload)
+fn due_filter(started_before: DateTime, uploads_started_before: DateTime) -> Document {
+    doc! {"$or": [
+        {"kind": {"$ne": "upload"}, "started_at": {"$lt": started_before}},
+        {"kind": "upload", "started_at": {"$lt": uploads_started_before}},
+    ]}
+}
+
+impl IlixDB {
+    /// logs the cleanup before its pivot, it returns the id to give to [`IlixDB::complete_cleanup`]
+    pub async fn log_cleanup(&self, cleanup: &Cleanup) -> Result<ObjectId, ServerError> {
+        let entry = CleanupEntry {
+            _id: ObjectId::new(),
+            cleanup: cleanup.clone(),
+            started_at: DateTime::now(),
+        };
+        self.client
+            .database(DB_NAME)
+            .collection::<CleanupEntry>(CLEANUPS_COLL)
+            .insert_one(&entry, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(entry._id)
+    }
+
+    /// the pivot didn't happen (e.g: the pool wasn't found), there is nothing to clean up
+    pub async fn drop_cleanup(&self, id: ObjectId) {
+        let _ = self
+            .client
+            .database(DB_NAME)
+            .collection::<Document>(CLEANUPS_COLL)
+            .delete_one(doc! {"_id": id}, None)
+            .await;
+    }
+
+    /// Deletes what's left once the pivot happened, it returns the ids of the deleted transfers.
+    ///
+    /// If it fails the entry is kept, the rest is deleted by the next replay: the pivot already happened so the
+    /// operation still succeeded
+    pub async fn complete_cleanup(&self, id: ObjectId, cleanup: &Cleanup) -> Vec<String> {
+        let Some(transfers_filter) = cleanup.transfers_filter() else {
+            self.drop_cleanup(id).await;
+            return vec![];
+        };
+        match self.purge_transfers(transfers_filter).await {
+            Ok((transfers_id, _)) => {
+                self.drop_cleanup(id).await;
+                transfers_id.into_iter().map(|id| id.to_hex()).collect()
+            }
+            Err(err) => {
+                console_log(
+                    &format!(
+                        "{cleanup:?} failed midway, it'll be replayed: {}",
+                        err.chain()
+                    ),
+                    log::Level::Warn,
+                );
+                vec![]
+            }
+        }
+    }
+
+    /// Finishes the cleanups started before `started_before` (`uploads_started_before` for the uploads) that are still
+    /// logged, the more recent ones may still be running. It returns how many were finished (or dropped, if their
+    /// pivot never happened)
+    pub async fn replay_cleanups(
+        &self,
+        started_before: DateTime,
+        uploads_started_before: DateTime,
+    ) -> Result<usize, ServerError> {
+        let db = self.client.database(DB_NAME);
+        let entries = db
+            .collection::<CleanupEntry>(CLEANUPS_COLL)
+            .find(due_filter(started_before, uploads_started_before), None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .server_err(ServerErrors::MongoError)?;
+
+        for entry in &entries {
+            let pool = match entry.cleanup.hashed_kp() {
+                Some(hashed_kp) => db
+                    .collection::<DevicesPool>(DEVICES_POOL_COLL)
+                    .find_one(doc! {"hashed_key_phrase": hashed_kp}, None)
+                    .await
+                    .server_err(ServerErrors::MongoError)?,
+                None => None,
+            };
+            if entry.cleanup.pivoted(pool.as_ref()) {
+                if let Some(transfers_filter) = entry.cleanup.transfers_filter() {
+                    self.purge_transfers(transfers_filter).await?;
+                }
+                match &entry.cleanup {
+                    // it stopped between the leave and the deletion of the empty pool
+                    Cleanup::LeavePool { hashed_kp, .. }
+                        if pool.is_some_and(|pool| pool.devices_id.is_empty()) =>
+                    {
+                        self.apply(hashed_kp, &PoolChange::DeleteIfEmpty).await?;
+                    }
+                    Cleanup::Upload { upload_id } => {
+                        self.delete_upload(*upload_id).await?;
+                    }
+                    _ => {}
+                }
+            }
+            self.drop_cleanup(entry._id).await;
+        }
+        Ok(entries.len())
+    }
+
+    /// Deletes the files stored by this upload that no transfer points to (thumbnails included), even the partially
+    /// stored ones. It returns how many were deleted
+    pub async fn delete_upload(&self, upload_id: ObjectId) -> Result<u64, ServerError> {
+        let db = self.client.database(DB_NAME);
+        // as documents, a partial file has no length nor upload date yet
+        let files = db
+            .collection::<Document>(GRIDFS_FILES_COLL)
+            .find(doc! {"metadata.upload_id": upload_id}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        if files.is_empty() {
+            return Ok(0);
+        }
+
+        let files_id = files
+            .iter()
+            .filter_map(|file| file.get_object_id("_id").ok())
+            .map(|id| id.to_hex())
+            .collect::<Vec<_>>();
+        let attached = db
+            .collection::<Document>(FILE_TRANSFER_COLL)
+            .distinct("files_id", doc! {"files_id": {"$in": &files_id}}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .into_iter()
+            .filter_map(|id| id.as_str().map(str::to_string))
+            .collect::<HashSet<_>>();
+        // the thumbnails of the attached files are kept with them
+        let kept = files
+            .iter()
+            .filter(|file| {
+                file.get_object_id("_id")
+                    .is_ok_and(|id| attached.contains(&id.to_hex()))
+            })
+            .filter_map(|file| {
+                file.get_document("metadata")
+                    .ok()?
+                    .get_str("thumbnail_id")
+                    .ok()
+            })
+            .chain(attached.iter().map(String::as_str))
+            .collect::<HashSet<_>>();
+        let deleted = files
+            .iter()
+            .filter_map(|file| file.get_object_id("_id").ok())
+            .filter(|id| !kept.contains(id.to_hex().as_str()))
+            .collect::<Vec<_>>();
+        if deleted.is_empty() {
+            return Ok(0);
+        }
+
+        // the chunks first, a files doc left behind is still found by the next replay
+        db.collection::<Document>(GRIDFS_CHUNKS_COLL)
+            .delete_many(doc! {"files_id": {"$in": &deleted}}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        db.collection::<Document>(GRIDFS_FILES_COLL)
+            .delete_many(doc! {"_id": {"$in": &deleted}}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(deleted.len() as u64)
+    }
+
+    /// [`IlixDB::delete_upload`], then drops its cleanup entry if it succeeded
+    pub async fn rollback_upload(
+        &self,
+        cleanup_id: ObjectId,
+        upload_id: ObjectId,
+    ) -> Result<u64, ServerError> {
+        let deleted = self.delete_upload(upload_id).await?;
+        self.drop_cleanup(cleanup_id).await;
+        Ok(deleted)
+    }
+
+    /// the cleanups that [`IlixDB::replay_cleanups`] would finish with these cutoffs, for `ilix_server gc --dry-run`
+    pub async fn pending_cleanups(
+        &self,
+        started_before: DateTime,
+        uploads_started_before: DateTime,
+    ) -> Result<Vec<Cleanup>, ServerError> {
+        let entries = self
+            .client
+            .database(DB_NAME)
+            .collection::<CleanupEntry>(CLEANUPS_COLL)
+            .find(due_filter(started_before, uploads_started_before), None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(entries.into_iter().map(|entry| entry.cleanup).collect())
+    }
+
+    /// replays, every `period`, the cleanups started more than `period` ago (`max_upload_duration` for the uploads)
+    pub fn spawn_cleanups_replay(&self, period: Duration, max_upload_duration: Duration) {
+        let db = self.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = interval(period);
+            loop {
+                interval.tick().await;
+                let now = DateTime::now().to_system_time();
+                let started_before = DateTime::from_system_time(now - period);
+                let uploads_started_before = DateTime::from_system_time(now - max_upload_duration);
+                match db
+                    .replay_cleanups(started_before, uploads_started_before)
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(replayed) => console_log(
+                        &format!("Replayed {replayed} unfinished deletion(s)"),
+                        log::Level::Warn,
+                    ),
+                    Err(err) => console_log(
+                        &format!("failed to replay the unfinished deletions: {}", err.chain()),
+                        log::Level::Error,
+                    ),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mongodb::bson::{doc, oid::ObjectId, DateTime};
+
+    use ilix_core::models::DevicesPool;
+
+    use super::{due_filter, Cleanup};
+
+    #[test]
+    fn cleanup_pivot_test() {
+        let pool = DevicesPool {
+            pool_name: "bl".to_string(),
+            devices_id: vec!["sasaki".to_string()],
+            devices_id_to_name: HashMap::from([(
+                "sasaki".to_string(),
+                "Sasaki's phone".to_string(),
+            )]),
+            devices_last_seen: HashMap::new(),
+            devices_online: vec![],
+            hashed_key_phrase: "hash".to_string(),
+            schema_version: DevicesPool::SCHEMA_VERSION,
+            created_at: 0,
+            version: 0,
+        };
+        let delete = Cleanup::DeletePool {
+            hashed_kp: "hash".to_string(),
+        };
+        let leave = |device_id: &str| Cleanup::LeavePool {
+            hashed_kp: "hash".to_string(),
+            device_id: device_id.to_string(),
+        };
+
+        // crashed before the pool update, nothing to delete
+        assert!(!delete.pivoted(Some(&pool)));
+        assert!(!leave("sasaki").pivoted(Some(&pool)));
+        // after it, the rest must be deleted
+        assert!(delete.pivoted(None));
+        assert!(leave("miyano").pivoted(Some(&pool)));
+        assert!(leave("sasaki").pivoted(None));
+        // whatever the pool, the files no transfer points to are deleted
+        let upload = Cleanup::Upload {
+            upload_id: ObjectId::new(),
+        };
+        assert!(upload.pivoted(Some(&pool)));
+        assert_eq!(upload.transfers_filter(), None);
+
+        // stored by kind, next to the entry fields
+        assert_eq!(
+            mongodb::bson::to_document(&leave("sasaki")).unwrap(),
+            doc! {"kind": "leave_pool", "hashed_kp": "hash", "device_id": "sasaki"}
+        );
+    }
+
+    #[test]
+    fn due_filter_test() {
+        let (before, uploads_before) = (DateTime::from_millis(2_000), DateTime::from_millis(1_000));
+        assert_eq!(
+            due_filter(before, uploads_before),
+            doc! {"$or": [
+                {"kind": {"$ne": "upload"}, "started_at": {"$lt": before}},
+                {"kind": "upload", "started_at": {"$lt": uploads_before}},
+            ]}
+        );
+    }
+}
@@ -1,54 +1,44 @@
-use crate::{
-    services::pool::NewPoolPayload,
-    utils::{
-        encryption::{decrypt_datas, encrypt_datas},
-        errors::ServerErrors,
-        keyphrase::{KeyPhrase, KEY_PHRASE_LEN},
-        TrimObjectId,
-    },
+use crate::utils::{
+    console_log,
+    metrics::METRICS,
+    mime::detect_mime_type,
+    thumbnail::{make_thumbnail, THUMBNAIL_MIME_TYPE},
+    TrimObjectId,
 };
 use anyhow::Result;
 use async_trait::async_trait;
 use futures_util::future;
+use ilix_core::{
+    compression::Codec,
+    encryption::{decrypt_datas, encrypt_datas},
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    keyphrase::{KeyPhrase, KEY_PHRASE_LEN},
+    models::{
+        DevicesPool, FileInfo, FileMetadata, FilePoolTransfer, FilePoolTransferExt, NewPoolPayload,
+    },
+    sha256,
+    storage::{DevicePoolsCollection, FilePoolTransferCollection, FileStorageGridFS, UploadedFile},
+};
 use mongodb::{
-    bson::{doc, oid::ObjectId},
+    bson::{doc, oid::ObjectId, DateTime},
     options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
-    Client, IndexModel,
+    IndexModel,
+};
+use mongodb_gridfs::{
+    options::{GridFSBucketOptions, GridFSUploadOptions},
+    GridFSBucket,
 };
-use mongodb_gridfs::{options::GridFSBucketOptions, GridFSBucket};
 use once_cell::sync::Lazy;
 use std::{collections::HashMap, str::FromStr};
 use tokio::task;
 use tokio_stream::StreamExt;
 
 use super::{
-    models::{DevicesPool, FileInfo, FilePoolTransfer, FilePoolTransferExt},
-    DB_NAME, DEVICES_POOL_COLL, FILE_TRANSFER_COLL, GRIDFS_BUCKET_NAME,
+    cleanups::Cleanup,
+    membership::{self, PoolChange, PoolDocuments},
+    IlixDB, DB_NAME, DEVICES_POOL_COLL, FILE_TRANSFER_COLL, GRIDFS_BUCKET_NAME, GRIDFS_FILES_COLL,
 };
 
-#[async_trait]
-pub trait DevicePoolsCollection {
-    async fn get_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerErrors>;
-    async fn create_pool(&self, args: NewPoolPayload) -> Result<String, ServerErrors>;
-    async fn join_pool(
-        &self,
-        key_phrase: &KeyPhrase,
-        device_id: &str,
-        device_name: &str,
-    ) -> Result<DevicesPool, ServerErrors>;
-    /// *this will also delete all the remaining user transfers and files*
-    ///
-    /// if nobody left in the pool, the pool is deleted
-    async fn leave_pool(
-        &self,
-        key_phrase: &KeyPhrase,
-        device_id: &str,
-    ) -> Result<DevicesPool, ServerErrors>;
-    /// deletes everything, the pool, all its corresponding transfers and files
-    async fn delete_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerErrors>;
-    async fn create_pool_hashed_kp_index(&self) -> Result<()>;
-}
-
 static KP_INDEX_MODEL_UNIQUE: Lazy<IndexModel> = Lazy::new(|| {
     let options = IndexOptions::builder().unique(true).build();
     IndexModel::builder()
@@ -65,15 +55,16 @@ static KP_INDEX_MODEL: Lazy<IndexModel> = Lazy::new(|| {
 });
 
 #[async_trait]
-impl DevicePoolsCollection for Client {
-    async fn get_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
+impl DevicePoolsCollection for IlixDB {
+    async fn get_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
         let mut device_pool = self
+            .client
             .database(DB_NAME)
             .collection::<DevicesPool>(DEVICES_POOL_COLL)
             .find_one(doc! {"hashed_key_phrase": hashed_kp}, None)
             .await
-            .map_err(|_| ServerErrors::MongoError)?
+            .server_err(ServerErrors::MongoError)?
             .ok_or(ServerErrors::PoolNotFound)?;
 
         // Security to not expose hashed_key_phrase
@@ -86,104 +77,70 @@ impl DevicePoolsCollection for Client {
         key_phrase: &KeyPhrase,
         device_id: &str,
         device_name: &str,
-    ) -> Result<DevicesPool, ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
-
-        let obj_entry = format!("devices_id_to_name.{device_id}");
-        let mut before_update = self
-            .database(DB_NAME)
-            .collection::<DevicesPool>(DEVICES_POOL_COLL)
-            .find_one_and_update(
-                doc! {"hashed_key_phrase": hashed_kp},
-                doc! {"$addToSet" : {"devices_id": device_id}, "$set": {obj_entry: device_name}},
-                Some(
-                    FindOneAndUpdateOptions::builder()
-                        .return_document(Some(ReturnDocument::Before))
-                        .build(),
-                ),
-            )
-            .await
-            .map_err(|_| ServerErrors::MongoError)?
-            .ok_or(ServerErrors::PoolNotFound)?;
-        if before_update.devices_id.contains(&device_id.to_string()) {
-            return Err(ServerErrors::AlreadyInPool);
-        }
-
-        before_update.devices_id.push(device_id.to_string());
-        before_update
-            .devices_id_to_name
-            .insert(device_id.to_string(), device_name.to_string());
+    ) -> Result<DevicesPool, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
+        let mut pool = membership::join(self, &hashed_kp, device_id, device_name).await?;
 
         // Security to not expose hashed_key_phrase
-        before_update.hashed_key_phrase = String::new();
+        pool.hashed_key_phrase = String::new();
 
-        Ok(before_update)
+        Ok(pool)
     }
 
     async fn leave_pool(
         &self,
         key_phrase: &KeyPhrase,
         device_id: &str,
-    ) -> Result<DevicesPool, ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
+    ) -> Result<(DevicesPool, Vec<String>), ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
 
-        // delete all transfers/files left
-        let transfers_left = self.find_transfers(key_phrase, device_id).await?;
-        let tasks = transfers_left.into_iter().map(|transfer| {
-            let (client, key_phrase) = (self.clone(), key_phrase.clone());
-            task::spawn(async move {
-                client
-                    .delete_transfer(&key_phrase, &transfer.to, &transfer._id)
-                    .await?;
-                client.delete_files(&transfer.files_id).await?;
-                Ok(())
-            })
-        });
-        for res in future::join_all(tasks).await {
-            res.map_err(|_| ServerErrors::MongoError)??;
-        }
+        // logged first, the transfers sent to the device must go once it left even if this stops midway
+        let cleanup = Cleanup::LeavePool {
+            hashed_kp: hashed_kp.clone(),
+            device_id: device_id.to_string(),
+        };
+        let cleanup_id = self.log_cleanup(&cleanup).await?;
+
+        // leave pool, and delete it if it was the last device
+        let mut pool = match membership::leave(self, &hashed_kp, device_id).await {
+            Ok(pool) => pool,
+            Err(err) => {
+                // on a database error the leave may have been applied, the replay checks
+                if !matches!(err.kind, ServerErrors::MongoError) {
+                    self.drop_cleanup(cleanup_id).await;
+                }
+                return Err(err);
+            }
+        };
 
-        // leave pool
-        let obj_entry = format!("devices_id_to_name.{device_id}");
-        let mut before_update = self
-            .database(DB_NAME)
-            .collection::<DevicesPool>(DEVICES_POOL_COLL)
-            .find_one_and_update(
-                doc! {"hashed_key_phrase": hashed_kp},
-                doc! {"$pull": {"devices_id": device_id}, "$unset": { obj_entry: "" } },
-                FindOneAndUpdateOptions::builder()
-                    .return_document(Some(ReturnDocument::Before))
-                    .build(),
-            )
-            .await
-            .map_err(|_| ServerErrors::MongoError)?
-            .ok_or(ServerErrors::PoolNotFound)?;
+        // delete all transfers/files left
+        let deleted_transfers_id = self.complete_cleanup(cleanup_id, &cleanup).await;
 
-        if !before_update.devices_id.contains(&device_id.to_string())
-            || !before_update
-                .devices_id_to_name
-                .contains_key(&device_id.to_string())
-        {
-            return Err(ServerErrors::NotInPool);
-        }
+        // Security to not expose hashed_key_phrase
+        pool.hashed_key_phrase = String::new();
 
-        // if before update, user count is equal to 1, after update the pool is empty, thus we delete it
-        if before_update.devices_id.len() == 1 {
-            let _ = self.delete_pool(key_phrase).await;
-        }
+        Ok((pool, deleted_transfers_id))
+    }
 
-        before_update.devices_id.retain(|id| id != device_id);
-        before_update.devices_id_to_name.remove(device_id);
+    async fn rename_pool(
+        &self,
+        key_phrase: &KeyPhrase,
+        pool_name: &str,
+        expected_version: Option<u64>,
+    ) -> Result<DevicesPool, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
+        let mut after_update =
+            membership::rename(self, &hashed_kp, pool_name, expected_version).await?;
 
         // Security to not expose hashed_key_phrase
-        before_update.hashed_key_phrase = String::new();
+        after_update.hashed_key_phrase = String::new();
 
-        Ok(before_update)
+        Ok(after_update)
     }
 
-    async fn create_pool(&self, args: NewPoolPayload) -> Result<String, ServerErrors> {
+    async fn create_pool(&self, args: NewPoolPayload) -> Result<String, ServerError> {
         let kp = KeyPhrase::new(KEY_PHRASE_LEN)?;
-        let hashed_kp = kp.hash()?;
+        let hashed_kp = kp.hash(&self.hash_params);
 
         let mut id_to_name = HashMap::new();
         id_to_name.insert(args.device_id.clone(), args.device_name);
@@ -192,52 +149,30 @@ impl DevicePoolsCollection for Client {
             pool_name: args.name,
             devices_id: vec![args.device_id],
             devices_id_to_name: id_to_name,
+            devices_last_seen: HashMap::new(),
+            devices_online: vec![],
             hashed_key_phrase: hashed_kp,
+            schema_version: DevicesPool::SCHEMA_VERSION,
+            created_at: DateTime::now().timestamp_millis(),
+            version: 0,
         };
 
-        self.database(DB_NAME)
+        self.client
+            .database(DB_NAME)
             .collection::<DevicesPool>(DEVICES_POOL_COLL)
             .insert_one(devices_pool, None)
             .await
-            .map_err(|_| ServerErrors::MongoError)?;
+            .server_err(ServerErrors::MongoError)?;
         Ok(kp.0)
     }
 
-    async fn delete_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerErrors> {
-        let pool = self.get_pool(key_phrase).await?;
-        let hashed_kp = key_phrase.hash()?;
-
-        let tasks = pool.devices_id.into_iter().map(|id| {
-            let (client, key_phrase) = (self.clone(), key_phrase.clone());
-            task::spawn(async move { client.find_transfers(&key_phrase, &id).await })
-        });
-
-        let mut transfers_to_delete = vec![];
-        for res in future::join_all(tasks).await {
-            let mut transfers = res.map_err(|_| ServerErrors::MongoError)??;
-            transfers_to_delete.append(&mut transfers);
-        }
-
-        let tasks = transfers_to_delete.into_iter().map(|transfer| {
-            let (client, key_phrase) = (self.clone(), key_phrase.clone());
-            task::spawn(async move {
-                client
-                    .delete_transfer(&key_phrase, &transfer.to, &transfer._id)
-                    .await?;
-                client.delete_files(&transfer.files_id).await?;
-                Ok(())
-            })
-        });
-        for res in future::join_all(tasks).await {
-            res.map_err(|_| ServerErrors::MongoError)??
-        }
+    async fn delete_pool(&self, key_phrase: &KeyPhrase) -> Result<DevicesPool, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
 
+        // the pool is deleted first, then its transfers and files (even if this stops midway, see `Cleanup`)
         let mut delete_report = self
-            .database(DB_NAME)
-            .collection::<DevicesPool>(DEVICES_POOL_COLL)
-            .find_one_and_delete(doc! {"hashed_key_phrase": hashed_kp }, None)
-            .await
-            .map_err(|_| ServerErrors::MongoError)?
+            .apply(&hashed_kp, &PoolChange::Delete)
+            .await?
             .ok_or(ServerErrors::PoolNotFound)?;
 
         // Security to not expose hashed_key_phrase
@@ -246,9 +181,32 @@ impl DevicePoolsCollection for Client {
         Ok(delete_report)
     }
 
+    /// Stores when the device was last seen in the pool, an older time than the stored one is ignored.
+    async fn update_last_seen(
+        &self,
+        hashed_key_phrase: &str,
+        device_id: &str,
+        last_seen: i64,
+    ) -> Result<(), ServerError> {
+        // a device that left the pool is not brought back
+        let last_seen_entry = format!("devices_last_seen.{device_id}");
+        self.client
+            .database(DB_NAME)
+            .collection::<DevicesPool>(DEVICES_POOL_COLL)
+            .update_one(
+                doc! {"hashed_key_phrase": hashed_key_phrase, "devices_id": device_id},
+                doc! {"$max": {last_seen_entry: last_seen}},
+                None,
+            )
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(())
+    }
+
     /// Creates an index on the "hashed_key_phrase" field to force the values to be unique.
     async fn create_pool_hashed_kp_index(&self) -> Result<()> {
-        self.database(DB_NAME)
+        self.client
+            .database(DB_NAME)
             .collection::<DevicesPool>(DEVICES_POOL_COLL)
             .create_index(KP_INDEX_MODEL_UNIQUE.to_owned(), None)
             .await?;
@@ -257,67 +215,27 @@ impl DevicePoolsCollection for Client {
 }
 
 #[async_trait]
-pub trait FilePoolTransferCollection {
+impl FilePoolTransferCollection for IlixDB {
     async fn find_transfers(
         &self,
         key_phrase: &KeyPhrase,
         device_id: &str,
-    ) -> Result<Vec<FilePoolTransferExt>, ServerErrors>;
-    /// this only creates the transfer in db, files must be added to the db before calling this,
-    /// files are mendatory to call this.
-    async fn create_transfer(
-        &self,
-        key_phrase: &KeyPhrase,
-        from: &str,
-        to: &str,
-        files_id: &[String],
-    ) -> Result<FilePoolTransferExt, ServerErrors>;
-    async fn add_files_to_transfer(
-        &self,
-        files_id: &[String],
-        transfer_id: &str,
-        key_phrase: &KeyPhrase,
-    ) -> Result<FilePoolTransferExt, ServerErrors>;
-    /// if no files left in the transfer, this'll remove the transfer
-    async fn remove_transfer_file(
-        &self,
-        file_id: &str,
-        key_phrase: &KeyPhrase,
-    ) -> Result<(), ServerErrors>;
-    /// **this only delete the transfer, not the files linked to it**,
-    /// it returns the transfer's files_ids
-    ///
-    /// *I know it's dumb, it's a development mistake, but I'm too lazy to changes it, because I don't want to break anything*
-    async fn delete_transfer(
-        &self,
-        key_phrase: &KeyPhrase,
-        device_id: &str,
-        transfer_id: &str,
-    ) -> Result<Vec<String>, ServerErrors>;
-    async fn create_transfer_hashed_kp_index(&self) -> Result<()>;
-}
-
-#[async_trait]
-impl FilePoolTransferCollection for Client {
-    async fn find_transfers(
-        &self,
-        key_phrase: &KeyPhrase,
-        device_id: &str,
-    ) -> Result<Vec<FilePoolTransferExt>, ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
+    ) -> Result<Vec<FilePoolTransferExt>, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
         let filter = doc! {"pool_hashed_key_phrase": hashed_kp, "to": device_id};
         let mut cursor = self
+            .client
             .database(DB_NAME)
             .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
             .find(filter, None)
             .await
-            .map_err(|_| ServerErrors::MongoError)?;
+            .server_err(ServerErrors::MongoError)?;
 
         let mut files_info = vec![];
         while let Some(file_info) = cursor
             .try_next()
             .await
-            .map_err(|_| ServerErrors::MongoError)?
+            .server_err(ServerErrors::MongoError)?
         {
             files_info.push(file_info);
         }
@@ -334,6 +252,7 @@ impl FilePoolTransferCollection for Client {
                 to: fi.to,
                 from: fi.from,
                 files_id: fi.files_id,
+                thumbnails_id: fi.thumbnails_id,
             })
             .collect::<Vec<_>>();
         Ok(files_info)
@@ -345,53 +264,71 @@ impl FilePoolTransferCollection for Client {
         from: &str,
         to: &str,
         files_id: &[String],
-    ) -> Result<FilePoolTransferExt, ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
+        thumbnails_id: &HashMap<String, String>,
+    ) -> Result<FilePoolTransferExt, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
         let data_to_insert = FilePoolTransfer {
             _id: ObjectId::new(), // no matter, this won't get serialized
             pool_hashed_key_phrase: hashed_kp,
             to: to.to_owned(),
             from: from.to_owned(),
             files_id: files_id.to_vec(),
+            thumbnails_id: thumbnails_id.clone(),
+            schema_version: FilePoolTransfer::SCHEMA_VERSION,
+            created_at: DateTime::now().timestamp_millis(),
         };
 
         let pool = self.get_pool(key_phrase).await?;
         if !pool.devices_id.contains(&from.to_string())
             || !pool.devices_id.contains(&to.to_string())
         {
-            return Err(ServerErrors::NotInPool);
+            return Err(ServerErrors::NotInPool.into());
         }
 
         let set_report = self
+            .client
             .database(DB_NAME)
             .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
             .insert_one(data_to_insert.clone(), None)
             .await
-            .map_err(|_| ServerErrors::MongoError)?;
+            .server_err(ServerErrors::MongoError)?;
         Ok(FilePoolTransferExt {
             _id: set_report.inserted_id.to_string().trim_object_id(),
             pool_hashed_key_phrase: data_to_insert.pool_hashed_key_phrase,
             to: data_to_insert.to,
             from: data_to_insert.from,
             files_id: data_to_insert.files_id,
+            thumbnails_id: data_to_insert.thumbnails_id,
         })
     }
 
     async fn add_files_to_transfer(
         &self,
         files_id: &[String],
+        thumbnails_id: &HashMap<String, String>,
         transfer_id: &str,
         key_phrase: &KeyPhrase,
-    ) -> Result<FilePoolTransferExt, ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
-
-        let id = ObjectId::from_str(transfer_id).map_err(|_| ServerErrors::InvalidObjectId)?;
+    ) -> Result<FilePoolTransferExt, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
+
+        let id = ObjectId::from_str(transfer_id).server_err(ServerErrors::InvalidObjectId)?;
+        let mut update = doc! {"$addToSet": {"files_id": {"$each": files_id }}};
+        if !thumbnails_id.is_empty() {
+            let thumbnails_entries = thumbnails_id
+                .iter()
+                .map(|(file_id, thumbnail_id)| {
+                    (format!("thumbnails_id.{file_id}"), thumbnail_id.into())
+                })
+                .collect::<mongodb::bson::Document>();
+            update.insert("$set", thumbnails_entries);
+        }
         let update_report = self
+            .client
             .database(DB_NAME)
             .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
             .find_one_and_update(
                 doc! {"_id": id, "pool_hashed_key_phrase": hashed_kp},
-                doc! {"$addToSet": {"files_id": {"$each": files_id }}},
+                update,
                 Some(
                     FindOneAndUpdateOptions::builder()
                         .return_document(Some(ReturnDocument::After))
@@ -399,14 +336,14 @@ impl FilePoolTransferCollection for Client {
                 ),
             )
             .await
-            .map_err(|_| ServerErrors::MongoError)?
+            .server_err(ServerErrors::MongoError)?
             .ok_or(ServerErrors::TransferNotFound)?;
 
         if !files_id
             .iter()
             .all(|id| update_report.files_id.contains(id))
         {
-            return Err(ServerErrors::MongoError);
+            return Err(ServerErrors::MongoError.into());
         }
 
         let updated_doc = FilePoolTransferExt {
@@ -415,6 +352,7 @@ impl FilePoolTransferCollection for Client {
             to: update_report.to,
             from: update_report.from,
             files_id: update_report.files_id,
+            thumbnails_id: update_report.thumbnails_id,
         };
 
         Ok(updated_doc)
@@ -424,14 +362,15 @@ impl FilePoolTransferCollection for Client {
         &self,
         file_id: &str,
         key_phrase: &KeyPhrase,
-    ) -> Result<(), ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
+    ) -> Result<FilePoolTransferExt, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
         let after_update = self
+            .client
             .database(DB_NAME)
             .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
             .find_one_and_update(
                 doc! {"pool_hashed_key_phrase": hashed_kp, "files_id": file_id},
-                doc! {"$pull" : {"files_id": file_id}},
+                doc! {"$pull" : {"files_id": file_id}, "$unset": {format!("thumbnails_id.{file_id}"): ""}},
                 Some(
                     FindOneAndUpdateOptions::builder()
                         .return_document(Some(ReturnDocument::After))
@@ -439,20 +378,25 @@ impl FilePoolTransferCollection for Client {
                 ),
             )
             .await
-            .map_err(|_| ServerErrors::MongoError)?
+            .server_err(ServerErrors::MongoError)?
             .ok_or(ServerErrors::TransferNotFound)?;
 
         if after_update.files_id.contains(&file_id.to_string()) {
-            return Err(ServerErrors::NotInTransfer);
+            return Err(ServerErrors::NotInTransfer.into());
         }
         if after_update.files_id.is_empty() {
-            return self
-                .delete_transfer(key_phrase, &after_update.to, &after_update._id.to_string())
-                .await
-                .map(|_| ());
+            self.delete_transfer(key_phrase, &after_update.to, &after_update._id.to_string())
+                .await?;
         }
 
-        Ok(())
+        Ok(FilePoolTransferExt {
+            _id: after_update._id.to_string(),
+            pool_hashed_key_phrase: String::new(), // to prevent leaks
+            to: after_update.to,
+            from: after_update.from,
+            files_id: after_update.files_id,
+            thumbnails_id: after_update.thumbnails_id,
+        })
     }
 
     async fn delete_transfer(
@@ -460,16 +404,17 @@ impl FilePoolTransferCollection for Client {
         key_phrase: &KeyPhrase,
         to_device_id: &str,
         transfer_id: &str,
-    ) -> Result<Vec<String>, ServerErrors> {
-        let hashed_kp = key_phrase.hash()?;
-        let id = ObjectId::from_str(transfer_id).map_err(|_| ServerErrors::InvalidObjectId)?;
+    ) -> Result<Vec<String>, ServerError> {
+        let hashed_kp = key_phrase.hash(&self.hash_params);
+        let id = ObjectId::from_str(transfer_id).server_err(ServerErrors::InvalidObjectId)?;
         let filter = doc! {"pool_hashed_key_phrase": hashed_kp, "to": to_device_id, "_id": id };
         let find_report = self
+            .client
             .database(DB_NAME)
             .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
             .find_one_and_delete(filter, None)
             .await
-            .map_err(|_| ServerErrors::MongoError)?
+            .server_err(ServerErrors::MongoError)?
             .ok_or(ServerErrors::TransferNotFound)?;
 
         Ok(find_report.files_id)
@@ -477,7 +422,8 @@ impl FilePoolTransferCollection for Client {
 
     /// Creates an index on the "hashed_key_phrase" field to force the values to be unique.
     async fn create_transfer_hashed_kp_index(&self) -> Result<()> {
-        self.database(DB_NAME)
+        self.client
+            .database(DB_NAME)
             .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
             .create_index(KP_INDEX_MODEL.to_owned(), None)
             .await?;
@@ -485,51 +431,81 @@ impl FilePoolTransferCollection for Client {
     }
 }
 
-#[async_trait]
-pub trait FileStorageGridFS {
-    async fn get_files_info(&self, files_ids: &[String]) -> Result<Vec<FileInfo>, ServerErrors>;
-    /// decrypts and download file from db
-    async fn get_file(
-        &self,
-        file_id: &str,
-        key_phrase: &KeyPhrase,
-    ) -> Result<(String, Vec<u8>), ServerErrors>;
-    /// encrypts and add files to db
-    async fn add_files(
-        &self,
-        files_datas: Vec<(String, Vec<u8>)>,
-        key_phrase: &KeyPhrase,
-    ) -> Result<Vec<String>, ServerErrors>;
-    async fn delete_files(&self, files_ids: &[String]) -> Result<(), ServerErrors>;
-}
-
 static BUCKET_OPTIONS: Lazy<GridFSBucketOptions> = Lazy::new(|| {
     GridFSBucketOptions::builder()
         .bucket_name(GRIDFS_BUCKET_NAME.to_string())
         .build()
 });
 
+/// helper to upload an already encrypted file with its metadata, tagged with the upload that stores it
+async fn upload_file(
+    mut bucket: GridFSBucket,
+    filename: &str,
+    enc_buf: &[u8],
+    metadata: &FileMetadata,
+    upload_id: ObjectId,
+) -> Result<ObjectId, ServerError> {
+    let mut metadata = mongodb::bson::to_document(metadata).server_err(ServerErrors::ParseError)?;
+    // the files doc is written before the chunks, so even a partial file can be found by its upload
+    metadata.insert("upload_id", upload_id);
+    let options = GridFSUploadOptions::builder()
+        .metadata(Some(metadata))
+        .build();
+    bucket
+        .upload_from_stream(filename, enc_buf, Some(options))
+        .await
+        .server_err(ServerErrors::MongoError)
+}
+
 #[async_trait]
-impl FileStorageGridFS for Client {
-    async fn get_files_info(&self, files_ids: &[String]) -> Result<Vec<FileInfo>, ServerErrors> {
+impl FileStorageGridFS for IlixDB {
+    async fn get_files_info(
+        &self,
+        files_ids: &[String],
+        key_phrase: &KeyPhrase,
+    ) -> Result<Vec<FileInfo>, ServerError> {
+        // only the files sent in the pool's transfers, the files of another pool aren't found
+        let hashed_kp = key_phrase.hash(&self.hash_params);
+        let pool_files: Vec<String> = self
+            .client
+            .database(DB_NAME)
+            .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
+            .distinct(
+                "files_id",
+                doc! {"pool_hashed_key_phrase": hashed_kp, "files_id": {"$in": files_ids}},
+                None,
+            )
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .into_iter()
+            .filter_map(|file_id| file_id.as_str().map(str::to_string))
+            .collect();
+        if files_ids
+            .iter()
+            .any(|file_id| !pool_files.contains(file_id))
+        {
+            return Err(ServerErrors::FileNotFound.into());
+        }
+
         let tasks = files_ids.iter().cloned().map(|file_id| {
-            let client = self.clone();
+            let db = self.clone();
             task::spawn(async move {
-                let id = ObjectId::from_str(&file_id).map_err(|_| ServerErrors::InvalidObjectId)?;
-                client
+                let id = ObjectId::from_str(&file_id).server_err(ServerErrors::InvalidObjectId)?;
+                db.client
                     .database(DB_NAME)
-                    .collection::<FileInfo>("ilix_fs.files")
+                    .collection::<FileInfo>(GRIDFS_FILES_COLL)
                     .find_one(doc! {"_id": id}, None)
                     .await
-                    .map_err(|_| ServerErrors::MongoError)
+                    .server_err(ServerErrors::MongoError)
             })
         });
 
         let mut files_info = vec![];
         for res in future::join_all(tasks).await {
-            let file_info = res
-                .map_err(|_| ServerErrors::MongoError)??
+            let mut file_info: FileInfo = res
+                .server_err(ServerErrors::MongoError)??
                 .ok_or(ServerErrors::FileNotFound)?;
+            file_info.metadata.open(key_phrase)?;
             files_info.push(file_info);
         }
 
@@ -540,78 +516,235 @@ impl FileStorageGridFS for Client {
         &self,
         file_id: &str,
         key_phrase: &KeyPhrase,
-    ) -> Result<(String, Vec<u8>), ServerErrors> {
-        let bucket = GridFSBucket::new(self.database(DB_NAME), Some(BUCKET_OPTIONS.to_owned()));
+    ) -> Result<(FileInfo, Vec<u8>), ServerError> {
+        let bucket = GridFSBucket::new(
+            self.client.database(DB_NAME),
+            Some(BUCKET_OPTIONS.to_owned()),
+        );
+
+        let id = ObjectId::from_str(file_id).server_err(ServerErrors::InvalidObjectId)?;
+        let cursor = bucket
+            .open_download_stream(id)
+            .await
+            .server_err(ServerErrors::MongoError)?;
 
-        let id = ObjectId::from_str(file_id).map_err(|_| ServerErrors::InvalidObjectId)?;
-        let (cursor, filename) = bucket
-            .open_download_stream_with_filename(id)
+        let file_info = self
+            .client
+            .database(DB_NAME)
+            .collection::<FileInfo>(GRIDFS_FILES_COLL)
+            .find_one(doc! {"_id": id}, None)
             .await
-            .map_err(|_| ServerErrors::MongoError)?;
+            .server_err(ServerErrors::MongoError)?
+            .ok_or(ServerErrors::FileNotFound)?;
 
         let enc_file_buffer = cursor.collect::<Vec<_>>().await.concat();
-        let decrypted_datas = decrypt_datas(&key_phrase.0, &enc_file_buffer)?;
-        Ok((filename, decrypted_datas))
+        // cpu-bound, kept off the async runtime threads
+        let (key_phrase, codec) = (key_phrase.clone(), file_info.metadata.codec);
+        let datas = task::spawn_blocking(move || {
+            let decrypted_datas = METRICS
+                .time_crypto("decrypt", || decrypt_datas(&key_phrase.0, &enc_file_buffer))?;
+            codec.decompress(&decrypted_datas)
+        })
+        .await
+        .server_err(ServerErrors::MongoError)??;
+        Ok((file_info, datas))
+    }
+
+    async fn get_thumbnail(
+        &self,
+        file_id: &str,
+        key_phrase: &KeyPhrase,
+    ) -> Result<Vec<u8>, ServerError> {
+        let id = ObjectId::from_str(file_id).server_err(ServerErrors::InvalidObjectId)?;
+        let thumbnail_id = self
+            .client
+            .database(DB_NAME)
+            .collection::<FileInfo>(GRIDFS_FILES_COLL)
+            .find_one(doc! {"_id": id}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .ok_or(ServerErrors::FileNotFound)?
+            .metadata
+            .thumbnail_id
+            .ok_or(ServerErrors::ThumbnailNotFound)?;
+
+        self.get_file(&thumbnail_id, key_phrase)
+            .await
+            .map(|(_, thumbnail)| thumbnail)
     }
 
     async fn add_files(
         &self,
-        files_datas: Vec<(String, Vec<u8>)>,
+        files: Vec<UploadedFile>,
         key_phrase: &KeyPhrase,
-    ) -> Result<Vec<String>, ServerErrors> {
-        // encrypt files
-        let tasks = files_datas.into_iter().map(|(filename, file_buffer)| {
-            let key_phrase: KeyPhrase = key_phrase.clone();
-            task::spawn(async move {
-                let enc_buf = encrypt_datas(&key_phrase.0, &file_buffer)?;
-                Ok((filename, enc_buf))
-            })
-        });
-        let mut enc_files = vec![];
-        for res in future::join_all(tasks).await {
-            let enc_file = res.map_err(|_| ServerErrors::MongoError)??;
-            enc_files.push(enc_file);
+    ) -> Result<(Vec<String>, HashMap<String, String>), ServerError> {
+        self.upload_files(files, key_phrase, ObjectId::new()).await
+    }
+
+    async fn delete_files(&self, files_ids: &[String]) -> Result<(), ServerError> {
+        let mut ids = files_ids
+            .iter()
+            .map(|file_id| ObjectId::from_str(file_id).server_err(ServerErrors::InvalidObjectId))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // thumbnails are deleted along with their file
+        let mut cursor = self
+            .client
+            .database(DB_NAME)
+            .collection::<FileInfo>(GRIDFS_FILES_COLL)
+            .find(
+                doc! {"_id": {"$in": &ids}, "metadata.thumbnail_id": {"$exists": true}},
+                None,
+            )
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        while let Some(file_info) = cursor
+            .try_next()
+            .await
+            .server_err(ServerErrors::MongoError)?
+        {
+            if let Some(Ok(thumbnail_id)) = file_info
+                .metadata
+                .thumbnail_id
+                .map(|id| ObjectId::from_str(&id))
+            {
+                ids.push(thumbnail_id);
+            }
         }
 
-        // add files
-        let bucket = GridFSBucket::new(self.database(DB_NAME), Some(BUCKET_OPTIONS.to_owned()));
+        let bucket = GridFSBucket::new(
+            self.client.database(DB_NAME),
+            Some(BUCKET_OPTIONS.to_owned()),
+        );
 
-        let tasks = enc_files.into_iter().map(|(filename, enc_buf)| {
-            let mut bucket = bucket.clone();
-            task::spawn(async move {
-                bucket
-                    .upload_from_stream(&filename, &enc_buf[..], None)
-                    .await
-                    .map_err(|_| ServerErrors::MongoError)
-            })
+        let tasks = ids.into_iter().map(|id| {
+            let bucket = bucket.clone();
+            task::spawn(async move { bucket.delete(id).await.server_err(ServerErrors::MongoError) })
         });
 
-        let mut files_ids = vec![];
         for res in future::join_all(tasks).await {
-            let id = res.map_err(|_| ServerErrors::MongoError)??;
-            files_ids.push(id.to_string())
+            res.server_err(ServerErrors::MongoError)??;
         }
-
-        Ok(files_ids)
+        Ok(())
     }
+}
 
-    async fn delete_files(&self, files_ids: &[String]) -> Result<(), ServerErrors> {
-        let bucket = GridFSBucket::new(self.database(DB_NAME), Some(BUCKET_OPTIONS.to_owned()));
-
-        let tasks = files_ids.iter().cloned().map(|file_id| {
-            let bucket = bucket.clone();
-            task::spawn(async move {
-                let id = ObjectId::from_str(&file_id).map_err(|_| ServerErrors::InvalidObjectId)?;
-                bucket
-                    .delete(id)
-                    .await
-                    .map_err(|_| ServerErrors::MongoError)
+impl IlixDB {
+    /// [`FileStorageGridFS::add_files`], the files (thumbnails included) are tagged with `upload_id` so that they can
+    /// be deleted even if this stops midway (see [`Cleanup::Upload`])
+    pub async fn upload_files(
+        &self,
+        files: Vec<UploadedFile>,
+        key_phrase: &KeyPhrase,
+        upload_id: ObjectId,
+    ) -> Result<(Vec<String>, HashMap<String, String>), ServerError> {
+        // compress and encrypt files (and their thumbnail), cpu-bound so kept off the async runtime threads
+        let tasks = files.into_iter().map(|file| {
+            let key_phrase: KeyPhrase = key_phrase.clone();
+            task::spawn_blocking(move || {
+                let mime_type = detect_mime_type(&file.datas, file.content_type.as_deref());
+                let enc_thumbnail = match make_thumbnail(&file.datas, &mime_type) {
+                    Some(Ok(thumbnail)) => Some(
+                        METRICS
+                            .time_crypto("encrypt", || encrypt_datas(&key_phrase.0, &thumbnail))?,
+                    ),
+                    Some(Err(_)) => {
+                        // the file is still sent, just without preview
+                        console_log(
+                            &format!("failed to make the thumbnail of '{}'", file.filename),
+                            log::Level::Warn,
+                        );
+                        None
+                    }
+                    None => None,
+                };
+
+                let codec = Codec::pick(&file.filename, &mime_type, &file.datas);
+                let mut metadata = FileMetadata {
+                    codec,
+                    size: Some(file.datas.len()),
+                    sha256: Some(sha256(&file.datas)),
+                    mime_type: Some(mime_type),
+                    client: file.client_metadata,
+                    ..Default::default()
+                };
+                metadata.seal(&key_phrase)?;
+
+                let compressed_buf = codec.compress(&file.datas)?;
+                let enc_buf = METRICS
+                    .time_crypto("encrypt", || encrypt_datas(&key_phrase.0, &compressed_buf))?;
+                Ok::<_, ServerError>((file.filename, enc_buf, metadata, enc_thumbnail))
             })
         });
+        let mut enc_files = vec![];
+        for res in future::join_all(tasks).await {
+            let enc_file = res.server_err(ServerErrors::MongoError)??;
+            enc_files.push(enc_file);
+        }
 
+        // add files
+        let bucket = GridFSBucket::new(
+            self.client.database(DB_NAME),
+            Some(BUCKET_OPTIONS.to_owned()),
+        );
+
+        let tasks =
+            enc_files
+                .into_iter()
+                .map(|(filename, enc_buf, mut metadata, enc_thumbnail)| {
+                    let bucket = bucket.clone();
+                    task::spawn(async move {
+                        // the thumbnail is uploaded first, so that the file can point to it
+                        if let Some(enc_thumbnail) = enc_thumbnail {
+                            let thumbnail_metadata = FileMetadata {
+                                mime_type: Some(THUMBNAIL_MIME_TYPE.to_string()),
+                                is_thumbnail: true,
+                                ..Default::default()
+                            };
+                            let thumbnail_id = upload_file(
+                                bucket.clone(),
+                                &format!("thumbnail-{filename}.jpg"),
+                                &enc_thumbnail,
+                                &thumbnail_metadata,
+                                upload_id,
+                            )
+                            .await?;
+                            metadata.thumbnail_id = Some(thumbnail_id.to_string());
+                        }
+
+                        let id =
+                            upload_file(bucket, &filename, &enc_buf, &metadata, upload_id).await?;
+                        Ok((id.to_string(), metadata.thumbnail_id))
+                    })
+                });
+
+        // every upload runs to the end, so that if one failed the others can be deleted
+        let (mut files_ids, mut thumbnails_ids, mut failed) = (vec![], HashMap::new(), None);
         for res in future::join_all(tasks).await {
-            res.map_err(|_| ServerErrors::MongoError)??;
+            match res.server_err(ServerErrors::MongoError).and_then(|res| res) {
+                Ok((id, thumbnail_id)) => {
+                    if let Some(thumbnail_id) = thumbnail_id {
+                        thumbnails_ids.insert(id.clone(), thumbnail_id);
+                    }
+                    files_ids.push(id)
+                }
+                Err(err) => failed = failed.or(Some(err)),
+            }
         }
-        Ok(())
+
+        // the files of the failed ones too, they may be partially stored
+        if let Some(err) = failed {
+            if let Err(cleanup_err) = self.delete_upload(upload_id).await {
+                console_log(
+                    &format!(
+                        "failed to delete the files of a failed upload, `ilix_server gc` will: {}",
+                        cleanup_err.chain()
+                    ),
+                    log::Level::Warn,
+                );
+            }
+            return Err(err);
+        }
+        Ok((files_ids, thumbnails_ids))
     }
 }
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+
+use futures_util::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime, Document};
+
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    models::{DevicesPool, FileInfo, FilePoolTransfer},
+    storage::FileStorageGridFS,
+};
+
+use super::{IlixDB, DB_NAME, DEVICES_POOL_COLL, FILE_TRANSFER_COLL, GRIDFS_FILES_COLL};
+
+/// What is stored, see `ilix_server stats`
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub pools: u64,
+    pub devices: u64,
+    pub transfers: u64,
+    pub files: u64,
+    pub thumbnails: u64,
+    /// once compressed and encrypted, thumbnails included
+    pub stored_bytes: u64,
+}
+
+/// What nothing points to anymore, see `ilix_server gc`
+#[derive(Debug, Default)]
+pub struct Orphans {
+    /// the transfers whose pool was deleted
+    pub transfers: Vec<FilePoolTransfer>,
+    /// the files (and thumbnails) of no transfer, including the ones of the orphaned transfers
+    pub files: Vec<FileInfo>,
+}
+
+/// What was deleted with the pool, see `ilix_server purge-pool`
+#[derive(Debug, Default)]
+pub struct PurgedPool {
+    pub pool: Option<DevicesPool>,
+    pub transfers: u64,
+    /// thumbnails aren't counted, they're deleted with their file
+    pub files: u64,
+}
+
+/// The maintenance queries of the admin commands, they scan whole collections so they aren't meant for the handlers
+impl IlixDB {
+    pub async fn stats(&self) -> Result<Stats, ServerError> {
+        let db = self.client.database(DB_NAME);
+        let mut stats = Stats {
+            transfers: db
+                .collection::<Document>(FILE_TRANSFER_COLL)
+                .count_documents(None, None)
+                .await
+                .server_err(ServerErrors::MongoError)?,
+            ..Default::default()
+        };
+
+        let mut pools = db
+            .collection::<DevicesPool>(DEVICES_POOL_COLL)
+            .find(None, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        while let Some(pool) = pools
+            .try_next()
+            .await
+            .server_err(ServerErrors::MongoError)?
+        {
+            stats.pools += 1;
+            stats.devices += pool.devices_id.len() as u64;
+        }
+
+        for file in self.all_files().await? {
+            match file.metadata.is_thumbnail {
+                true => stats.thumbnails += 1,
+                false => stats.files += 1,
+            }
+            stats.stored_bytes += file.length as u64;
+        }
+        Ok(stats)
+    }
+
+    /// The transfers whose pool is gone, and the files no transfer points to.
+    ///
+    /// The files uploaded after `uploaded_before` are skipped, they may belong to an upload in progress (the files are
+    /// stored before their transfer)
+    pub async fn find_orphans(&self, uploaded_before: DateTime) -> Result<Orphans, ServerError> {
+        let db = self.client.database(DB_NAME);
+        let pools = db
+            .collection::<DevicesPool>(DEVICES_POOL_COLL)
+            .distinct("hashed_key_phrase", None, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .into_iter()
+            .filter_map(|hashed_kp| hashed_kp.as_str().map(str::to_string))
+            .collect::<HashSet<_>>();
+
+        let (live, orphaned): (Vec<_>, Vec<_>) = db
+            .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
+            .find(None, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .into_iter()
+            .partition(|transfer| pools.contains(&transfer.pool_hashed_key_phrase));
+
+        let transfers_files = |transfers: &[FilePoolTransfer]| {
+            transfers
+                .iter()
+                .flat_map(|transfer| {
+                    transfer
+                        .files_id
+                        .iter()
+                        .chain(transfer.thumbnails_id.values())
+                        .cloned()
+                })
+                .collect::<HashSet<_>>()
+        };
+        let mut referenced = transfers_files(&live);
+        let of_orphaned_transfers = transfers_files(&orphaned);
+
+        let files = self.all_files().await?;
+        // the thumbnails of the files uploaded before `thumbnails_id` existed are only pointed to by their file
+        for file in &files {
+            if referenced.contains(&file._id.to_hex()) {
+                if let Some(thumbnail_id) = &file.metadata.thumbnail_id {
+                    referenced.insert(thumbnail_id.clone());
+                }
+            }
+        }
+
+        let files = files
+            .into_iter()
+            .filter(|file| {
+                let id = file._id.to_hex();
+                !referenced.contains(&id)
+                    && (file.uploadDate < uploaded_before || of_orphaned_transfers.contains(&id))
+            })
+            .collect();
+        Ok(Orphans {
+            transfers: orphaned,
+            files,
+        })
+    }
+
+    pub async fn delete_orphans(&self, orphans: &Orphans) -> Result<(), ServerError> {
+        let transfers_id = orphans
+            .transfers
+            .iter()
+            .map(|transfer| transfer._id)
+            .collect::<Vec<_>>();
+        if !transfers_id.is_empty() {
+            self.client
+                .database(DB_NAME)
+                .collection::<Document>(FILE_TRANSFER_COLL)
+                .delete_many(doc! {"_id": {"$in": transfers_id}}, None)
+                .await
+                .server_err(ServerErrors::MongoError)?;
+        }
+
+        let files_id = orphans
+            .files
+            .iter()
+            .map(|file| file._id.to_hex())
+            .collect::<Vec<_>>();
+        self.delete_files(&files_id).await
+    }
+
+    /// deletes the pool, its transfers and their files. It works even if the pool itself is already gone
+    pub async fn purge_pool(&self, hashed_kp: &str) -> Result<PurgedPool, ServerError> {
+        let (transfers_id, files) = self
+            .purge_transfers(doc! {"pool_hashed_key_phrase": hashed_kp})
+            .await?;
+        let pool = self
+            .client
+            .database(DB_NAME)
+            .collection::<DevicesPool>(DEVICES_POOL_COLL)
+            .find_one_and_delete(doc! {"hashed_key_phrase": hashed_kp}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+
+        Ok(PurgedPool {
+            pool,
+            transfers: transfers_id.len() as u64,
+            files,
+        })
+    }
+
+    /// Deletes the transfers matching `filter` and their files, it returns the ids of the deleted transfers and how
+    /// many files were deleted.
+    ///
+    /// The files are deleted first so that if it fails midway, the transfers still point to what's left and it can be
+    /// run again. So only the files still stored are deleted
+    pub async fn purge_transfers(
+        &self,
+        filter: Document,
+    ) -> Result<(Vec<ObjectId>, u64), ServerError> {
+        let db = self.client.database(DB_NAME);
+        let transfers = db
+            .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
+            .find(filter, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .try_collect::<Vec<_>>()
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        if transfers.is_empty() {
+            return Ok((vec![], 0));
+        }
+
+        let files_id = transfers
+            .iter()
+            .flat_map(|transfer| transfer.files_id.iter())
+            .filter_map(|file_id| file_id.parse::<ObjectId>().ok())
+            .collect::<Vec<_>>();
+        let files_id = db
+            .collection::<FileInfo>(GRIDFS_FILES_COLL)
+            .distinct("_id", doc! {"_id": {"$in": files_id}}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .into_iter()
+            .filter_map(|id| id.as_object_id().map(|id| id.to_hex()))
+            .collect::<Vec<_>>();
+        self.delete_files(&files_id).await?;
+
+        // by id, the transfers created in the meantime aren't touched since their files weren't deleted
+        let transfers_id = transfers
+            .iter()
+            .map(|transfer| transfer._id)
+            .collect::<Vec<_>>();
+        db.collection::<Document>(FILE_TRANSFER_COLL)
+            .delete_many(doc! {"_id": {"$in": &transfers_id}}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok((transfers_id, files_id.len() as u64))
+    }
+
+    /// the transfers of a pool, by its hashed key phrase
+    pub async fn pool_transfers(
+        &self,
+        hashed_kp: &str,
+    ) -> Result<Vec<FilePoolTransfer>, ServerError> {
+        self.client
+            .database(DB_NAME)
+            .collection::<FilePoolTransfer>(FILE_TRANSFER_COLL)
+            .find(doc! {"pool_hashed_key_phrase": hashed_kp}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .try_collect()
+            .await
+            .server_err(ServerErrors::MongoError)
+    }
+
+    pub async fn file_info(&self, file_id: &str) -> Result<Option<FileInfo>, ServerError> {
+        let id = file_id
+            .parse::<ObjectId>()
+            .server_err(ServerErrors::InvalidObjectId)?;
+        self.client
+            .database(DB_NAME)
+            .collection::<FileInfo>(GRIDFS_FILES_COLL)
+            .find_one(doc! {"_id": id}, None)
+            .await
+            .server_err(ServerErrors::MongoError)
+    }
+
+    /// the info of every stored file, thumbnails included
+    async fn all_files(&self) -> Result<Vec<FileInfo>, ServerError> {
+        self.client
+            .database(DB_NAME)
+            .collection::<FileInfo>(GRIDFS_FILES_COLL)
+            .find(None, None)
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .try_collect()
+            .await
+            .server_err(ServerErrors::MongoError)
+    }
+}
@@ -0,0 +1,504 @@
+use async_trait::async_trait;
+use mongodb::{
+    bson::{doc, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    models::DevicesPool,
+};
+
+use super::{cleanups::Cleanup, IlixDB, DB_NAME, DEVICES_POOL_COLL};
+
+/// A change of a pool, applied by a single conditional update: it's done only if the pool matches its condition when
+/// it's written, not when it was read. Each update bumps the pool `version`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PoolChange {
+    /// if the device isn't in the pool yet
+    Join {
+        device_id: String,
+        device_name: String,
+    },
+    /// if the device is in the pool
+    Leave {
+        device_id: String,
+    },
+    /// if the pool is still at `expected_version`, when there's one
+    Rename {
+        pool_name: String,
+        expected_version: Option<u64>,
+    },
+    /// if the pool is still empty, i.e: nobody joined after the last device left
+    DeleteIfEmpty,
+    Delete,
+}
+
+impl PoolChange {
+    /// the condition, as a mongodb filter
+    fn filter(&self, hashed_kp: &str) -> Document {
+        let mut filter = doc! {"hashed_key_phrase": hashed_kp};
+        match self {
+            Self::Join { device_id, .. } => {
+                filter.insert("devices_id", doc! {"$ne": device_id});
+            }
+            Self::Leave { device_id } => {
+                filter.insert("devices_id", device_id);
+            }
+            Self::DeleteIfEmpty => {
+                filter.insert("devices_id", doc! {"$size": 0});
+            }
+            Self::Rename {
+                expected_version: Some(version),
+                ..
+            } => {
+                filter.insert("version", *version as i64);
+            }
+            Self::Rename { .. } | Self::Delete => {}
+        }
+        filter
+    }
+
+    /// the update, `None` if the pool is deleted
+    fn update(&self) -> Option<Document> {
+        match self {
+            Self::Join {
+                device_id,
+                device_name,
+            } => Some(doc! {
+                "$push": {"devices_id": device_id},
+                "$set": {format!("devices_id_to_name.{device_id}"): device_name},
+                "$inc": {"version": 1},
+            }),
+            Self::Leave { device_id } => Some(doc! {
+                "$pull": {"devices_id": device_id},
+                "$unset": {
+                    format!("devices_id_to_name.{device_id}"): "",
+                    format!("devices_last_seen.{device_id}"): "",
+                },
+                "$inc": {"version": 1},
+            }),
+            Self::Rename { pool_name, .. } => Some(doc! {
+                "$set": {"pool_name": pool_name},
+                "$inc": {"version": 1},
+            }),
+            Self::DeleteIfEmpty | Self::Delete => None,
+        }
+    }
+}
+
+/// Where the pools are stored, the membership operations only go through it so that their interleavings can be
+/// tested without a database
+#[async_trait]
+pub trait PoolDocuments {
+    /// applies the change if the pool matches its condition, it returns the pool after the update (or as it was when
+    /// deleted), `None` if it didn't match
+    async fn apply(
+        &self,
+        hashed_kp: &str,
+        change: &PoolChange,
+    ) -> Result<Option<DevicesPool>, ServerError>;
+    async fn find(&self, hashed_kp: &str) -> Result<Option<DevicesPool>, ServerError>;
+}
+
+/// why a change didn't match, read after the fact: it only picks the error
+async fn unmatched(
+    pools: &impl PoolDocuments,
+    hashed_kp: &str,
+    exists: ServerErrors,
+) -> ServerError {
+    match pools.find(hashed_kp).await {
+        Ok(Some(_)) => exists.into(),
+        Ok(None) => ServerErrors::PoolNotFound.into(),
+        Err(err) => err,
+    }
+}
+
+/// `AlreadyInPool` if the device is in it, it returns the pool after the join
+pub async fn join(
+    pools: &impl PoolDocuments,
+    hashed_kp: &str,
+    device_id: &str,
+    device_name: &str,
+) -> Result<DevicesPool, ServerError> {
+    let change = PoolChange::Join {
+        device_id: device_id.to_string(),
+        device_name: device_name.to_string(),
+    };
+    match pools.apply(hashed_kp, &change).await? {
+        Some(pool) => Ok(pool),
+        None => Err(unmatched(pools, hashed_kp, ServerErrors::AlreadyInPool).await),
+    }
+}
+
+/// `NotInPool` if the device isn't in it, it returns the pool after the leave. It's deleted with its last device,
+/// unless someone joined in between
+pub async fn leave(
+    pools: &impl PoolDocuments,
+    hashed_kp: &str,
+    device_id: &str,
+) -> Result<DevicesPool, ServerError> {
+    let change = PoolChange::Leave {
+        device_id: device_id.to_string(),
+    };
+    let pool = match pools.apply(hashed_kp, &change).await? {
+        Some(pool) => pool,
+        None => return Err(unmatched(pools, hashed_kp, ServerErrors::NotInPool).await),
+    };
+
+    // if this stops before, the replay of the leave deletes it (see `Cleanup::LeavePool`)
+    if pool.devices_id.is_empty() {
+        pools.apply(hashed_kp, &PoolChange::DeleteIfEmpty).await?;
+    }
+    Ok(pool)
+}
+
+/// `VersionConflict` if the pool isn't at `expected_version` (when there's one), it returns the pool after the rename
+pub async fn rename(
+    pools: &impl PoolDocuments,
+    hashed_kp: &str,
+    pool_name: &str,
+    expected_version: Option<u64>,
+) -> Result<DevicesPool, ServerError> {
+    let change = PoolChange::Rename {
+        pool_name: pool_name.to_string(),
+        expected_version,
+    };
+    match pools.apply(hashed_kp, &change).await? {
+        Some(pool) => Ok(pool),
+        None => Err(unmatched(pools, hashed_kp, ServerErrors::VersionConflict).await),
+    }
+}
+
+#[async_trait]
+impl PoolDocuments for IlixDB {
+    async fn apply(
+        &self,
+        hashed_kp: &str,
+        change: &PoolChange,
+    ) -> Result<Option<DevicesPool>, ServerError> {
+        let collection = self
+            .client
+            .database(DB_NAME)
+            .collection::<DevicesPool>(DEVICES_POOL_COLL);
+        let Some(update) = change.update() else {
+            return self.delete_pool_where(change.filter(hashed_kp)).await;
+        };
+
+        collection
+            .find_one_and_update(
+                change.filter(hashed_kp),
+                update,
+                FindOneAndUpdateOptions::builder()
+                    .return_document(Some(ReturnDocument::After))
+                    .build(),
+            )
+            .await
+            .server_err(ServerErrors::MongoError)
+    }
+
+    async fn find(&self, hashed_kp: &str) -> Result<Option<DevicesPool>, ServerError> {
+        self.client
+            .database(DB_NAME)
+            .collection::<DevicesPool>(DEVICES_POOL_COLL)
+            .find_one(doc! {"hashed_key_phrase": hashed_kp}, None)
+            .await
+            .server_err(ServerErrors::MongoError)
+    }
+}
+
+impl IlixDB {
+    /// deletes the pool matching `filter`, then its transfers and their files (see [`Cleanup::DeletePool`])
+    async fn delete_pool_where(
+        &self,
+        filter: Document,
+    ) -> Result<Option<DevicesPool>, ServerError> {
+        let hashed_kp = filter
+            .get_str("hashed_key_phrase")
+            .server_err(ServerErrors::BadArgs)?
+            .to_string();
+        let cleanup = Cleanup::DeletePool { hashed_kp };
+        let cleanup_id = self.log_cleanup(&cleanup).await?;
+        let deleted = self
+            .client
+            .database(DB_NAME)
+            .collection::<DevicesPool>(DEVICES_POOL_COLL)
+            .find_one_and_delete(filter, None)
+            .await
+            .server_err(ServerErrors::MongoError)?; // kept for the replay, it may have been applied
+
+        match deleted {
+            Some(_) => {
+                self.complete_cleanup(cleanup_id, &cleanup).await;
+            }
+            None => self.drop_cleanup(cleanup_id).await,
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+
+    use async_trait::async_trait;
+    use futures_util::future;
+    use mongodb::bson::{from_document, to_document, Bson, Document};
+    use parking_lot::Mutex;
+    use proptest::prelude::*;
+
+    use ilix_core::{
+        errors::{ServerError, ServerErrors},
+        models::DevicesPool,
+    };
+
+    use super::{join, leave, rename, PoolChange, PoolDocuments};
+
+    /// `PoolNotFound` when the change didn't match
+    async fn apply_or_not_found(
+        pools: &MemoryPool,
+        change: &PoolChange,
+    ) -> Result<(), ServerError> {
+        match pools.apply(HASHED_KP, change).await? {
+            Some(_) => Ok(()),
+            None => Err(ServerErrors::PoolNotFound.into()),
+        }
+    }
+
+    const HASHED_KP: &str = "sasamiya";
+    const DEVICES: [&str; 3] = ["sasaki", "miyano", "hirano"];
+
+    fn as_i64(value: &Bson) -> i64 {
+        match value {
+            Bson::Int32(n) => i64::from(*n),
+            Bson::Int64(n) => *n,
+            value => panic!("not an integer: {value}"),
+        }
+    }
+
+    /// the filter operators used by [`PoolChange::filter`], on a document in memory
+    fn matches(pool: &Document, filter: &Document) -> bool {
+        filter.iter().all(|(field, condition)| {
+            let value = pool.get(field);
+            match condition {
+                Bson::Document(operators) => {
+                    operators
+                        .iter()
+                        .all(|(operator, arg)| match (operator.as_str(), value) {
+                            ("$ne", Some(Bson::Array(values))) => !values.contains(arg),
+                            ("$ne", value) => value != Some(arg),
+                            ("$size", Some(Bson::Array(values))) => {
+                                values.len() as i64 == as_i64(arg)
+                            }
+                            ("$size", _) => false,
+                            _ => panic!("unsupported filter {operator} on {field}"),
+                        })
+                }
+                condition => match value {
+                    Some(Bson::Array(values)) => values.contains(condition),
+                    value => value == Some(condition),
+                },
+            }
+        })
+    }
+
+    /// the update operators used by [`PoolChange::update`], on a document in memory
+    fn apply_update(pool: &mut Document, update: &Document) {
+        for (operator, fields) in update {
+            for (path, arg) in fields.as_document().unwrap() {
+                // at most one level of nesting, e.g: `devices_id_to_name.sasaki`
+                let (parent, field) = match path.split_once('.') {
+                    Some((parent, field)) => (pool.get_document_mut(parent).unwrap(), field),
+                    None => (&mut *pool, path.as_str()),
+                };
+                match operator.as_str() {
+                    "$set" => {
+                        parent.insert(field, arg.clone());
+                    }
+                    "$unset" => {
+                        parent.remove(field);
+                    }
+                    "$inc" => {
+                        let value = parent.get(field).map_or(0, as_i64) + as_i64(arg);
+                        parent.insert(field, value);
+                    }
+                    "$push" => parent.get_array_mut(field).unwrap().push(arg.clone()),
+                    "$pull" => parent
+                        .get_array_mut(field)
+                        .unwrap()
+                        .retain(|value| value != arg),
+                    _ => panic!("unsupported update {operator} on {path}"),
+                }
+            }
+        }
+    }
+
+    /// A single pool, each access yields to the other operations a number of times picked by the test.
+    ///
+    /// The changes run the same filter and update documents as the database
+    struct MemoryPool {
+        pool: Mutex<Option<Document>>,
+        yields: Mutex<VecDeque<u8>>,
+    }
+
+    impl MemoryPool {
+        async fn interleave(&self) {
+            let yields = self.yields.lock().pop_front().unwrap_or(0);
+            for _ in 0..yields {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    #[async_trait]
+    impl PoolDocuments for MemoryPool {
+        async fn apply(
+            &self,
+            hashed_kp: &str,
+            change: &PoolChange,
+        ) -> Result<Option<DevicesPool>, ServerError> {
+            self.interleave().await;
+            let mut stored = self.pool.lock();
+            let Some(pool) = stored.as_mut() else {
+                return Ok(None);
+            };
+            if !matches(pool, &change.filter(hashed_kp)) {
+                return Ok(None);
+            }
+            let pool = match change.update() {
+                Some(update) => {
+                    apply_update(pool, &update);
+                    pool.clone()
+                }
+                None => stored.take().unwrap(),
+            };
+            Ok(Some(from_document(pool).unwrap()))
+        }
+
+        async fn find(&self, hashed_kp: &str) -> Result<Option<DevicesPool>, ServerError> {
+            self.interleave().await;
+            Ok(self
+                .pool
+                .lock()
+                .clone()
+                .filter(|pool| pool.get_str("hashed_key_phrase") == Ok(hashed_kp))
+                .map(|pool| from_document(pool).unwrap()))
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Join(usize),
+        Leave(usize),
+        Rename,
+        /// at the version it read just before
+        RenameIfUnchanged,
+        Delete,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..DEVICES.len()).prop_map(Op::Join),
+            (0..DEVICES.len()).prop_map(Op::Leave),
+            Just(Op::Rename),
+            Just(Op::RenameIfUnchanged),
+            Just(Op::Delete),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(500))]
+
+        #[test]
+        fn concurrent_membership_test(
+            ops in proptest::collection::vec(op(), 1..8),
+            yields in proptest::collection::vec(0..4u8, 0..40),
+        ) {
+            let pool = DevicesPool {
+                pool_name: "bl".to_string(),
+                devices_id: vec![DEVICES[0].to_string()],
+                devices_id_to_name: HashMap::from([(DEVICES[0].to_string(), "phone".to_string())]),
+                devices_last_seen: HashMap::new(),
+                devices_online: vec![],
+                hashed_key_phrase: HASHED_KP.to_string(),
+                schema_version: DevicesPool::SCHEMA_VERSION,
+                created_at: 0,
+                version: 0,
+            };
+            let pools = MemoryPool {
+                pool: Mutex::new(Some(to_document(&pool).unwrap())),
+                yields: Mutex::new(yields.into()),
+            };
+
+            // all at once, interleaved on each access to the pool
+            let results = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(future::join_all(ops.iter().map(|op| {
+                    let pools = &pools;
+                    async move {
+                        match op {
+                            Op::Join(i) => join(pools, HASHED_KP, DEVICES[*i], "phone").await.map(|_| ()),
+                            Op::Leave(i) => leave(pools, HASHED_KP, DEVICES[*i]).await.map(|_| ()),
+                            Op::Rename => rename(pools, HASHED_KP, "sasamiya", None).await.map(|_| ()),
+                            Op::RenameIfUnchanged => {
+                                let read = pools.find(HASHED_KP).await.unwrap().ok_or(ServerErrors::PoolNotFound)?;
+                                let renamed = rename(pools, HASHED_KP, "sasamiya", Some(read.version)).await?;
+                                // nothing ran in between
+                                assert_eq!(renamed.version, read.version + 1);
+                                Ok(())
+                            }
+                            Op::Delete => apply_or_not_found(pools, &PoolChange::Delete).await,
+                        }
+                    }
+                })));
+
+            // the successful joins and leaves of each device, they alternate so the sum says if it's still there
+            let mut members = HashMap::from([(DEVICES[0], 1)]);
+            let (mut changes, mut deleted) = (0, false);
+            for (op, result) in ops.iter().zip(&results) {
+                match (op, result) {
+                    (Op::Join(i), Ok(())) => *members.entry(DEVICES[*i]).or_default() += 1,
+                    (Op::Leave(i), Ok(())) => *members.entry(DEVICES[*i]).or_default() -= 1,
+                    (Op::Rename | Op::RenameIfUnchanged, Ok(())) => {}
+                    (Op::Delete, Ok(())) => deleted = true,
+                    (_, Err(err)) => prop_assert!(
+                        matches!(
+                            err.kind,
+                            ServerErrors::AlreadyInPool | ServerErrors::NotInPool | ServerErrors::PoolNotFound | ServerErrors::VersionConflict
+                        ),
+                        "{op:?}: {err:?}"
+                    ),
+                }
+                changes += matches!(
+                    (op, result),
+                    (Op::Join(_) | Op::Leave(_) | Op::Rename | Op::RenameIfUnchanged, Ok(()))
+                ) as u64;
+            }
+            prop_assert!(members.values().all(|count| (0..=1).contains(count)), "{members:?}");
+            let mut expected = members
+                .into_iter()
+                .filter(|(_, count)| *count == 1)
+                .map(|(device, _)| device.to_string())
+                .collect::<Vec<_>>();
+            expected.sort();
+
+            match pools.pool.into_inner().map(|pool| from_document::<DevicesPool>(pool).unwrap()) {
+                // deleted explicitly, or with its last device
+                None => prop_assert!(deleted || expected.is_empty(), "deleted with {expected:?} in it"),
+                Some(pool) => {
+                    prop_assert!(!deleted, "still there after a delete");
+                    // whatever ran between the last leave and the delete
+                    prop_assert!(!pool.devices_id.is_empty(), "empty but not deleted");
+                    let mut devices = pool.devices_id.clone();
+                    devices.sort();
+                    prop_assert_eq!(&devices, &expected);
+                    let mut named = pool.devices_id_to_name.keys().cloned().collect::<Vec<_>>();
+                    named.sort();
+                    prop_assert_eq!(&named, &expected);
+                    prop_assert_eq!(pool.version, changes);
+                }
+            }
+        }
+    }
+}
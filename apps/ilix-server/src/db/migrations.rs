@@ -0,0 +1,209 @@
+use futures_util::future::BoxFuture;
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    options::UpdateOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use ilix_core::errors::{ServerError, ServerErrorContext, ServerErrors};
+
+use super::{IlixDB, DB_NAME, DEVICES_POOL_COLL, FILE_TRANSFER_COLL, SCHEMA_COLL};
+
+/// One step of the database schema, the steps are applied in order and each only once.
+///
+/// The version is recorded once the step ran, so if the server stops in between it runs again: a step must be
+/// idempotent (e.g: only update the documents it didn't update yet)
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    /// returns how many documents it changed, or would change in a dry run
+    run: for<'a> fn(&'a IlixDB, bool) -> BoxFuture<'a, Result<u64, ServerError>>,
+}
+
+/// every migration, by version. A new one goes at the end with the next version
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "backfill the schema_version and created_at of the pools and transfers",
+        run: backfill_versions,
+    },
+    Migration {
+        version: 2,
+        description: "start the version of the pools at 0",
+        run: init_pools_version,
+    },
+];
+
+/// the schema version this server expects
+pub fn latest_schema_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// the single document of the `schema_version` collection
+#[derive(Debug, Deserialize, Serialize)]
+struct SchemaState {
+    version: u32,
+    /// the applied migrations, for whoever wonders what happened to the documents
+    #[serde(default)]
+    migrations: Vec<AppliedMigration>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AppliedMigration {
+    version: u32,
+    description: String,
+    applied_at: DateTime,
+    changed: u64,
+}
+
+/// What a migration did, or would do in a dry run
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub version: u32,
+    pub description: &'static str,
+    pub changed: u64,
+}
+
+impl IlixDB {
+    /// the version of the last migration applied, 0 on a database that never was migrated
+    pub async fn schema_version(&self) -> Result<u32, ServerError> {
+        let state = self
+            .client
+            .database(DB_NAME)
+            .collection::<SchemaState>(SCHEMA_COLL)
+            .find_one(doc! {"_id": "schema"}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(state.map_or(0, |state| state.version))
+    }
+
+    /// Applies the migrations newer than the database schema, in order, and returns what each did.
+    ///
+    /// With `dry_run` nothing is written, the reports count the documents that would change. Several instances can
+    /// migrate at once: the steps are idempotent and the version only goes up
+    pub async fn migrate(&self, dry_run: bool) -> Result<Vec<MigrationReport>, ServerError> {
+        let current = self.schema_version().await?;
+        let mut reports = vec![];
+        for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+            let changed = (migration.run)(self, dry_run).await?;
+            if !dry_run {
+                self.record_migration(migration, changed).await?;
+            }
+            reports.push(MigrationReport {
+                version: migration.version,
+                description: migration.description,
+                changed,
+            });
+        }
+        Ok(reports)
+    }
+
+    async fn record_migration(
+        &self,
+        migration: &Migration,
+        changed: u64,
+    ) -> Result<(), ServerError> {
+        let schema = self
+            .client
+            .database(DB_NAME)
+            .collection::<Document>(SCHEMA_COLL);
+        schema
+            .update_one(
+                doc! {"_id": "schema"},
+                doc! {"$setOnInsert": {"version": 0, "migrations": []}},
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+            .server_err(ServerErrors::MongoError)?;
+
+        // if another instance recorded it first, this one doesn't match
+        let applied = mongodb::bson::to_bson(&AppliedMigration {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied_at: DateTime::now(),
+            changed,
+        })
+        .server_err(ServerErrors::ParseError)?;
+        schema
+            .update_one(
+                doc! {"_id": "schema", "version": {"$lt": migration.version}},
+                doc! {"$set": {"version": migration.version}, "$push": {"migrations": applied}},
+                None,
+            )
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(())
+    }
+
+    /// Applies `set` (an aggregation `$set` stage) to the documents below `version` and sets their `schema_version`.
+    ///
+    /// A step must pass the version it brings the documents to, not the current `SCHEMA_VERSION` of the model: it's
+    /// bumped by the next steps
+    async fn upgrade_collection(
+        &self,
+        collection: &str,
+        version: u32,
+        mut set: Document,
+        dry_run: bool,
+    ) -> Result<u64, ServerError> {
+        let collection = self
+            .client
+            .database(DB_NAME)
+            .collection::<Document>(collection);
+        let outdated = doc! {"$or": [
+            {"schema_version": {"$exists": false}},
+            {"schema_version": {"$lt": version}},
+        ]};
+        if dry_run {
+            return collection
+                .count_documents(outdated, None)
+                .await
+                .server_err(ServerErrors::MongoError);
+        }
+
+        set.insert("schema_version", version);
+        let report = collection
+            .update_many(outdated, vec![doc! {"$set": set}], None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(report.modified_count)
+    }
+}
+
+/// `created_at` is read from the `_id`, which holds the insertion time
+fn backfill_versions(db: &IlixDB, dry_run: bool) -> BoxFuture<'_, Result<u64, ServerError>> {
+    Box::pin(async move {
+        let created_at =
+            doc! {"created_at": {"$ifNull": ["$created_at", {"$toLong": {"$toDate": "$_id"}}]}};
+        let pools = db
+            .upgrade_collection(DEVICES_POOL_COLL, 1, created_at.clone(), dry_run)
+            .await?;
+        let transfers = db
+            .upgrade_collection(FILE_TRANSFER_COLL, 1, created_at, dry_run)
+            .await?;
+        Ok(pools + transfers)
+    })
+}
+
+/// the pools created before it report a version too
+fn init_pools_version(db: &IlixDB, dry_run: bool) -> BoxFuture<'_, Result<u64, ServerError>> {
+    Box::pin(async move {
+        let version = doc! {"version": {"$ifNull": ["$version", 0_i64]}};
+        db.upgrade_collection(DEVICES_POOL_COLL, 2, version, dry_run)
+            .await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{latest_schema_version, MIGRATIONS};
+
+    #[test]
+    fn migrations_order_test() {
+        // applied by version, a gap or a duplicate would skip or replay one
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1, "{}", migration.description);
+        }
+        assert_eq!(latest_schema_version(), MIGRATIONS.len() as u32);
+    }
+}
@@ -1,34 +1,54 @@
+pub mod cleanups;
 pub mod collections;
-pub mod models;
+pub mod maintenance;
+pub mod membership;
+pub mod migrations;
+pub mod presence;
 
 use anyhow::Result;
-use std::env;
 
-use mongodb::{options::ClientOptions, Client};
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    keyphrase::HashParams,
+};
+use mongodb::{
+    bson::{doc, Document},
+    options::ClientOptions,
+    Client,
+};
+
+use crate::config::Config;
 
 pub const DB_NAME: &str = "ilix";
 pub const DEVICES_POOL_COLL: &str = "devices_pools";
 pub const FILE_TRANSFER_COLL: &str = "files_transfers";
 pub const GRIDFS_BUCKET_NAME: &str = "ilix_fs";
+/// the collections of the gridfs bucket, `{GRIDFS_BUCKET_NAME}.files` and `.chunks`
+pub const GRIDFS_FILES_COLL: &str = "ilix_fs.files";
+pub const GRIDFS_CHUNKS_COLL: &str = "ilix_fs.chunks";
+pub const SSE_EVENTS_COLL: &str = "sse_events";
+pub const SSE_EVENT_IDS_COLL: &str = "sse_event_ids";
+pub const SCHEMA_COLL: &str = "schema_version";
+pub const CLEANUPS_COLL: &str = "pending_cleanups";
+pub const PRESENCE_COLL: &str = "devices_presence";
 
 #[derive(Debug)]
 pub enum IlixDBErrors {
-    UriNotFound,
     FailedToConnect,
     InvalidOption,
 }
 
-/// wrapper for the db connection
+/// wrapper for the db connection, it implements the `ilix_core::storage` traits
 #[derive(Clone)]
 pub struct IlixDB {
     pub client: Client,
+    /// the pools are stored by their hashed key phrase
+    pub hash_params: HashParams,
 }
 
 impl IlixDB {
-    pub async fn connect() -> Result<Self, IlixDBErrors> {
-        let db_uri = env::var("MONGODB_URI").map_err(|_| IlixDBErrors::UriNotFound)?;
-
-        let mut db_options = ClientOptions::parse(db_uri)
+    pub async fn connect(config: &Config) -> Result<Self, IlixDBErrors> {
+        let mut db_options = ClientOptions::parse(&config.mongodb_uri)
             .await
             .map_err(|_| IlixDBErrors::FailedToConnect)?;
         db_options.app_name = Some("ilix".to_string());
@@ -37,6 +57,41 @@ impl IlixDB {
         let db_client =
             Client::with_options(db_options).map_err(|_| IlixDBErrors::InvalidOption)?;
 
-        Ok(Self { client: db_client })
+        Ok(Self {
+            client: db_client,
+            hash_params: config.hash_params(),
+        })
+    }
+
+    /// checks that mongodb answers
+    pub async fn ping(&self) -> Result<(), ServerError> {
+        self.client
+            .database(DB_NAME)
+            .run_command(doc! {"ping": 1}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(())
+    }
+
+    /// checks that the gridfs bucket, where the files are stored, can be read
+    pub async fn ping_storage(&self) -> Result<(), ServerError> {
+        self.client
+            .database(DB_NAME)
+            .collection::<Document>(GRIDFS_FILES_COLL)
+            .find_one(None, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(())
+    }
+
+    /// the db of the tests, the client connects lazily: nothing reaches mongodb unless a test runs a query
+    #[cfg(test)]
+    pub async fn test() -> Self {
+        Self {
+            client: Client::with_uri_str("mongodb://localhost:27017")
+                .await
+                .unwrap(),
+            hash_params: HashParams::new(10, "sasamiya").unwrap(),
+        }
     }
 }
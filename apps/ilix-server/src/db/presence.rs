@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use futures_util::{future, TryStreamExt};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{IndexOptions, UpdateOptions},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use ilix_core::errors::{ServerError, ServerErrorContext, ServerErrors};
+
+use super::{IlixDB, DB_NAME, PRESENCE_COLL};
+
+/// A device connected to the event stream of some instance.
+///
+/// Each instance refreshes `online_until` for the devices connected to it, so a device is online for every instance
+/// until it disconnects, or until it expires if its instance stopped without telling (mongodb removes it then)
+#[derive(Debug, Deserialize, Serialize)]
+struct DevicePresence {
+    /// `{pool_id}:{device_id}`
+    _id: String,
+    pool_id: String,
+    device_id: String,
+    online_until: DateTime,
+}
+
+impl IlixDB {
+    /// expires the presences that weren't refreshed in time
+    pub async fn create_presence_ttl_index(&self) -> Result<(), ServerError> {
+        let index = IndexModel::builder()
+            .keys(doc! {"online_until": 1})
+            .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+            .build();
+        self.client
+            .database(DB_NAME)
+            .collection::<DevicePresence>(PRESENCE_COLL)
+            .create_index(index, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(())
+    }
+
+    /// marks these devices (`(pool_id, device_id)`) online for `ttl` from now
+    pub async fn refresh_presence(
+        &self,
+        devices: &[(String, String)],
+        ttl: Duration,
+    ) -> Result<(), ServerError> {
+        let online_until = DateTime::from_system_time(DateTime::now().to_system_time() + ttl);
+        let collection = self
+            .client
+            .database(DB_NAME)
+            .collection::<DevicePresence>(PRESENCE_COLL);
+        let upserts = devices.iter().map(|(pool_id, device_id)| {
+            collection.update_one(
+                doc! {"_id": format!("{pool_id}:{device_id}")},
+                doc! {
+                    "$set": {"pool_id": pool_id, "device_id": device_id},
+                    "$max": {"online_until": online_until},
+                },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+        });
+        for upserted in future::join_all(upserts).await {
+            upserted.server_err(ServerErrors::MongoError)?;
+        }
+        Ok(())
+    }
+
+    /// the device's last connection to this instance closed. If it's still connected to another one, that one marks
+    /// it online again on its next refresh
+    pub async fn remove_presence(&self, pool_id: &str, device_id: &str) -> Result<(), ServerError> {
+        self.client
+            .database(DB_NAME)
+            .collection::<DevicePresence>(PRESENCE_COLL)
+            .delete_one(doc! {"_id": format!("{pool_id}:{device_id}")}, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(())
+    }
+
+    /// the devices of this pool connected to the event stream of any instance
+    pub async fn online_devices(&self, pool_id: &str) -> Result<Vec<String>, ServerError> {
+        // mongodb removes the expired ones about every minute, they're filtered meanwhile
+        self.client
+            .database(DB_NAME)
+            .collection::<DevicePresence>(PRESENCE_COLL)
+            .find(
+                doc! {"pool_id": pool_id, "online_until": {"$gt": DateTime::now()}},
+                None,
+            )
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .map_ok(|presence| presence.device_id)
+            .try_collect()
+            .await
+            .server_err(ServerErrors::MongoError)
+    }
+}
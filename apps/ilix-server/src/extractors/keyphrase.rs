@@ -1,44 +1,44 @@
-use actix_web::{dev::Payload, http::StatusCode, FromRequest, HttpRequest};
+use std::ops::Deref;
+
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
 use futures_util::future::{err, ok, Ready};
+use ilix_core::{errors::ServerErrors, keyphrase::KeyPhrase};
+
+use crate::services::ApiError;
+
+/// Extracts the [`KeyPhrase`] of the "Authorization" header, it derefs to it
+pub struct AuthKeyPhrase(pub KeyPhrase);
+
+impl Deref for AuthKeyPhrase {
+    type Target = KeyPhrase;
 
-use crate::{services::ResponsePayload, utils::keyphrase::KeyPhrase};
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
 
-impl FromRequest for KeyPhrase {
-    type Error = ResponsePayload;
+impl FromRequest for AuthKeyPhrase {
+    type Error = ApiError;
     type Future = Ready<Result<Self, Self::Error>>;
 
     #[inline]
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
         let auth = match req.headers().get("Authorization") {
             Some(d) => d,
-            None => {
-                return err(ResponsePayload::new(
-                    false,
-                    &(),
-                    Some(StatusCode::UNAUTHORIZED),
-                    Some("missing 'Authorization' header".to_string()),
-                ))
-            }
+            None => return err(ServerErrors::MissingAuthorization.into()),
         };
         let kp = match String::from_utf8(auth.as_bytes().to_vec()) {
             Ok(d) => d,
             Err(_) => {
-                return err(ResponsePayload::new(
-                    false,
-                    &(),
-                    Some(StatusCode::UNAUTHORIZED),
-                    Some("invalid 'Authorization' header".to_string()),
+                return err(ApiError::with_details(
+                    ServerErrors::InvalidKeyPhrase,
+                    "invalid 'Authorization' header",
                 ))
             }
         };
         match KeyPhrase::try_from(kp) {
-            Ok(key_phrase) => ok(key_phrase),
-            Err(why) => err(ResponsePayload::new(
-                false,
-                &(),
-                Some(StatusCode::UNAUTHORIZED),
-                Some(why.to_string()),
-            )),
+            Ok(key_phrase) => ok(AuthKeyPhrase(key_phrase)),
+            Err(why) => err(why.into()),
         }
     }
 }
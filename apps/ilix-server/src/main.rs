@@ -1,116 +1,210 @@
+mod admin;
+mod config;
 mod db;
-mod e2e;
 mod extractors;
+mod middlewares;
 mod services;
 mod utils;
 
 use actix_web::{middleware::Logger, web, App, HttpResponse, HttpServer};
-use anyhow::Result;
-use db::{
-    collections::{DevicePoolsCollection, FilePoolTransferCollection},
-    IlixDB,
-};
+use actix_web_lab::middleware::from_fn;
+use clap::Parser;
+use config::{Cli, Config};
+use db::{migrations::latest_schema_version, IlixDB};
 use env_logger::Env;
+use ilix_core::storage::{DevicePoolsCollection, FilePoolTransferCollection};
+use middlewares::deprecation::deprecated;
 use services::{
-    events::event_stream,
-    file::{delete_file, get_file},
-    file_transfer::{add_files_to_transfer, create_transfer, delete_transfer, get_all_transfer},
-    files::get_files_info,
-    pool::{delete_pool, get_pool, join_pool, leave_pool, new_pool},
+    health::{healthz, metrics, readyz},
+    openapi::{docs, openapi_json},
+    v1_routes, v2_routes,
 };
-use std::env;
 use std::sync::Arc;
-use utils::{console_log, is_prod, sse::Broadcaster};
+use utils::{console_log, pubsub, sse::Broadcaster, uploads::Uploads};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    load_env();
-    let srv_addr = get_server_addr().expect("Couldn't get server addr");
+    // defaults < config file < env vars (and the .env file) < flags
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    let srv_addr = config.bind_addr();
+
+    // before anything logs: the migrations, the replayed cleanups...
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
 
     // db connection
-    let db = IlixDB::connect()
+    let db = IlixDB::connect(&config)
         .await
         .expect("Couldn't connect to mongodb database");
 
+    if let Some(command) = cli.command {
+        if let Err(err) = admin::run(command, &db, &config).await {
+            eprintln!("{err:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Index creation
     {
-        db.client
-            .create_pool_hashed_kp_index()
+        db.create_pool_hashed_kp_index()
+            .await
+            .expect("creating an index should succeed");
+        db.create_transfer_hashed_kp_index()
             .await
             .expect("creating an index should succeed");
-        db.client
-            .create_transfer_hashed_kp_index()
+        db.create_presence_ttl_index()
             .await
             .expect("creating an index should succeed");
     }
 
+    // the documents must be in the format this version reads and writes
+    if let Err(err) = ensure_schema(&db, config.auto_migrate).await {
+        eprintln!("{err:#}");
+        std::process::exit(1);
+    }
+
+    // finishes the deletions (and rolls back the uploads) a crash or a failed step left halfway
+    db.spawn_cleanups_replay(
+        config.timeouts.cleanup_replay,
+        config.timeouts.max_upload_duration,
+    );
+
     // launch SSE module
-    let see_broadcaster = Broadcaster::create();
+    let pubsub = pubsub::from_config(&config, &db.client)
+        .await
+        .expect("Couldn't connect to the pub/sub backend");
+    let see_broadcaster = Broadcaster::create(db.clone(), pubsub, &config)
+        .await
+        .expect("Couldn't subscribe to the pub/sub backend");
 
     // Launch web service
-    env_logger::init_from_env(Env::default().default_filter_or("info"));
     console_log(
         &format!("Lauching web service on: {}:{} 🌐", srv_addr.0, srv_addr.1),
         log::Level::Info,
     );
-    HttpServer::new(move || {
+    let sunset = config.unversioned_sunset();
+    let uploads = Uploads::default();
+    let app_db = db.clone();
+    let app_uploads = uploads.clone();
+    let app_broadcaster = Arc::clone(&see_broadcaster);
+    let shutdown_deadline = config.timeouts.shutdown_deadline;
+    let server = HttpServer::new(move || {
         App::new()
+            // tags every request (and its logs) with an id, inside the loggers so that they can print it
+            .wrap(from_fn(middlewares::request_id::request_id))
+            // the error responses are shaped by the negotiated version
+            .wrap(from_fn(middlewares::api_version::api_version))
+            // requests count and latency by route, served by /metrics
+            .wrap(from_fn(middlewares::metrics::record_metrics))
             // Req Logger
             .wrap(Logger::default())
-            .wrap(Logger::new("%a %{User-Agent}i"))
+            .wrap(Logger::new("%a %{User-Agent}i %{x-request-id}o"))
             // app datas
-            .app_data(web::Data::new(db.clone()))
-            .app_data(web::Data::from(Arc::clone(&see_broadcaster)))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(app_db.clone()))
+            .app_data(web::Data::from(Arc::clone(&app_broadcaster)))
+            .app_data(web::Data::new(app_uploads.clone()))
             // services
             .route(
                 "/ping",
                 web::get().to(|| async { HttpResponse::Ok().body("pong") }),
             )
+            .service(healthz)
+            .service(readyz)
+            .service(metrics)
+            .service(openapi_json)
+            .service(docs)
+            .service(web::scope("/v1").configure(v1_routes))
+            .service(web::scope("/v2").configure(v2_routes))
+            // the unprefixed v1 aliases, for the mobile builds already out there
             .service(
-                web::scope("/pool")
-                    .service(new_pool)
-                    .service(get_pool)
-                    .service(join_pool)
-                    .service(leave_pool)
-                    .service(delete_pool),
+                web::scope("")
+                    .wrap(deprecated("/v1", sunset.as_ref()))
+                    .configure(v1_routes),
             )
-            .service(
-                web::scope("/file-transfer")
-                    .service(get_all_transfer)
-                    .service(create_transfer)
-                    .service(add_files_to_transfer)
-                    .service(delete_transfer),
-            )
-            .service(web::scope("/file").service(get_file).service(delete_file))
-            .service(web::scope("/files").service(get_files_info))
-            .service(event_stream)
     })
+    // the signals are handled below, the event streams must be closed before waiting for the requests to finish
+    .disable_signals()
+    .shutdown_timeout(shutdown_deadline.as_secs())
     .bind(srv_addr)?
-    .run()
-    .await
+    .run();
+
+    let handle = server.handle();
+    let stopping_uploads = uploads.clone();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        console_log(
+            &format!(
+                "Shutting down, waiting for {} upload(s) to finish 🛑",
+                stopping_uploads.len()
+            ),
+            log::Level::Info,
+        );
+        see_broadcaster.shutdown();
+        handle.stop(true).await;
+    });
+    server.await?;
+
+    // what's left didn't finish before the deadline
+    let rolled_back = uploads.rollback_all(&db).await;
+    if rolled_back > 0 {
+        console_log(
+            &format!("Rolled back {rolled_back} file(s) of unfinished uploads"),
+            log::Level::Warn,
+        );
+    }
+    Ok(())
 }
 
-/// It panics if it failed to load env vars
-fn load_env() {
-    match is_prod() {
-        true => {
-            assert!(!env::var("APP_MODE").unwrap().is_empty());
-            assert!(!env::var("HASH_ROUND").unwrap().is_empty());
-            assert!(!env::var("MONGODB_URI").unwrap().is_empty());
-            assert!(!env::var("SALT").unwrap().is_empty());
-        }
-        false => {
-            dotenv::dotenv().unwrap();
-        }
-    };
+/// applies the pending migrations, or refuses to start if `auto_migrate` is off and the database is behind
+async fn ensure_schema(db: &IlixDB, auto_migrate: bool) -> anyhow::Result<()> {
+    let (current, latest) = (db.schema_version().await?, latest_schema_version());
+    if current > latest {
+        anyhow::bail!(
+            "the database schema (v{current}) is newer than this server (v{latest}), update it"
+        );
+    }
+    if current == latest {
+        return Ok(());
+    }
+    if !auto_migrate {
+        anyhow::bail!(
+            "the database schema (v{current}) is behind this server (v{latest}), run `ilix_server migrate` first"
+        );
+    }
+
+    for report in db.migrate(false).await? {
+        console_log(
+            &format!(
+                "Migrated the database to v{}: {} ({} document(s) changed)",
+                report.version, report.description, report.changed
+            ),
+            log::Level::Info,
+        );
+    }
+    Ok(())
 }
 
-fn get_server_addr() -> Result<(&'static str, u16)> {
-    let port = env::var("PORT")
-        .unwrap_or(String::from("8080"))
-        .parse::<u16>()?;
-    Ok(match is_prod() {
-        true => ("0.0.0.0", port),
-        false => ("127.0.0.1", port),
-    })
+/// SIGTERM (docker, fly...) or ctrl-c
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = signal(SignalKind::terminate()).expect("Couldn't listen to SIGTERM");
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = actix_web::rt::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = actix_web::rt::signal::ctrl_c().await;
 }
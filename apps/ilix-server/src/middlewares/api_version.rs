@@ -0,0 +1,29 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    Error,
+};
+use actix_web_lab::middleware::Next;
+
+use crate::services::ApiVersion;
+
+tokio::task_local! {
+    static API_VERSION: ApiVersion;
+}
+
+/// returns the api version negotiated by the request being handled, v1 outside of a request
+pub fn current_api_version() -> ApiVersion {
+    API_VERSION
+        .try_with(|version| *version)
+        .unwrap_or(ApiVersion::V1)
+}
+
+/// Negotiates the api version once per request (see [`ApiVersion::of`]), for what only gets the error and not the
+/// request: the error responses
+pub async fn api_version(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let version = ApiVersion::of(req.request());
+    API_VERSION.scope(version, next.call(req)).await
+}
@@ -0,0 +1,60 @@
+use actix_web::{http::header::HttpDate, middleware::DefaultHeaders};
+
+/// Marks the routes it wraps as deprecated (`Deprecation` header), pointing to their `successor` and, if it's known,
+/// to the date they will be removed (`Sunset` header, RFC 8594)
+pub fn deprecated(successor: &str, sunset: Option<&HttpDate>) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add(("Deprecation", "true"))
+        .add(("Link", format!("<{successor}>; rel=\"successor-version\"")));
+    match sunset {
+        Some(sunset) => headers.add(("Sunset", sunset.to_string())),
+        None => headers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use actix_web::{http::header::HttpDate, test as actix_test, web, App, HttpResponse};
+
+    use super::deprecated;
+
+    #[actix_web::test]
+    async fn deprecated_test() {
+        let sunset = HttpDate::from_str("Sat, 01 Nov 2025 00:00:00 GMT").unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .service(web::scope("/v1").route("/pool", web::get().to(HttpResponse::Ok)))
+                .service(
+                    web::scope("")
+                        .wrap(deprecated("/v1", Some(&sunset)))
+                        .route("/pool", web::get().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+
+        let res = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::get().uri("/v1/pool").to_request(),
+        )
+        .await;
+        assert!(res.headers().get("Deprecation").is_none());
+
+        let res = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::get().uri("/pool").to_request(),
+        )
+        .await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get("Deprecation").unwrap(), "true");
+        assert_eq!(
+            res.headers().get("Sunset").unwrap(),
+            "Sat, 01 Nov 2025 00:00:00 GMT"
+        );
+        assert_eq!(
+            res.headers().get("Link").unwrap(),
+            "</v1>; rel=\"successor-version\""
+        );
+    }
+}
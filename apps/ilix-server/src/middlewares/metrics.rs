@@ -0,0 +1,29 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    Error,
+};
+use actix_web_lab::middleware::Next;
+
+use crate::utils::metrics::METRICS;
+
+/// the route of the requests that didn't match any, so that random paths don't make new series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Records the count and the latency of the requests, by route
+pub async fn record_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+    let res = next.call(req).await?;
+    METRICS.observe_request(&method, &route, res.status().as_u16(), start.elapsed());
+    Ok(res)
+}
@@ -0,0 +1,4 @@
+pub mod api_version;
+pub mod deprecation;
+pub mod metrics;
+pub mod request_id;
@@ -0,0 +1,101 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use actix_web_lab::middleware::Next;
+use uuid::Uuid;
+
+/// the header carrying the request id, in the request (if set by a proxy) and in every response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// a request id coming from outside longer than this is replaced
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// returns the id of the request being handled, `None` outside of a request (e.g: in a spawned task)
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Tags every request with an id, sent back in the `x-request-id` header and attached to the logs and error responses.
+///
+/// A client can give this id to an operator, who finds the server side cause of the failure in the logs
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    // the id set by a proxy in front of us is kept, so that both logs match
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let mut res = REQUEST_ID.scope(id.clone(), next.call(req)).await?;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(res)
+}
+
+/// the id ends up in the logs, it must not be able to forge log lines
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test as actix_test, web, App, HttpResponse};
+    use actix_web_lab::middleware::from_fn;
+
+    use super::{current_request_id, is_valid_request_id, request_id, REQUEST_ID_HEADER};
+
+    #[test]
+    fn is_valid_request_id_test() {
+        assert!(is_valid_request_id("3f2b5c1e-sasaki_miyano"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("a\nINFO forged log line"));
+        assert!(!is_valid_request_id(&"a".repeat(65)));
+    }
+
+    #[actix_web::test]
+    async fn request_id_middleware_test() {
+        let app =
+            actix_test::init_service(App::new().wrap(from_fn(request_id)).route(
+                "/",
+                web::get().to(|| async {
+                    HttpResponse::Ok().body(current_request_id().unwrap_or_default())
+                }),
+            ))
+            .await;
+
+        // generated
+        let res =
+            actix_test::call_service(&app, actix_test::TestRequest::get().uri("/").to_request())
+                .await;
+        let header = res.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        let body = actix_test::read_body(res).await;
+        assert_eq!(header.as_bytes(), &body[..]);
+        assert!(!body.is_empty());
+
+        // kept from the proxy
+        let req = actix_test::TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "from-the-proxy"))
+            .to_request();
+        let body = actix_test::call_and_read_body(&app, req).await;
+        assert_eq!(&body[..], b"from-the-proxy");
+        assert!(current_request_id().is_none());
+    }
+}
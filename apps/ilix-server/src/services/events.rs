@@ -1,47 +1,60 @@
-use actix_web::{get, http::StatusCode, web, Either};
-use actix_web_lab::sse::ChannelStream;
+use actix_web::{get, web, HttpRequest};
+use actix_web_lab::sse::Sse;
 use serde::Deserialize;
+use utoipa::IntoParams;
+
+use ilix_core::storage::DevicePoolsCollection;
 
 use crate::{
-    db::{collections::DevicePoolsCollection, IlixDB},
-    utils::{errors::ServerErrors, keyphrase::KeyPhrase, sse::Broadcaster},
+    db::IlixDB,
+    extractors::keyphrase::AuthKeyPhrase,
+    utils::sse::{Broadcaster, ClientStream},
 };
 
-use super::ResponsePayload;
+use super::ApiError;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 struct EventPayload {
     device_id: String,
+    /// the schema version of the events the client understands (see the `connected` event), without it the pool
+    /// changes are sent as the full pool (`pool` events) instead of `device_joined`, `device_left` and `pool_renamed`
+    schema_version: Option<u32>,
 }
 
-type RegisterResult = Either<ResponsePayload, actix_web_lab::sse::Sse<ChannelStream>>;
+/// Server-sent events of the pool, for this device. The `Last-Event-ID` header replays the events missed since then
+#[utoipa::path(
+    tag = "events",
+    params(EventPayload, ("Last-Event-ID" = Option<u64>, Header, description = "the id of the last event received")),
+    responses(
+        (status = 200, description = "the event stream, see the `SSEData` schema for the events datas. When the server stops, a `shutdown` event (`{\"retry_ms\": 5000}`) ends it", content_type = "text/event-stream", body = String),
+        (status = 404, description = "PoolNotFound", body = ResponsePayload),
+        (status = 503, description = "ShuttingDown", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[get("/events")]
 async fn event_stream(
+    req: HttpRequest,
     db: web::Data<IlixDB>,
     sse: web::Data<Broadcaster>,
-    key_phrase: KeyPhrase,
+    key_phrase: AuthKeyPhrase,
     query: web::Query<EventPayload>,
-) -> RegisterResult {
-    if let Err(err) = db.client.get_pool(&key_phrase).await {
-        let err_status_code = match err {
-            ServerErrors::PoolNotFound => Some(StatusCode::NOT_FOUND),
-            _ => None,
-        };
-        return Either::Left(ResponsePayload::new(
-            false,
-            &(),
-            err_status_code,
-            Some(err.to_string()),
-        ));
-    };
+) -> Result<Sse<ClientStream>, ApiError> {
+    db.get_pool(&key_phrase).await?;
+
+    // sent by the client when reconnecting, to replay the events it missed
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<u64>().ok());
 
-    match sse.new_client(&query.device_id, &key_phrase).await {
-        Ok(channel) => Either::Right(channel),
-        Err(err) => Either::Left(ResponsePayload::new(
-            false,
-            &(),
-            None,
-            Some(err.to_string()),
-        )),
-    }
+    Ok(sse
+        .new_client(
+            &query.device_id,
+            &key_phrase,
+            last_event_id,
+            query.schema_version.is_some(),
+        )
+        .await?)
 }
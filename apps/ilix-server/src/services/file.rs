@@ -1,105 +1,167 @@
 use std::io::Write;
 
 use actix_files::NamedFile;
-use actix_web::{delete, get, http::StatusCode, web, Either, Responder, Result};
+use actix_web::{delete, get, web, HttpResponse};
 use uuid::Uuid;
 
+use ilix_core::{
+    errors::ServerErrors,
+    events::{FileRemoved, SSEData, TransferDeleted},
+    storage::{FilePoolTransferCollection, FileStorageGridFS},
+};
+
 use crate::{
-    db::{
-        collections::{FilePoolTransferCollection, FileStorageGridFS},
-        IlixDB,
+    db::IlixDB,
+    extractors::keyphrase::AuthKeyPhrase,
+    utils::{
+        filename::{content_disposition, safe_extension},
+        is_str_empty,
+        metrics::METRICS,
+        sse::Broadcaster,
+        thumbnail::THUMBNAIL_MIME_TYPE,
     },
-    services::BAD_ARGS_RESP,
-    utils::{errors::ServerErrors, is_str_empty, keyphrase::KeyPhrase},
 };
 
-use super::ResponsePayload;
+use super::{ApiError, ApiResult, ResponsePayload};
 
 // if client wants to get multiple files at once, it musts call async this endpoint and handle the Promises on their own
-type GetFileResult = Either<ResponsePayload, Result<NamedFile>>;
+/// the decrypted file
+#[utoipa::path(
+    context_path = "/file",
+    tag = "file",
+    params(("file_id" = String, Path, description = "the file id")),
+    responses(
+        (status = 200, description = "the file, with its original filename in `Content-Disposition`", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 404, description = "FileNotFound", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[get("/{file_id}")]
 async fn get_file(
     db: web::Data<IlixDB>,
     file_id: web::Path<String>,
-    key_phrase: KeyPhrase,
-) -> GetFileResult {
+    key_phrase: AuthKeyPhrase,
+) -> Result<NamedFile, ApiError> {
     if is_str_empty(&file_id) {
-        return Either::Left(BAD_ARGS_RESP.clone());
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db.client.get_file(&file_id, &key_phrase).await;
-    match db_result {
-        Ok((filename, filebuf)) => {
-            let filepath = format!("./tmp/{}-{filename}", Uuid::new_v4());
-            let filepath2 = filepath.clone();
-            let filepath3 = filepath.clone();
-
-            if let Ok(Ok(mut f)) = web::block(|| std::fs::File::create(filepath)).await {
-                if let Ok(Ok(_)) = web::block(move || f.write_all(&filebuf)).await {
-                    if let Ok(file) = NamedFile::open_async(filepath2).await {
-                        scopeguard::defer! {
-                            let _ = std::fs::remove_file(filepath3);
-                        };
-
-                        return Either::Right(Ok(file));
-                    }
+    let (file_info, filebuf) = db.get_file(&file_id, &key_phrase).await?;
+    METRICS.downloaded(filebuf.len());
+
+    // the client filename never touches the filesystem, it's only sent back in the headers. Its extension is kept,
+    // the content type (and whether it's shown inline) is guessed from it when none was stored
+    let filepath = match safe_extension(&file_info.filename) {
+        Some(ext) => format!("./tmp/{}.{ext}", Uuid::new_v4()),
+        None => format!("./tmp/{}", Uuid::new_v4()),
+    };
+    let filepath2 = filepath.clone();
+    let filepath3 = filepath.clone();
+
+    if let Ok(Ok(mut f)) = web::block(|| std::fs::File::create(filepath)).await {
+        if let Ok(Ok(_)) = web::block(move || f.write_all(&filebuf)).await {
+            if let Ok(mut file) = NamedFile::open_async(filepath2).await {
+                scopeguard::defer! {
+                    let _ = std::fs::remove_file(filepath3);
+                };
+
+                let disposition = file.content_disposition().disposition.clone();
+                file = file
+                    .set_content_disposition(content_disposition(disposition, &file_info.filename));
+
+                // otherwise it's guessed from the file extension
+                if let Some(mime) = file_info
+                    .metadata
+                    .mime_type
+                    .and_then(|mime| mime.parse::<mime::Mime>().ok())
+                {
+                    file = file.set_content_type(mime);
                 }
+                return Ok(file);
             }
         }
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::InvalidObjectId => StatusCode::BAD_REQUEST,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-
-            return Either::Left(ResponsePayload::new(
-                false,
-                &(),
-                Some(err_status_code),
-                Some(err.to_string()),
-            ));
-        }
-    };
+    }
 
-    Either::Left(ResponsePayload::new(
-        false,
-        &(),
-        None,
-        Some("Couldn't send file".to_string()),
-    ))
+    Err(ServerErrors::FileSendFailed.into())
 }
 
-#[delete("/{file_id}")]
-async fn delete_file(
+/// the decrypted preview of an image
+///
+/// Only jpeg, png, gif, webp and bmp images have one, videos and the other files answer `ThumbnailNotFound`
+#[utoipa::path(
+    context_path = "/file",
+    tag = "file",
+    params(("file_id" = String, Path, description = "the file id")),
+    responses(
+        (status = 200, content_type = THUMBNAIL_MIME_TYPE, body = Vec<u8>),
+        (status = 404, description = "FileNotFound or ThumbnailNotFound", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
+#[get("/{file_id}/thumbnail")]
+async fn get_thumbnail(
     db: web::Data<IlixDB>,
     file_id: web::Path<String>,
-    key_phrase: KeyPhrase,
-) -> impl Responder {
+    key_phrase: AuthKeyPhrase,
+) -> Result<HttpResponse, ApiError> {
     if is_str_empty(&file_id) {
-        return BAD_ARGS_RESP.clone();
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db.client.remove_transfer_file(&file_id, &key_phrase).await;
-    if let Err(err) = db_result {
-        if err != ServerErrors::NotInTransfer {
-            return ResponsePayload::new(
-                false,
-                &(),
-                Some(StatusCode::CONFLICT),
-                Some(err.to_string()),
-            );
-        }
+    let thumbnail = db.get_thumbnail(&file_id, &key_phrase).await?;
+    METRICS.downloaded(thumbnail.len());
+    Ok(HttpResponse::Ok()
+        .content_type(THUMBNAIL_MIME_TYPE)
+        .body(thumbnail))
+}
+
+/// removes the file from its transfer, the transfer is deleted with its last file
+#[utoipa::path(
+    context_path = "/file",
+    tag = "file",
+    params(("file_id" = String, Path, description = "the file id")),
+    responses(
+        (status = 200, body = ResponsePayload),
+        (status = 404, description = "FileNotFound", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
+#[delete("/{file_id}")]
+async fn delete_file(
+    db: web::Data<IlixDB>,
+    sse: web::Data<Broadcaster>,
+    file_id: web::Path<String>,
+    key_phrase: AuthKeyPhrase,
+) -> ApiResult {
+    if is_str_empty(&file_id) {
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db.client.delete_files(&[file_id.into_inner()]).await;
+    let db_result = db.remove_transfer_file(&file_id, &key_phrase).await;
     match db_result {
-        Ok(_) => ResponsePayload::new(true, &(), None, None),
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::InvalidObjectId => StatusCode::BAD_REQUEST,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-            ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()))
+        Ok(transfer) => {
+            let (sse_kp, file_id) = (key_phrase.clone(), file_id.to_string());
+            tokio::spawn(async move {
+                let to = [transfer.to];
+                let removed = SSEData::FileRemoved(FileRemoved {
+                    transfer_id: transfer._id.clone(),
+                    file_id,
+                });
+                let _ = sse.broadcast_to(&to, &sse_kp, removed).await;
+
+                // it was the last file of the transfer
+                if transfer.files_id.is_empty() {
+                    let deleted = SSEData::TransferDeleted(TransferDeleted {
+                        transfer_id: transfer._id,
+                    });
+                    let _ = sse.broadcast_to(&to, &sse_kp, deleted).await;
+                }
+            });
         }
+        Err(err) if err == ServerErrors::NotInTransfer => {}
+        Err(err) => return Err(err.into()),
     }
+
+    db.delete_files(&[file_id.into_inner()]).await?;
+    Ok(ResponsePayload::new(true, &(), None, None))
 }
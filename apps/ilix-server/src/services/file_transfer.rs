@@ -1,84 +1,106 @@
-use crate::db::collections::FileStorageGridFS;
-use crate::services::{from_multipart, BAD_ARGS_RESP};
-use crate::utils::errors::ServerErrors;
-use crate::utils::keyphrase::KeyPhrase;
-use crate::utils::sse::{Broadcaster, SSEData};
-use crate::{
-    db::{collections::FilePoolTransferCollection, IlixDB},
-    utils::is_str_empty,
-};
+use crate::extractors::keyphrase::AuthKeyPhrase;
+use crate::services::from_multipart;
+use crate::utils::{metrics::METRICS, sse::Broadcaster, uploads::Uploads};
+use crate::{db::IlixDB, utils::is_str_empty};
+use ilix_core::errors::ServerErrors;
+use ilix_core::events::{FileAdded, SSEData, TransferDeleted};
+use ilix_core::storage::{FilePoolTransferCollection, FileStorageGridFS};
 
 use actix_multipart::Multipart;
-use actix_web::{delete, get, http::StatusCode, post, web, Responder};
+use actix_web::{delete, get, post, web};
 use serde::Deserialize;
-
-use super::ResponsePayload;
-
+use utoipa::IntoParams;
+
+use super::{ApiError, ApiResult, ResponsePayload};
+
+/// the transfers sent to the device
+#[utoipa::path(
+    context_path = "/file-transfer",
+    tag = "file-transfer",
+    params(("device_id" = String, Path, description = "the recipient device id")),
+    responses(
+        (status = 200, body = TransfersResponse),
+        (status = 404, description = "PoolNotFound", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[get("/{device_id}/all")]
 async fn get_all_transfer(
     db: web::Data<IlixDB>,
-    key_phrase: KeyPhrase,
+    key_phrase: AuthKeyPhrase,
     device_id: web::Path<String>,
-) -> impl Responder {
+) -> ApiResult {
     if is_str_empty(&device_id) {
-        return BAD_ARGS_RESP.clone();
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db.client.find_transfers(&key_phrase, &device_id).await;
-    match db_result {
-        Ok(datas) => ResponsePayload::new(true, &datas, None, None),
-        Err(err) => ResponsePayload::new(false, &(), None, Some(err.to_string())),
-    }
+    let datas = db.find_transfers(&key_phrase, &device_id).await?;
+    Ok(ResponsePayload::new(true, &datas, None, None))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 struct AddTransferPayload {
+    /// the sender device id
     from: String,
+    /// the recipient device id
     to: String,
 }
 
+/// uploads the files and sends them to a device, returns the transfer id
+#[utoipa::path(
+    context_path = "/file-transfer",
+    tag = "file-transfer",
+    params(AddTransferPayload),
+    request_body(content = FilesUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = StringResponse),
+        (status = 400, description = "BadArgs or InvalidFile", body = ResponsePayload),
+        (status = 404, description = "PoolNotFound or NotInPool", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[post("")]
 async fn create_transfer(
     db: web::Data<IlixDB>,
     sse: web::Data<Broadcaster>,
-    key_phrase: KeyPhrase,
+    uploads: web::Data<Uploads>,
+    key_phrase: AuthKeyPhrase,
     query: web::Query<AddTransferPayload>,
     form: Multipart,
-) -> impl Responder {
+) -> ApiResult {
     if is_str_empty(&query.to) || is_str_empty(&query.from) {
-        return BAD_ARGS_RESP.clone();
+        return Err(ServerErrors::BadArgs.into());
     }
 
     // parse request files
-    let bad_file_resp = ResponsePayload::new(
-        false,
-        &(),
-        Some(StatusCode::BAD_REQUEST),
-        Some("Failed to parse file".to_string()),
-    );
-
-    let files = match from_multipart(form).await {
-        Ok(files) => files,
-        Err(_) => return bad_file_resp,
-    };
+    let files = from_multipart(form).await?;
     if files.is_empty() {
-        return bad_file_resp;
+        return Err(ApiError::with_details(
+            ServerErrors::InvalidFile,
+            "no files",
+        ));
     }
 
-    // add files to db
-    let files_id = match db.client.add_files(files, &key_phrase).await {
-        Ok(files_ids) => files_ids,
-        Err(err) => return ResponsePayload::new(false, &(), None, Some(err.to_string())),
-    };
+    METRICS.uploaded(files.iter().map(|file| file.datas.len()).sum());
+
+    // add files to db, they're deleted if the transfer isn't created (even if the request is dropped)
+    let upload = uploads.start(&db);
+    let (files_id, thumbnails_id) = upload.add_files(files, &key_phrase).await?;
 
     // create transfer with files ids
     let db_result = db
-        .client
-        .create_transfer(&key_phrase, &query.from, &query.to, &files_id)
+        .create_transfer(
+            &key_phrase,
+            &query.from,
+            &query.to,
+            &files_id,
+            &thumbnails_id,
+        )
         .await;
 
     match db_result {
         Ok(transfer) => {
+            upload.commit().await;
             let t_id = transfer._id.clone();
             tokio::spawn(async move {
                 let _ = sse
@@ -89,126 +111,117 @@ async fn create_transfer(
                     )
                     .await;
             });
-            ResponsePayload::new(true, &t_id, None, None)
+            Ok(ResponsePayload::new(true, &t_id, None, None))
         }
         Err(err) => {
-            let _ = db.client.delete_files(&files_id).await; // failed to create transfer, delete all added files
-            let err_status_code = match err {
-                ServerErrors::NotInPool => StatusCode::NOT_FOUND,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-            ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()))
+            upload.rollback().await; // failed to create transfer, delete all added files
+            Err(err.into())
         }
     }
 }
 
 /// attach files to a transfer
+#[utoipa::path(
+    context_path = "/file-transfer",
+    tag = "file-transfer",
+    params(("transfer_id" = String, Path, description = "the transfer id")),
+    request_body(content = FilesUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the ids of the added files", body = StringsResponse),
+        (status = 400, description = "BadArgs or InvalidFile", body = ResponsePayload),
+        (status = 404, description = "TransferNotFound", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[post("/{transfer_id}/add_files")]
 async fn add_files_to_transfer(
     db: web::Data<IlixDB>,
     sse: web::Data<Broadcaster>,
-    key_phrase: KeyPhrase,
+    uploads: web::Data<Uploads>,
+    key_phrase: AuthKeyPhrase,
     transfer_id: web::Path<String>,
     form: Multipart,
-) -> impl Responder {
+) -> ApiResult {
     if is_str_empty(&transfer_id) {
-        return BAD_ARGS_RESP.clone();
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let bad_file_resp = ResponsePayload::new(
-        false,
-        &(),
-        Some(StatusCode::BAD_REQUEST),
-        Some("Failed to parse file".to_string()),
-    );
-
     // parse request files
-    let files = match from_multipart(form).await {
-        Ok(files) => files,
-        Err(_) => return bad_file_resp,
-    };
+    let files = from_multipart(form).await?;
     if files.is_empty() {
-        return bad_file_resp;
+        return Err(ApiError::with_details(
+            ServerErrors::InvalidFile,
+            "no files",
+        ));
     }
 
-    // add files to db
-    let files_id = match db.client.add_files(files, &key_phrase).await {
-        Ok(fids) => fids,
-        Err(err) => return ResponsePayload::new(false, &(), None, Some(err.to_string())),
-    };
+    METRICS.uploaded(files.iter().map(|file| file.datas.len()).sum());
+
+    // add files to db, they're deleted if they aren't added to the transfer (even if the request is dropped)
+    let upload = uploads.start(&db);
+    let (files_id, thumbnails_id) = upload.add_files(files, &key_phrase).await?;
 
     // add files to transfer
     let db_result = db
-        .client
-        .add_files_to_transfer(&files_id, &transfer_id, &key_phrase)
+        .add_files_to_transfer(&files_id, &thumbnails_id, &transfer_id, &key_phrase)
         .await;
 
     match db_result {
         Ok(transfer) => {
+            upload.commit().await;
+            let added = SSEData::FileAdded(FileAdded {
+                transfer_id: transfer._id,
+                files_id: files_id.clone(),
+                thumbnails_id,
+            });
             tokio::spawn(async move {
-                let _ = sse
-                    .broadcast_to(
-                        &[transfer.to.clone()],
-                        &key_phrase,
-                        SSEData::Transfer(transfer),
-                    )
-                    .await;
+                let _ = sse.broadcast_to(&[transfer.to], &key_phrase, added).await;
             });
-            ResponsePayload::new(true, &files_id, None, None)
+            Ok(ResponsePayload::new(true, &files_id, None, None))
         }
         Err(err) => {
-            let _ = db.client.delete_files(&files_id).await; // failed to add transfer, delete all added files
-            let err_status_code = match err {
-                ServerErrors::TransferNotFound => StatusCode::NOT_FOUND,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-            ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()))
+            upload.rollback().await; // failed to add transfer, delete all added files
+            Err(err.into())
         }
     }
 }
 
+/// deletes the transfer and its files
+#[utoipa::path(
+    context_path = "/file-transfer",
+    tag = "file-transfer",
+    params(("device_id" = String, Path, description = "the recipient device id"), ("transfer_id" = String, Path, description = "the transfer id")),
+    responses(
+        (status = 200, body = ResponsePayload),
+        (status = 404, description = "TransferNotFound or NotInTransfer", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[delete("/{device_id}/{transfer_id}")]
 async fn delete_transfer(
     db: web::Data<IlixDB>,
-    key_phrase: KeyPhrase,
+    sse: web::Data<Broadcaster>,
+    key_phrase: AuthKeyPhrase,
     path: web::Path<(String, String)>,
-) -> impl Responder {
+) -> ApiResult {
     let (device_id, transfer_id) = path.into_inner();
 
     if is_str_empty(&device_id) || is_str_empty(&transfer_id) {
-        return BAD_ARGS_RESP.clone();
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db
-        .client
+    let files_id_to_delete = db
         .delete_transfer(&key_phrase, &device_id, &transfer_id)
-        .await;
-
-    let files_id_to_delete = match db_result {
-        Ok(fid) => fid,
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::TransferNotFound => StatusCode::NOT_FOUND,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-
-            return ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()));
-        }
-    };
-
-    if let Err(err) = db.client.delete_files(&files_id_to_delete).await {
-        let err_status_code = match err {
-            ServerErrors::InvalidObjectId => StatusCode::BAD_REQUEST,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-
-        ResponsePayload::new(
-            false,
-            &(),
-            Some(err_status_code),
-            Some("Transfer was deleted but some files were not deleted".to_string()),
-        )
-    } else {
-        ResponsePayload::new(true, &(), None, None)
-    }
+        .await?;
+
+    let sse_kp = key_phrase.clone();
+    tokio::spawn(async move {
+        let deleted = SSEData::TransferDeleted(TransferDeleted { transfer_id });
+        let _ = sse.broadcast_to(&[device_id], &sse_kp, deleted).await;
+    });
+
+    db.delete_files(&files_id_to_delete).await.map_err(|err| {
+        ApiError::with_details(err, "Transfer was deleted but some files were not deleted")
+    })?;
+    Ok(ResponsePayload::new(true, &(), None, None))
 }
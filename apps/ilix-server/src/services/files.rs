@@ -1,20 +1,21 @@
 use std::{fmt, str::FromStr};
 
-use actix_web::{get, http::StatusCode, web, Responder};
+use actix_web::{get, web};
 use mongodb::bson::oid::ObjectId;
 use serde::{de, Deserialize};
+use utoipa::IntoParams;
 
-use crate::{
-    db::{collections::FileStorageGridFS, IlixDB},
-    services::BAD_ARGS_RESP,
-    utils::errors::ServerErrors,
-};
+use ilix_core::{errors::ServerErrors, storage::FileStorageGridFS};
 
-use super::ResponsePayload;
+use crate::{db::IlixDB, extractors::keyphrase::AuthKeyPhrase};
 
-#[derive(Deserialize)]
+use super::{ApiResult, ResponsePayload};
+
+#[derive(Deserialize, IntoParams)]
 struct GetFilesInfoPayload {
+    /// comma separated files ids
     #[serde(deserialize_with = "deserialize_stringified_files_ids_list")]
+    #[param(value_type = String)]
     files_ids: Vec<String>,
 }
 fn deserialize_stringified_files_ids_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
@@ -46,24 +47,27 @@ where
     deserializer.deserialize_any(StringVecVisitor)
 }
 
+#[utoipa::path(
+    context_path = "/files",
+    tag = "file",
+    params(GetFilesInfoPayload),
+    responses(
+        (status = 200, body = FilesInfoResponse),
+        (status = 400, description = "BadArgs", body = ResponsePayload),
+        (status = 404, description = "FileNotFound, also for the files of another pool", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[get("/info")]
 async fn get_files_info(
     db: web::Data<IlixDB>,
     query: web::Query<GetFilesInfoPayload>,
-) -> impl Responder {
+    key_phrase: AuthKeyPhrase,
+) -> ApiResult {
     if query.files_ids.is_empty() {
-        return BAD_ARGS_RESP.clone();
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db.client.get_files_info(&query.files_ids).await;
-    match db_result {
-        Ok(files_info) => ResponsePayload::new(true, &files_info, None, None),
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::InvalidObjectId => StatusCode::BAD_REQUEST,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-            ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()))
-        }
-    }
+    let files_info = db.get_files_info(&query.files_ids, &key_phrase).await?;
+    Ok(ResponsePayload::new(true, &files_info, None, None))
 }
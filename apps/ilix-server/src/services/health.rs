@@ -0,0 +1,138 @@
+use std::{future::Future, time::Duration};
+
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+
+use ilix_core::errors::ServerError;
+
+use crate::{
+    config::Config,
+    db::IlixDB,
+    utils::{metrics::METRICS, sse::Broadcaster},
+};
+
+/// the process is up, it doesn't check its dependencies (see `/readyz`) so that it isn't restarted when mongodb is down
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    /// "ok", or why the check failed
+    database: String,
+    storage: String,
+    shutting_down: bool,
+}
+
+/// the instance can handle requests: mongodb and the gridfs storage answer, and it isn't shutting down
+#[get("/readyz")]
+async fn readyz(
+    db: web::Data<IlixDB>,
+    sse: web::Data<Broadcaster>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    let timeout = config.timeouts.readiness_timeout;
+    let (database, storage) =
+        futures_util::join!(check(timeout, db.ping()), check(timeout, db.ping_storage()));
+    let shutting_down = sse.is_shutting_down();
+
+    let ready = database.is_ok() && storage.is_ok() && !shutting_down;
+    let readiness = Readiness {
+        ready,
+        database: database.err().unwrap_or_else(|| "ok".to_string()),
+        storage: storage.err().unwrap_or_else(|| "ok".to_string()),
+        shutting_down,
+    };
+    match ready {
+        true => HttpResponse::Ok().json(readiness),
+        false => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
+
+async fn check(
+    timeout: Duration,
+    ping: impl Future<Output = Result<(), ServerError>>,
+) -> Result<(), String> {
+    match tokio::time::timeout(timeout, ping).await {
+        Ok(Ok(())) => Ok(()),
+        // only the kind, like the error responses
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(format!("no answer after {timeout:?}")),
+    }
+}
+
+/// the metrics of this instance, in the prometheus text format
+#[get("/metrics")]
+async fn metrics(sse: web::Data<Broadcaster>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(METRICS.render(sse.connection_stats()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{http::StatusCode, test as actix_test, web, App};
+    use mongodb::Client;
+
+    use crate::{
+        config::Config,
+        db::IlixDB,
+        utils::{pubsub::InProcessPubSub, sse::Broadcaster},
+    };
+
+    use super::{healthz, metrics, readyz};
+
+    #[actix_web::test]
+    async fn health_test() {
+        // nothing listens there, the readiness check must fail
+        let db = IlixDB {
+            client: Client::with_uri_str("mongodb://localhost:1").await.unwrap(),
+            ..IlixDB::test().await
+        };
+        let mut config = Config::default();
+        config.timeouts.readiness_timeout = Duration::from_millis(200);
+        let sse = Broadcaster::create(db.clone(), Box::<InProcessPubSub>::default(), &config)
+            .await
+            .unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::from(sse))
+                .service(healthz)
+                .service(readyz)
+                .service(metrics),
+        )
+        .await;
+
+        let res = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::get().uri("/healthz").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::get().uri("/readyz").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["ready"], false);
+        assert_ne!(body["database"], "ok");
+
+        let res = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::get().uri("/metrics").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = actix_test::read_body(res).await;
+        assert!(String::from_utf8_lossy(&body).contains("ilix_sse_connections 0"));
+    }
+}
@@ -2,42 +2,168 @@ pub mod events;
 pub mod file;
 pub mod file_transfer;
 pub mod files;
+pub mod health;
+pub mod openapi;
 pub mod pool;
+pub mod ws;
 
-use std::{fmt::Display, io::Read};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, Read},
+};
 
-use actix_multipart::Multipart;
+use actix_multipart::{Multipart, MultipartError};
 use actix_web::{
     body::BoxBody,
-    http::{header::ContentType, StatusCode},
-    web::Buf,
+    http::{
+        header::{self, ContentType},
+        StatusCode,
+    },
+    web::{self, Buf},
     HttpRequest, HttpResponse, HttpResponseBuilder, Responder, ResponseError,
 };
-use anyhow::{anyhow, Result};
-use once_cell::sync::Lazy;
 use serde::Serialize;
+use serde_json::value::{to_raw_value, RawValue};
 use tokio_stream::StreamExt;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-static BAD_ARGS_RESP: Lazy<ResponsePayload> = Lazy::new(|| {
-    ResponsePayload::new(
-        false,
-        &(),
-        Some(StatusCode::BAD_REQUEST),
-        Some("Bad Args".to_string()),
-    )
-});
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    models::ClientFileMetadata,
+    storage::UploadedFile,
+};
+
+use crate::{
+    middlewares::{api_version::current_api_version, request_id::current_request_id},
+    services::{
+        events::event_stream,
+        file::{delete_file, get_file, get_thumbnail},
+        file_transfer::{
+            add_files_to_transfer, create_transfer, delete_transfer, get_all_transfer,
+        },
+        files::get_files_info,
+        pool::{delete_pool, get_pool, join_pool, leave_pool, new_pool, rename_pool},
+        ws::ws_stream,
+    },
+    utils::{console_log, filename::sanitize_filename},
+};
+
+/// what the json handlers return
+pub type ApiResult = Result<ResponsePayload, ApiError>;
+
+/// the media type asking for the v2 envelope on the unprefixed routes
+pub const V2_MEDIA_TYPE: &str = "application/vnd.ilix.v2+json";
+
+/// The v1 api, mounted under `/v1` and, for the clients that can't be updated, unprefixed
+pub fn v1_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/pool").configure(pool_routes))
+        .service(web::scope("/file-transfer").configure(file_transfer_routes))
+        .service(web::scope("/file").configure(file_routes))
+        .service(web::scope("/files").configure(files_routes))
+        .service(event_stream)
+        .service(ws_stream);
+}
+
+/// The v2 api, mounted under `/v2`. Only the envelope changes for now (see [`ApiVersion`]).
+///
+/// A handler whose contract changes in v2 is registered in its scope before the v1 routes, e.g:
+/// `web::scope("/pool").service(v2::get_pool).configure(pool_routes)`, the first matching route wins
+pub fn v2_routes(cfg: &mut web::ServiceConfig) {
+    v1_routes(cfg);
+}
+
+fn pool_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(new_pool)
+        .service(get_pool)
+        .service(join_pool)
+        .service(leave_pool)
+        .service(rename_pool)
+        .service(delete_pool);
+}
+
+fn file_transfer_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_all_transfer)
+        .service(create_transfer)
+        .service(add_files_to_transfer)
+        .service(delete_transfer);
+}
+
+fn file_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_file)
+        .service(get_thumbnail)
+        .service(delete_file);
+}
+
+fn files_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_files_info);
+}
+
+/// The shape of the json responses.
+///
+/// In v1 `data` is a json string that the client has to parse again (kept for the old mobile builds), in v2 it's the
+/// json value itself
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    /// picked by the route prefix (`/v1` or `/v2`), an unprefixed route is v2 only if the client accepts
+    /// [`V2_MEDIA_TYPE`]
+    pub fn of(req: &HttpRequest) -> Self {
+        let prefixed = |prefix: &str| {
+            req.path()
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        if prefixed("/v1") {
+            return Self::V1;
+        }
+        if prefixed("/v2") {
+            return Self::V2;
+        }
+
+        let accepted = req
+            .headers()
+            .get_all(header::ACCEPT)
+            .filter_map(|accept| accept.to_str().ok())
+            .any(|accept| accept.contains(V2_MEDIA_TYPE));
+        match accepted {
+            true => Self::V2,
+            false => Self::V1,
+        }
+    }
+}
 
-#[derive(Serialize, Clone, Debug)]
+/// The response envelope, on errors and when there is no data
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub struct ResponsePayload {
     success: bool,
     status_code: u16,
 
+    /// the stable error code, e.g: "PoolNotFound"
     #[serde(skip_serializing_if = "Option::is_none")]
     reason: Option<String>,
 
+    /// human readable explanation of the error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+
+    /// what exactly went wrong, when there is more to say than the error code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<String>,
+
+    /// to find the cause of an error in the server logs
     #[serde(skip_serializing_if = "Option::is_none")]
-    data: Option<String>,
+    request_id: Option<String>,
+
+    /// already serialized, it's embedded as is (v2) or as a string (v1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Value>)]
+    data: Option<Box<RawValue>>,
 }
 
 impl ResponsePayload {
@@ -48,8 +174,8 @@ impl ResponsePayload {
         mut error_reason: Option<String>,
     ) -> Self {
         let data = match success {
-            true => match serde_json::to_string(data) {
-                Ok(json_str) => Some(json_str),
+            true => match to_raw_value(data) {
+                Ok(json) => Some(json),
                 Err(err) => {
                     success = false;
                     error_status = Some(StatusCode::INTERNAL_SERVER_ERROR);
@@ -73,6 +199,24 @@ impl ResponsePayload {
                 true => None,
                 false => Some(error_reason.unwrap_or("No error message provided".to_string())),
             },
+            message: None,
+            details: None,
+            request_id: None,
+        }
+    }
+}
+
+impl From<&ApiError> for ResponsePayload {
+    fn from(err: &ApiError) -> Self {
+        let kind = err.error.kind;
+        Self {
+            success: false,
+            status_code: kind.status_code().as_u16(),
+            reason: Some(kind.to_string()),
+            message: Some(kind.message().to_string()),
+            details: err.details.clone(),
+            request_id: current_request_id(),
+            data: None,
         }
     }
 }
@@ -80,16 +224,29 @@ impl ResponsePayload {
 impl Responder for ResponsePayload {
     type Body = BoxBody;
 
-    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
         // Create response and set content type
         let statuc_code = StatusCode::from_u16(self.status_code).unwrap_or(if self.success {
             StatusCode::OK
         } else {
             StatusCode::INTERNAL_SERVER_ERROR
         });
-        HttpResponseBuilder::new(statuc_code)
-            .content_type(ContentType::json())
-            .json(self)
+        let mut res = HttpResponseBuilder::new(statuc_code);
+        match ApiVersion::of(req) {
+            ApiVersion::V1 => res.content_type(ContentType::json()).json(self.into_v1()),
+            ApiVersion::V2 => res.content_type(V2_MEDIA_TYPE).json(self),
+        }
+    }
+}
+
+impl ResponsePayload {
+    /// `data` as a json string
+    fn into_v1(self) -> Self {
+        let data = self.data.map(|json| {
+            // serializing a string can't fail
+            to_raw_value(json.get()).expect("failed to stringify the response data")
+        });
+        Self { data, ..self }
     }
 }
 
@@ -99,35 +256,253 @@ impl Display for ResponsePayload {
     }
 }
 
-impl ResponseError for ResponsePayload {
+/// The error returned by every handler (and extractor).
+///
+/// Its status is fixed by the error variant, see [`ServerErrors::status_code`]
+#[derive(Clone, Debug)]
+pub struct ApiError {
+    /// its cause is logged but never sent to the client
+    pub error: ServerError,
+    pub details: Option<String>,
+}
+
+impl ApiError {
+    pub fn with_details(error: impl Into<ServerError>, details: impl ToString) -> Self {
+        Self {
+            error: error.into(),
+            details: Some(details.to_string()),
+        }
+    }
+}
+
+impl From<ServerErrors> for ApiError {
+    fn from(error: ServerErrors) -> Self {
+        ServerError::from(error).into()
+    }
+}
+
+impl From<ServerError> for ApiError {
+    fn from(error: ServerError) -> Self {
+        Self {
+            error,
+            details: None,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.details {
+            Some(details) => write!(f, "{}: {details}", self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.error.kind.status_code()
+    }
+
     fn error_response(&self) -> HttpResponse<BoxBody> {
-        let status_code = StatusCode::from_u16(self.status_code).unwrap_or(Self::status_code(self));
-        HttpResponseBuilder::new(status_code)
-            .content_type(ContentType::json())
-            .json(self)
+        // client errors are expected, only the server ones need an operator
+        let lvl = match self.status_code().is_server_error() {
+            true => log::Level::Error,
+            false => log::Level::Info,
+        };
+        let details = self
+            .details
+            .as_ref()
+            .map(|details| format!(" ({details})"))
+            .unwrap_or_default();
+        console_log(&format!("{}{details}", self.error.chain()), lvl);
+
+        // there is no `data` in an error, only the content type differs between the versions
+        let mut res = HttpResponseBuilder::new(self.status_code());
+        match current_api_version() {
+            ApiVersion::V1 => res.content_type(ContentType::json()),
+            ApiVersion::V2 => res.content_type(V2_MEDIA_TYPE),
+        }
+        .json(ResponsePayload::from(self))
     }
 }
 
-pub async fn from_multipart(mut form: Multipart) -> Result<Vec<(String, Vec<u8>)>> {
+/// suffix of the form fields carrying a file's client metadata (as json), e.g: `file-0.metadata` for the `file-0` field
+const METADATA_FIELD_SUFFIX: &str = ".metadata";
+
+/// `MultipartError` isn't `Sync`, so only its message is kept as the cause
+fn multipart_err(err: MultipartError) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+pub async fn from_multipart(mut form: Multipart) -> Result<Vec<UploadedFile>, ServerError> {
     let mut files = vec![];
+    let mut metadatas = HashMap::new();
     // iterate over multipart stream
-    while let Some(mut field) = form.try_next().await.map_err(|_| anyhow!(""))? {
+    while let Some(mut field) = form
+        .try_next()
+        .await
+        .map_err(multipart_err)
+        .server_err(ServerErrors::InvalidFile)?
+    {
         // A multipart/form-data stream has to contain `content_disposition`
 
         let mut file_buf = vec![];
         // Field in turn is stream of *Bytes* object
-        while let Some(chunk) = field.try_next().await.map_err(|_| anyhow!(""))? {
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(multipart_err)
+            .server_err(ServerErrors::InvalidFile)?
+        {
             let mut reader = chunk.reader();
             let _ = reader.read_to_end(&mut file_buf);
         }
 
-        let filename = field
-            .content_disposition()
-            .get_filename()
-            .unwrap_or(&Uuid::new_v4().to_string())
+        let content_disposition = field.content_disposition();
+        let field_name = content_disposition
+            .get_name()
+            .unwrap_or_default()
             .to_string();
+        if let (None, Some(file_field)) = (
+            content_disposition.get_filename(),
+            field_name.strip_suffix(METADATA_FIELD_SUFFIX),
+        ) {
+            // it's optional, a malformed one doesn't cost the client its upload
+            match serde_json::from_slice::<ClientFileMetadata>(&file_buf) {
+                Ok(client_metadata) => {
+                    metadatas.insert(file_field.to_string(), client_metadata);
+                }
+                Err(err) => console_log(
+                    &format!("ignored the malformed metadata of '{file_field}': {err}"),
+                    log::Level::Warn,
+                ),
+            }
+            continue;
+        }
+
+        let filename = sanitize_filename(
+            content_disposition
+                .get_filename()
+                .unwrap_or(&Uuid::new_v4().to_string()),
+        );
+
+        files.push((
+            field_name,
+            UploadedFile {
+                filename,
+                datas: file_buf,
+                content_type: field.content_type().map(|mime| mime.to_string()),
+                client_metadata: ClientFileMetadata::default(),
+            },
+        ));
+    }
+
+    Ok(files
+        .into_iter()
+        .map(|(field_name, mut file)| {
+            if let Some(client_metadata) = metadatas.remove(&field_name) {
+                file.client_metadata = client_metadata;
+            }
+            file
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::to_bytes,
+        http::{header, StatusCode},
+        test::{self as actix_test, TestRequest},
+        web, App, HttpResponse, Responder,
+    };
+    use actix_web_lab::middleware::from_fn;
+    use serde_json::{json, Value};
+
+    use ilix_core::errors::ServerErrors;
+
+    use crate::middlewares::api_version::api_version;
+
+    use super::{ApiError, ApiVersion, ResponsePayload, V2_MEDIA_TYPE};
+
+    #[actix_web::test]
+    async fn response_payload_versions_test() {
+        let payload = ResponsePayload::new(true, &json!({"pool_name": "ilovecat"}), None, None);
+        let body = |req: TestRequest| {
+            let res = payload.clone().respond_to(&req.to_http_request());
+            async move {
+                serde_json::from_slice::<Value>(&to_bytes(res.into_body()).await.unwrap()).unwrap()
+            }
+        };
+
+        // v1: stringified
+        let req = TestRequest::get().uri("/pool");
+        assert_eq!(ApiVersion::of(&req.to_http_request()), ApiVersion::V1);
+        let v1 = body(TestRequest::get().uri("/pool")).await;
+        assert_eq!(v1["data"], json!(r#"{"pool_name":"ilovecat"}"#));
+
+        // v2: embedded, with the prefix or the accept header
+        let v2 = body(TestRequest::get().uri("/v2/pool")).await;
+        assert_eq!(v2["data"], json!({"pool_name": "ilovecat"}));
+        let v2 = body(
+            TestRequest::get()
+                .uri("/pool")
+                .insert_header((header::ACCEPT, V2_MEDIA_TYPE)),
+        )
+        .await;
+        assert_eq!(v2["data"], json!({"pool_name": "ilovecat"}));
+        assert_eq!(v2["success"], json!(true));
+
+        let version = |req: TestRequest| ApiVersion::of(&req.to_http_request());
+        assert_eq!(version(TestRequest::get().uri("/v2pool")), ApiVersion::V1);
+        assert_eq!(
+            version(
+                TestRequest::get()
+                    .uri("/v1/pool")
+                    .insert_header((header::ACCEPT, V2_MEDIA_TYPE))
+            ),
+            ApiVersion::V1
+        );
+    }
+    #[actix_web::test]
+    async fn api_error_versions_test() {
+        let app = actix_test::init_service(App::new().wrap(from_fn(api_version)).default_service(
+            web::to(|| async {
+                Err::<HttpResponse, _>(ApiError::from(ServerErrors::PoolNotFound))
+            }),
+        ))
+        .await;
+        let content_type = |req: TestRequest| {
+            let app = &app;
+            async move {
+                let res = actix_test::call_service(app, req.to_request()).await;
+                assert_eq!(res.status(), StatusCode::NOT_FOUND);
+                res.headers()
+                    .get(header::CONTENT_TYPE)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
 
-        files.push((filename, file_buf));
+        assert_eq!(
+            content_type(TestRequest::get().uri("/pool")).await,
+            "application/json"
+        );
+        assert_eq!(
+            content_type(TestRequest::get().uri("/v2/pool")).await,
+            V2_MEDIA_TYPE
+        );
+        assert_eq!(
+            content_type(
+                TestRequest::get()
+                    .uri("/pool")
+                    .insert_header((header::ACCEPT, V2_MEDIA_TYPE))
+            )
+            .await,
+            V2_MEDIA_TYPE
+        );
     }
-    Ok(files)
 }
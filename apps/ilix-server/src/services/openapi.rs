@@ -0,0 +1,376 @@
+use actix_web::{
+    get,
+    http::header::{self, ContentType},
+    HttpResponse,
+};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    Modify, OpenApi, ToSchema,
+};
+
+use ilix_core::{
+    compression::Codec,
+    events::{
+        DeviceJoined, DeviceLeft, FileAdded, FileRemoved, PoolRenamed, Presence, SSEData,
+        TransferAck, TransferDeleted,
+    },
+    models::{
+        ClientFileMetadata, DevicesPool, FileInfo, FileMetadata, FilePoolTransferExt,
+        NewPoolPayload,
+    },
+};
+
+use crate::services::{
+    events, file, file_transfer, files,
+    pool::{self, JoinPoolPayload, LeavePoolPayload, RenamePoolPayload},
+    ws, ResponsePayload,
+};
+
+/// The OpenAPI document of the v2 api, generated from the handlers annotations.
+///
+/// The v1 routes are the same but their `data` is a json string
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "ilix",
+        description = "Share files between the devices of a pool. The pool is identified by its key phrase, sent in the \
+            `Authorization` header"
+    ),
+    servers(
+        (url = "/v2", description = "`data` is a json value"),
+        (url = "/v1", description = "`data` is a json string, also served unprefixed (deprecated)"),
+    ),
+    paths(
+        pool::get_pool,
+        pool::join_pool,
+        pool::leave_pool,
+        pool::rename_pool,
+        pool::new_pool,
+        pool::delete_pool,
+        file_transfer::get_all_transfer,
+        file_transfer::create_transfer,
+        file_transfer::add_files_to_transfer,
+        file_transfer::delete_transfer,
+        file::get_file,
+        file::get_thumbnail,
+        file::delete_file,
+        files::get_files_info,
+        events::event_stream,
+        ws::ws_stream,
+    ),
+    components(schemas(
+        ResponsePayload,
+        PoolResponse,
+        StringResponse,
+        StringsResponse,
+        TransfersResponse,
+        FilesInfoResponse,
+        JoinPoolPayload,
+        LeavePoolPayload,
+        RenamePoolPayload,
+        NewPoolPayload,
+        FilesUpload,
+        DevicesPool,
+        FilePoolTransferExt,
+        FileInfo,
+        FileMetadata,
+        ClientFileMetadata,
+        Codec,
+        SSEData,
+        TransferAck,
+        TransferDeleted,
+        FileAdded,
+        FileRemoved,
+        DeviceJoined,
+        DeviceLeft,
+        PoolRenamed,
+        Presence,
+    )),
+    modifiers(&KeyPhraseAuth),
+    tags(
+        (name = "pool"),
+        (name = "file-transfer"),
+        (name = "file"),
+        (name = "events", description = "the pool events, through sse or websocket"),
+    )
+)]
+pub struct ApiDoc;
+
+/// [`ResponsePayload`] with its `data` typed, only for the documentation
+#[derive(ToSchema)]
+#[allow(dead_code)]
+#[aliases(
+    PoolResponse = ApiResponse<DevicesPool>,
+    StringResponse = ApiResponse<String>,
+    StringsResponse = ApiResponse<Vec<String>>,
+    TransfersResponse = ApiResponse<Vec<FilePoolTransferExt>>,
+    FilesInfoResponse = ApiResponse<Vec<FileInfo>>,
+)]
+pub struct ApiResponse<T> {
+    success: bool,
+    status_code: u16,
+    data: T,
+}
+
+/// The uploaded files, one field per file. A file can come with its [`ClientFileMetadata`] as json, in a field named
+/// `<file field>.metadata`
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct FilesUpload {
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+    #[schema(rename = "file.metadata", value_type = Option<ClientFileMetadata>)]
+    file_metadata: Option<ClientFileMetadata>,
+}
+
+/// the key phrase of the pool, in the `Authorization` header
+struct KeyPhraseAuth;
+
+impl Modify for KeyPhraseAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "key_phrase",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("Authorization"))),
+        );
+    }
+}
+
+/// The docs page, rendered once from the OpenAPI document.
+///
+/// It's plain html without any script: nothing from a third-party runs on this origin, and it works offline
+static DOCS_PAGE: Lazy<String> = Lazy::new(|| {
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap_or_default();
+    let text = |value: &Value| escape(value.as_str().unwrap_or_default());
+
+    let mut page = format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><title>ilix api</title><style>{DOCS_STYLE}</style>\
+            </head><body><h1>ilix api</h1><p>{}</p><p>The raw document is at <a href=\"/openapi.json\">\
+            /openapi.json</a>.</p>",
+        text(&spec["info"]["description"])
+    );
+
+    for (path, operations) in spec["paths"].as_object().into_iter().flatten() {
+        for (method, operation) in operations.as_object().into_iter().flatten() {
+            page += &format!(
+                "<section><h2><code>{} {}</code></h2><p>{}</p><p>{}</p>",
+                method.to_uppercase(),
+                escape(path),
+                text(&operation["summary"]),
+                text(&operation["description"])
+            );
+            if operation.get("security").is_some() {
+                page +=
+                    "<p><em>needs the key phrase in the <code>Authorization</code> header</em></p>";
+            }
+            let parameters = operation["parameters"].as_array().into_iter().flatten();
+            for parameter in parameters {
+                let required = match parameter["required"] {
+                    Value::Bool(true) => " (required)",
+                    _ => "",
+                };
+                page += &format!(
+                    "<p>{} <code>{}</code>{required}: {}</p>",
+                    text(&parameter["in"]),
+                    text(&parameter["name"]),
+                    text(&parameter["description"])
+                );
+            }
+            if let Some(body) = operation.get("requestBody") {
+                page += &format!("<p>body: {}</p>", content_schemas(&body["content"]));
+            }
+            page += "<ul>";
+            for (status, response) in operation["responses"].as_object().into_iter().flatten() {
+                page += &format!(
+                    "<li><code>{}</code> {} {}</li>",
+                    escape(status),
+                    text(&response["description"]),
+                    content_schemas(&response["content"])
+                );
+            }
+            page += "</ul></section>";
+        }
+    }
+
+    page += "<h2>Schemas</h2>";
+    for (name, schema) in spec["components"]["schemas"]
+        .as_object()
+        .into_iter()
+        .flatten()
+    {
+        page += &format!(
+            "<section id=\"{0}\"><h3>{0}</h3><pre>{1}</pre></section>",
+            escape(name),
+            escape(&serde_json::to_string_pretty(schema).unwrap_or_default())
+        );
+    }
+    page + "</body></html>"
+});
+
+const DOCS_STYLE: &str = "body{font-family:sans-serif;max-width:60rem;margin:auto;padding:1rem}\
+    section{border-top:1px solid #ddd}pre{background:#f6f6f6;padding:.5rem;overflow:auto}";
+
+/// the content types of a request or response body, with a link to their schema
+fn content_schemas(content: &Value) -> String {
+    content
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(content_type, media)| {
+            let schema = media["schema"]["$ref"]
+                .as_str()
+                .and_then(|schema_ref| schema_ref.strip_prefix("#/components/schemas/"))
+                .map(|name| format!(" <a href=\"#{0}\">{0}</a>", escape(name)))
+                .unwrap_or_default();
+            format!("<code>{}</code>{schema}", escape(content_type))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[get("/openapi.json")]
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// renders the OpenAPI document
+#[get("/docs")]
+async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        // the page has no script, none can be injected either
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; style-src 'unsafe-inline'",
+        ))
+        .body(DOCS_PAGE.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{Method, StatusCode},
+        test as actix_test, web, App,
+    };
+    use utoipa::OpenApi;
+
+    use crate::{
+        config::Config,
+        db::IlixDB,
+        services::v2_routes,
+        utils::{
+            pubsub::InProcessPubSub, sse::Broadcaster, thumbnail::THUMBNAIL_MIME_TYPE,
+            uploads::Uploads,
+        },
+    };
+
+    use super::{escape, ApiDoc, DOCS_PAGE};
+
+    /// every route macro of the handlers, they all must be documented
+    const HANDLERS_SOURCES: [&str; 6] = [
+        include_str!("events.rs"),
+        include_str!("file.rs"),
+        include_str!("file_transfer.rs"),
+        include_str!("files.rs"),
+        include_str!("pool.rs"),
+        include_str!("ws.rs"),
+    ];
+
+    #[test]
+    fn docs_page_test() {
+        let page = DOCS_PAGE.as_str();
+        assert!(!page.contains("<script"));
+        assert!(page.contains("<code>GET /pool</code>"));
+        assert!(page.contains("<code>PUT /pool/rename</code>"));
+        assert!(page.contains("<a href=\"#PoolResponse\">PoolResponse</a>"));
+        assert!(page.contains("<section id=\"DevicesPool\">"));
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[actix_web::test]
+    async fn openapi_drift_test() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        // every `$ref` points to a registered schema
+        let json = spec.to_string();
+        for schema_ref in json.split("\"$ref\":\"#/components/schemas/").skip(1) {
+            let name = &schema_ref[..schema_ref.find('"').unwrap()];
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "the schema {name} isn't registered in ApiDoc"
+            );
+        }
+
+        // every handler is documented
+        let route_macros = HANDLERS_SOURCES
+            .iter()
+            .flat_map(|src| src.lines())
+            .filter(|line| {
+                ["#[get(", "#[post(", "#[put(", "#[delete("]
+                    .iter()
+                    .any(|route_macro| line.starts_with(route_macro))
+            })
+            .count();
+        let operations = spec["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|path| path.as_object().unwrap().len())
+            .sum::<usize>();
+        assert_eq!(
+            operations, route_macros,
+            "a handler isn't in ApiDoc's paths (or lacks #[utoipa::path])"
+        );
+        // documented as what it sends
+        let thumbnail = &spec["paths"]["/file/{file_id}/thumbnail"]["get"]["responses"]["200"];
+        assert!(thumbnail["content"].get(THUMBNAIL_MIME_TYPE).is_some());
+
+        // every documented operation is routed, the handlers fail on their extractors before touching the db
+        let db = IlixDB::test().await;
+        let config = Config::default();
+        let sse = Broadcaster::create(db.clone(), Box::<InProcessPubSub>::default(), &config)
+            .await
+            .unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::from(sse))
+                .app_data(web::Data::new(Uploads::default()))
+                .service(web::scope("/v2").configure(v2_routes)),
+        )
+        .await;
+
+        for (path, operations) in spec["paths"].as_object().unwrap() {
+            let uri = format!("/v2{}", path.replace(['{', '}'], ""));
+            for method in operations.as_object().unwrap().keys() {
+                let req = actix_test::TestRequest::default()
+                    .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                    .uri(&uri)
+                    .to_request();
+                let res = actix_test::call_service(&app, req).await;
+                // a missing route is an empty 404 (or a 405), the handlers errors have a body
+                let status = res.status();
+                let body = actix_test::read_body(res).await;
+                assert!(
+                    status != StatusCode::METHOD_NOT_ALLOWED
+                        && (status != StatusCode::NOT_FOUND || !body.is_empty()),
+                    "{} {uri} isn't routed",
+                    method.to_uppercase()
+                );
+            }
+        }
+    }
+}
@@ -1,177 +1,251 @@
-use actix_web::{delete, get, http::StatusCode, post, put, web, Responder};
+use actix_web::{delete, get, post, put, web};
 use serde::Deserialize;
+use utoipa::ToSchema;
 
-use crate::{
-    db::{collections::DevicePoolsCollection, IlixDB},
-    services::BAD_ARGS_RESP,
-    utils::{
-        errors::ServerErrors,
-        is_str_empty,
-        keyphrase::KeyPhrase,
-        sse::{Broadcaster, SSEData},
-    },
+use ilix_core::{
+    errors::ServerErrors,
+    events::{DeviceJoined, DeviceLeft, PoolRenamed, SSEData, TransferDeleted},
+    models::NewPoolPayload,
+    storage::DevicePoolsCollection,
 };
 
-use super::ResponsePayload;
+use crate::{
+    config::Config,
+    db::IlixDB,
+    extractors::keyphrase::AuthKeyPhrase,
+    utils::{console_log, is_str_empty, sse::Broadcaster},
+};
 
+use super::{ApiResult, ResponsePayload};
+
+/// the pool of the key phrase, with its online devices
+#[utoipa::path(
+    context_path = "/pool",
+    tag = "pool",
+    responses(
+        (status = 200, body = PoolResponse),
+        (status = 404, description = "PoolNotFound", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[get("")]
-async fn get_pool(db: web::Data<IlixDB>, key_phrase: KeyPhrase) -> impl Responder {
-    let db_result = db.client.get_pool(&key_phrase).await;
-    match db_result {
-        Ok(datas) => ResponsePayload::new(true, &datas, None, None),
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::PoolNotFound => Some(StatusCode::NOT_FOUND),
-                _ => None,
-            };
-            ResponsePayload::new(false, &(), err_status_code, Some(err.to_string()))
-        }
-    }
+async fn get_pool(
+    db: web::Data<IlixDB>,
+    sse: web::Data<Broadcaster>,
+    key_phrase: AuthKeyPhrase,
+) -> ApiResult {
+    let mut datas = db.get_pool(&key_phrase).await?;
+    // a device can be connected to another instance
+    datas.devices_online = sse.online_devices(&key_phrase).await.unwrap_or_else(|err| {
+        console_log(
+            &format!("failed to read the presence: {err}"),
+            log::Level::Warn,
+        );
+        sse.connected_devices(&key_phrase)
+    });
+    Ok(ResponsePayload::new(true, &datas, None, None))
 }
 
-#[derive(Deserialize)]
-struct JoinPoolPayload {
+#[derive(Deserialize, ToSchema)]
+pub struct JoinPoolPayload {
     device_id: String,
     device_name: String,
 }
 
+#[utoipa::path(
+    context_path = "/pool",
+    tag = "pool",
+    request_body = JoinPoolPayload,
+    responses(
+        (status = 200, body = PoolResponse),
+        (status = 400, description = "BadArgs", body = ResponsePayload),
+        (status = 409, description = "AlreadyInPool", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[put("/join")]
 async fn join_pool(
     db: web::Data<IlixDB>,
     sse: web::Data<Broadcaster>,
+    config: web::Data<Config>,
     info: web::Json<JoinPoolPayload>,
-    key_phrase: KeyPhrase,
-) -> impl Responder {
-    if is_str_empty(&info.device_id) {
-        return BAD_ARGS_RESP.clone();
+    key_phrase: AuthKeyPhrase,
+) -> ApiResult {
+    if is_str_empty(&info.device_id) || info.device_name.len() > config.limits.max_device_name_len {
+        return Err(ServerErrors::BadArgs.into());
     }
 
     let info = info.0;
-    let db_result = db
-        .client
+    let datas = db
         .join_pool(&key_phrase, &info.device_id, &info.device_name)
-        .await;
-
-    match db_result {
-        Ok(datas) => {
-            let sse_data = datas.clone();
-            tokio::spawn(async move {
-                let _ = sse
-                    .broadcast_to(
-                        &sse_data.devices_id.clone(),
-                        &key_phrase,
-                        SSEData::Pool(sse_data),
-                    )
-                    .await;
-            });
-            ResponsePayload::new(true, &datas, None, None)
-        }
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::AlreadyInPool => StatusCode::CONFLICT,
-                ServerErrors::PoolNotFound => StatusCode::NOT_FOUND,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-
-            ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()))
-        }
-    }
+        .await?;
+
+    let sse_data = datas.clone();
+    tokio::spawn(async move {
+        let devices_id = sse_data.devices_id.clone();
+        let joined = SSEData::DeviceJoined(DeviceJoined {
+            device_id: info.device_id,
+            device_name: info.device_name,
+        });
+        let _ = sse
+            .broadcast_pool_change(&devices_id, &key_phrase, joined, sse_data)
+            .await;
+    });
+    Ok(ResponsePayload::new(true, &datas, None, None))
 }
 
-#[derive(Deserialize)]
-struct LeavePoolPayload {
+#[derive(Deserialize, ToSchema)]
+pub struct LeavePoolPayload {
     device_id: String,
 }
 
+/// the transfers sent to or from the device are deleted
+#[utoipa::path(
+    context_path = "/pool",
+    tag = "pool",
+    request_body = LeavePoolPayload,
+    responses(
+        (status = 200, body = ResponsePayload),
+        (status = 404, description = "PoolNotFound or NotInPool", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[delete("/leave")]
 async fn leave_pool(
     db: web::Data<IlixDB>,
     sse: web::Data<Broadcaster>,
     info: web::Json<LeavePoolPayload>,
-    key_phrase: KeyPhrase,
-) -> impl Responder {
+    key_phrase: AuthKeyPhrase,
+) -> ApiResult {
     if is_str_empty(&info.device_id) {
-        return BAD_ARGS_RESP.clone();
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db.client.leave_pool(&key_phrase, &info.device_id).await;
-    match db_result {
-        Ok(pool) => {
-            tokio::spawn(async move {
-                let _ = sse
-                    .broadcast_to(&pool.devices_id.clone(), &key_phrase, SSEData::Pool(pool))
-                    .await;
-            });
-            ResponsePayload::new(true, &(), None, None)
-        }
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::NotInPool => StatusCode::CONFLICT,
-                ServerErrors::PoolNotFound => StatusCode::NOT_FOUND,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-
-            ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()))
+    let (pool, deleted_transfers_id) = db.leave_pool(&key_phrase, &info.device_id).await?;
+
+    tokio::spawn(async move {
+        // its other connections (if any) still need to know that its transfers are gone
+        let leaving_device = [info.device_id.clone()];
+        for transfer_id in deleted_transfers_id {
+            let deleted = SSEData::TransferDeleted(TransferDeleted { transfer_id });
+            let _ = sse
+                .broadcast_to(&leaving_device, &key_phrase, deleted)
+                .await;
         }
-    }
+
+        let devices_id = pool.devices_id.clone();
+        let left = SSEData::DeviceLeft(DeviceLeft {
+            device_id: info.into_inner().device_id,
+        });
+        let _ = sse
+            .broadcast_pool_change(&devices_id, &key_phrase, left, pool)
+            .await;
+    });
+    Ok(ResponsePayload::new(true, &(), None, None))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RenamePoolPayload {
+    /// 1 to 50 characters
+    name: String,
+    /// the `version` of the pool the client renames, it's refused if the pool changed since. Without it the rename
+    /// always applies
+    #[serde(default)]
+    version: Option<u64>,
 }
 
-#[derive(Deserialize)]
-pub struct NewPoolPayload {
-    pub name: String,
-    pub device_id: String,
-    pub device_name: String,
+#[utoipa::path(
+    context_path = "/pool",
+    tag = "pool",
+    request_body = RenamePoolPayload,
+    responses(
+        (status = 200, body = PoolResponse),
+        (status = 400, description = "BadArgs", body = ResponsePayload),
+        (status = 404, description = "PoolNotFound", body = ResponsePayload),
+        (status = 409, description = "VersionConflict", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
+#[put("/rename")]
+async fn rename_pool(
+    db: web::Data<IlixDB>,
+    sse: web::Data<Broadcaster>,
+    config: web::Data<Config>,
+    info: web::Json<RenamePoolPayload>,
+    key_phrase: AuthKeyPhrase,
+) -> ApiResult {
+    if is_str_empty(&info.name) || info.name.len() > config.limits.max_pool_name_len {
+        return Err(ServerErrors::BadArgs.into());
+    }
+
+    let pool = db
+        .rename_pool(&key_phrase, &info.name, info.version)
+        .await?;
+
+    let sse_data = pool.clone();
+    tokio::spawn(async move {
+        let devices_id = sse_data.devices_id.clone();
+        let renamed = SSEData::PoolRenamed(PoolRenamed {
+            pool_name: sse_data.pool_name.clone(),
+        });
+        let _ = sse
+            .broadcast_pool_change(&devices_id, &key_phrase, renamed, sse_data)
+            .await;
+    });
+    Ok(ResponsePayload::new(true, &pool, None, None))
 }
 
+/// creates a pool with this device in it, returns its key phrase
+#[utoipa::path(
+    context_path = "/pool",
+    tag = "pool",
+    request_body = NewPoolPayload,
+    responses(
+        (status = 200, body = StringResponse),
+        (status = 400, description = "BadArgs", body = ResponsePayload),
+    )
+)]
 #[post("/new")]
-async fn new_pool(db: web::Data<IlixDB>, info: web::Json<NewPoolPayload>) -> impl Responder {
+async fn new_pool(
+    db: web::Data<IlixDB>,
+    config: web::Data<Config>,
+    info: web::Json<NewPoolPayload>,
+) -> ApiResult {
     if is_str_empty(&info.name)
         || is_str_empty(&info.device_id)
         || is_str_empty(&info.device_name)
-        || info.name.len() > 50
-        || info.device_name.len() > 50
+        || info.name.len() > config.limits.max_pool_name_len
+        || info.device_name.len() > config.limits.max_device_name_len
     {
-        return ResponsePayload::new(
-            false,
-            &(),
-            Some(StatusCode::BAD_REQUEST),
-            Some("Empty Args".to_string()),
-        );
+        return Err(ServerErrors::BadArgs.into());
     }
 
-    let db_result = db.client.create_pool(info.0).await;
-    match db_result {
-        Ok(datas) => ResponsePayload::new(true, &datas, None, None),
-        Err(err) => ResponsePayload::new(false, &(), None, Some(err.to_string())),
-    }
+    let datas = db.create_pool(info.0).await?;
+    Ok(ResponsePayload::new(true, &datas, None, None))
 }
 
+/// deletes the pool, its transfers and their files, every connected device is logged out
+#[utoipa::path(
+    context_path = "/pool",
+    tag = "pool",
+    responses(
+        (status = 200, body = ResponsePayload),
+        (status = 404, description = "PoolNotFound", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
 #[delete("")]
 async fn delete_pool(
     db: web::Data<IlixDB>,
     sse: web::Data<Broadcaster>,
-    key_phrase: KeyPhrase,
-) -> impl Responder {
-    let db_result = db.client.delete_pool(&key_phrase).await;
-    match db_result {
-        Ok(pool) => {
-            tokio::spawn(async move {
-                let _ = sse
-                    .broadcast_to(&pool.devices_id.clone(), &key_phrase, SSEData::Logout)
-                    .await;
-            });
-            ResponsePayload::new(true, &(), None, None)
-        }
-        Err(err) => {
-            let err_status_code = match err {
-                ServerErrors::InvalidObjectId => StatusCode::BAD_REQUEST,
-                ServerErrors::PoolNotFound | ServerErrors::TransferNotFound => {
-                    StatusCode::NOT_FOUND
-                }
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-
-            ResponsePayload::new(false, &(), Some(err_status_code), Some(err.to_string()))
-        }
-    }
+    key_phrase: AuthKeyPhrase,
+) -> ApiResult {
+    let pool = db.delete_pool(&key_phrase).await?;
+
+    tokio::spawn(async move {
+        let _ = sse
+            .broadcast_to(&pool.devices_id.clone(), &key_phrase, SSEData::Logout)
+            .await;
+    });
+    Ok(ResponsePayload::new(true, &(), None, None))
 }
@@ -0,0 +1,315 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+use ilix_core::{
+    errors::{ServerError, ServerErrors},
+    events::{SSEData, TransferAck, SSE_SCHEMA_VERSION},
+    keyphrase::KeyPhrase,
+    storage::{DevicePoolsCollection, FilePoolTransferCollection},
+};
+
+use crate::{
+    config::{Config, Timeouts},
+    db::IlixDB,
+    extractors::keyphrase::AuthKeyPhrase,
+    utils::{
+        console_log,
+        sse::{BroadcastMessage, Broadcaster, Subscription},
+    },
+};
+
+use super::ApiError;
+
+#[derive(Deserialize, IntoParams)]
+struct WsPayload {
+    device_id: String,
+    /// the schema version of the events the client understands, without it the pool changes are sent as the full
+    /// pool (`pool` events) instead of the incremental events
+    schema_version: Option<u32>,
+}
+
+/// messages the client can send through the websocket, as json: `{"type": "ack", "transfer_id": "..."}`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// tells the sender of the transfer that this device received it
+    Ack { transfer_id: String },
+    /// keeps the connection alive, the server answers with a heartbeat too
+    Heartbeat,
+    /// replays the events missed since `last_event_id`, or sends the whole pool state without it
+    Resync { last_event_id: Option<u64> },
+}
+
+/// messages sent to the client, the same events as the sse stream: `{"id": 1, "event": "transfer", "data": {...}}`
+#[derive(Serialize)]
+struct ServerMessage<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<&'a SSEData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// only sent with the "connected" event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema_version: Option<u32>,
+    /// only sent with the "shutdown" event, how long to wait before reconnecting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_ms: Option<u64>,
+}
+
+impl<'a> ServerMessage<'a> {
+    fn event(event: &'a str) -> Self {
+        Self {
+            id: None,
+            event,
+            data: None,
+            reason: None,
+            schema_version: None,
+            retry_ms: None,
+        }
+    }
+
+    fn connected() -> Self {
+        Self {
+            schema_version: Some(SSE_SCHEMA_VERSION),
+            ..Self::event("connected")
+        }
+    }
+
+    fn data(id: Option<u64>, data: &'a SSEData) -> Self {
+        Self {
+            id,
+            event: data.event_name(),
+            data: Some(data),
+            reason: None,
+            schema_version: None,
+            retry_ms: None,
+        }
+    }
+
+    fn shutdown(retry: Duration) -> Self {
+        Self {
+            retry_ms: Some(retry.as_millis() as u64),
+            ..Self::event("shutdown")
+        }
+    }
+
+    fn error(err: impl Into<ServerError>) -> Self {
+        let err = err.into();
+        if err.kind.status_code().is_server_error() {
+            console_log(&err.chain(), log::Level::Error);
+        }
+        Self {
+            reason: Some(err.kind.to_string()),
+            ..Self::event("error")
+        }
+    }
+
+    async fn send(&self, session: &mut Session) -> Result<(), actix_ws::Closed> {
+        match serde_json::to_string(self) {
+            Ok(json) => session.text(json).await,
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// Bidirectional alternative to the `/events` sse stream, clients of both transports share the same broadcaster
+#[utoipa::path(
+    tag = "events",
+    params(WsPayload),
+    responses(
+        (status = 101, description = "switches to the websocket protocol, messages are json (`{\"type\": \"ack\", \"transfer_id\": \"...\"}`, `heartbeat`, `resync`). When the server stops, it sends a `shutdown` event (with `retry_ms`) and closes with the 1012 (restart) code"),
+        (status = 404, description = "PoolNotFound", body = ResponsePayload),
+        (status = 503, description = "ShuttingDown", body = ResponsePayload),
+    ),
+    security(("key_phrase" = []))
+)]
+#[get("/ws")]
+async fn ws_stream(
+    req: HttpRequest,
+    body: web::Payload,
+    db: web::Data<IlixDB>,
+    sse: web::Data<Broadcaster>,
+    config: web::Data<Config>,
+    key_phrase: AuthKeyPhrase,
+    query: web::Query<WsPayload>,
+) -> Result<HttpResponse, ApiError> {
+    db.get_pool(&key_phrase).await?;
+
+    let versioned = query.schema_version.is_some();
+    let subscription = sse
+        .subscribe(&query.device_id, &key_phrase, None, versioned)
+        .await?;
+    let (response, session, msg_stream) = actix_ws::handle(&req, body)
+        .map_err(|err| ApiError::with_details(ServerErrors::BadArgs, err))?;
+
+    let client = WsClient {
+        db: db.into_inner(),
+        sse: sse.into_inner(),
+        key_phrase: key_phrase.0,
+        device_id: query.into_inner().device_id,
+        versioned,
+        timeouts: config.timeouts.clone(),
+    };
+    actix_web::rt::spawn(client.run(session, msg_stream, subscription));
+
+    Ok(response)
+}
+
+struct WsClient {
+    db: Arc<IlixDB>,
+    sse: Arc<Broadcaster>,
+    key_phrase: KeyPhrase,
+    device_id: String,
+    /// see [`Broadcaster::subscribe`]
+    versioned: bool,
+    timeouts: Timeouts,
+}
+
+impl WsClient {
+    /// forwards the broadcasted events to the client and handles its messages, until one of the side closes
+    async fn run(
+        self,
+        mut session: Session,
+        mut msg_stream: MessageStream,
+        mut subscription: Subscription,
+    ) {
+        let mut last_activity = Instant::now();
+        let mut close_reason = None;
+        let mut heartbeat = actix_web::rt::time::interval(self.timeouts.ws_heartbeat);
+
+        loop {
+            let sent = tokio::select! {
+                broadcast = subscription.recv() => match broadcast {
+                    Some(BroadcastMessage::Connected) => {
+                        ServerMessage::connected().send(&mut session).await
+                    }
+                    Some(BroadcastMessage::Data(id, data)) => {
+                        ServerMessage::data(Some(id), &data).send(&mut session).await
+                    }
+                    Some(BroadcastMessage::Shutdown(retry)) => {
+                        let _ = ServerMessage::shutdown(retry).send(&mut session).await;
+                        close_reason = Some(CloseReason {
+                            code: CloseCode::Restart,
+                            description: Some("server shutting down".to_string()),
+                        });
+                        break;
+                    }
+                    None => break,
+                },
+                msg = msg_stream.recv() => {
+                    last_activity = Instant::now();
+                    match msg {
+                        Some(Ok(Message::Text(text))) => self.handle_message(&mut session, &text).await,
+                        Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await,
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => Ok(()),
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    if last_activity.elapsed() > self.timeouts.ws_client_timeout {
+                        break;
+                    }
+                    session.ping(b"").await
+                }
+            };
+            if sent.is_err() {
+                break; // the client is gone
+            }
+        }
+
+        let _ = session.close(close_reason).await;
+    }
+
+    async fn handle_message(
+        &self,
+        session: &mut Session,
+        text: &str,
+    ) -> Result<(), actix_ws::Closed> {
+        let msg = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(msg) => msg,
+            Err(_) => {
+                return ServerMessage::error(ServerErrors::ParseError)
+                    .send(session)
+                    .await
+            }
+        };
+
+        match msg {
+            ClientMessage::Heartbeat => ServerMessage::event("heartbeat").send(session).await,
+            ClientMessage::Ack { transfer_id } => match self.ack_transfer(&transfer_id).await {
+                Ok(()) => Ok(()),
+                Err(err) => ServerMessage::error(err).send(session).await,
+            },
+            ClientMessage::Resync {
+                last_event_id: Some(last_event_id),
+            } => {
+                let missed_events = match self.sse.missed_events(
+                    &self.device_id,
+                    &self.key_phrase,
+                    last_event_id,
+                    self.versioned,
+                ) {
+                    Ok(events) => events,
+                    Err(err) => return ServerMessage::error(err).send(session).await,
+                };
+                for (id, data) in missed_events {
+                    ServerMessage::data(Some(id), &data).send(session).await?;
+                }
+                Ok(())
+            }
+            ClientMessage::Resync {
+                last_event_id: None,
+            } => {
+                let state = match self.pool_state().await {
+                    Ok(state) => state,
+                    Err(err) => return ServerMessage::error(err).send(session).await,
+                };
+                for data in state {
+                    ServerMessage::data(None, &data).send(session).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// only the recipient of a transfer can acknowledge it
+    async fn ack_transfer(&self, transfer_id: &str) -> Result<(), ServerError> {
+        let transfer = self
+            .db
+            .find_transfers(&self.key_phrase, &self.device_id)
+            .await?
+            .into_iter()
+            .find(|transfer| transfer._id == transfer_id)
+            .ok_or(ServerErrors::TransferNotFound)?;
+
+        let ack = SSEData::TransferAck(TransferAck {
+            transfer_id: transfer._id,
+            device_id: self.device_id.clone(),
+        });
+        self.sse
+            .broadcast_to(&[transfer.from], &self.key_phrase, ack)
+            .await
+    }
+
+    /// the current pool and all the transfers sent to this device
+    async fn pool_state(&self) -> Result<Vec<SSEData>, ServerError> {
+        let pool = self.db.get_pool(&self.key_phrase).await?;
+        let transfers = self
+            .db
+            .find_transfers(&self.key_phrase, &self.device_id)
+            .await?;
+
+        let mut state = vec![SSEData::Pool(pool)];
+        state.extend(transfers.into_iter().map(SSEData::Transfer));
+        Ok(state)
+    }
+}
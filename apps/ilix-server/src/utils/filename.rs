@@ -0,0 +1,144 @@
+use actix_web::http::header::{
+    Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
+
+/// most filesystems limit a filename to 255 bytes
+const MAX_FILENAME_LEN: usize = 255;
+/// used when nothing is left of the filename after its sanitisation
+const DEFAULT_FILENAME: &str = "file";
+/// longer than any real extension (e.g: `.numbers`)
+const MAX_EXTENSION_LEN: usize = 16;
+/// characters forbidden in a filename by at least one major os
+const RESERVED_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Normalises a client provided filename so that it can safely be stored, used in a path and sent back in a header:
+///     - only the last path component is kept (no `/`, `\` nor `..`)
+///     - control and reserved characters are replaced by `_`
+///     - leading dots, and trailing dots/spaces are removed (no hidden files, windows trims them)
+///     - it is truncated to `MAX_FILENAME_LEN` bytes, keeping the extension
+pub fn sanitize_filename(filename: &str) -> String {
+    let basename = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c.is_control() || RESERVED_CHARS.contains(&c) {
+            true => '_',
+            false => c,
+        })
+        .collect::<String>();
+    let basename = basename
+        .trim()
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' ']);
+
+    if basename.is_empty() {
+        return DEFAULT_FILENAME.to_string();
+    }
+    truncate_filename(basename)
+}
+
+fn truncate_filename(filename: &str) -> String {
+    if filename.len() <= MAX_FILENAME_LEN {
+        return filename.to_string();
+    }
+
+    let (stem, ext) = match filename.rsplit_once('.') {
+        // an extension that long is not an extension
+        Some((stem, ext)) if ext.len() < MAX_FILENAME_LEN / 2 => (stem, format!(".{ext}")),
+        _ => (filename, String::new()),
+    };
+    let mut end = MAX_FILENAME_LEN - ext.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{ext}", &stem[..end])
+}
+
+/// The extension of a client provided filename, if it's a plain ascii one (alphanumeric, not too long), so that it can
+/// be put on a server path: the content type is guessed from it
+pub fn safe_extension(filename: &str) -> Option<String> {
+    let filename = sanitize_filename(filename);
+    let (_, ext) = filename.rsplit_once('.')?;
+    let is_plain = !ext.is_empty()
+        && ext.len() <= MAX_EXTENSION_LEN
+        && ext.chars().all(|c| c.is_ascii_alphanumeric());
+    is_plain.then(|| ext.to_ascii_lowercase())
+}
+
+/// Builds a `Content-Disposition` header for this filename (RFC 6266).
+///
+/// The ascii `filename` parameter is a fallback for old clients, `filename*` (RFC 5987) carries the real utf8 name
+pub fn content_disposition(disposition: DispositionType, filename: &str) -> ContentDisposition {
+    let filename = sanitize_filename(filename);
+    let ascii_filename = filename
+        .chars()
+        .map(|c| match c.is_ascii() {
+            true => c,
+            false => '_',
+        })
+        .collect::<String>();
+
+    let mut parameters = vec![DispositionParam::Filename(ascii_filename)];
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.into_bytes(),
+        }));
+    }
+
+    ContentDisposition {
+        disposition,
+        parameters,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::DispositionType;
+
+    use super::{content_disposition, safe_extension, sanitize_filename, MAX_FILENAME_LEN};
+
+    #[test]
+    fn sanitize_filename_test() {
+        assert_eq!(sanitize_filename("test1.jpg"), "test1.jpg");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("..\\..\\boot.ini"), "boot.ini");
+        assert_eq!(sanitize_filename(".."), "file");
+        assert_eq!(sanitize_filename("/"), "file");
+        assert_eq!(sanitize_filename(".env"), "env");
+        assert_eq!(sanitize_filename("a\nb\0c.txt"), "a_b_c.txt");
+        assert_eq!(sanitize_filename("what?.txt. . "), "what_.txt");
+        assert_eq!(sanitize_filename("佐々木と宮野.png"), "佐々木と宮野.png");
+
+        let long = format!("{}.txt", "é".repeat(300));
+        let truncated = sanitize_filename(&long);
+        assert!(truncated.len() <= MAX_FILENAME_LEN);
+        assert!(truncated.ends_with("é.txt"));
+    }
+
+    #[test]
+    fn safe_extension_test() {
+        assert_eq!(safe_extension("test1.JPG").as_deref(), Some("jpg"));
+        assert_eq!(safe_extension("archive.tar.gz").as_deref(), Some("gz"));
+        assert_eq!(safe_extension("../../etc/passwd"), None);
+        assert_eq!(safe_extension(".env"), None); // a hidden file, not an extension
+        assert_eq!(safe_extension("a.b/../c"), None);
+        assert_eq!(safe_extension("photo.p ng"), None);
+        assert_eq!(safe_extension("宮野.ピング"), None);
+        assert_eq!(safe_extension(&format!("a.{}", "x".repeat(40))), None);
+    }
+
+    #[test]
+    fn content_disposition_test() {
+        let header = content_disposition(DispositionType::Attachment, "test1.jpg");
+        assert_eq!(header.to_string(), "attachment; filename=\"test1.jpg\"");
+
+        let header = content_disposition(DispositionType::Inline, "../宮野.png");
+        assert_eq!(
+            header.to_string(),
+            "inline; filename=\"__.png\"; filename*=UTF-8''%E5%AE%AE%E9%87%8E.png"
+        );
+    }
+}
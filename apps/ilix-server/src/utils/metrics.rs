@@ -0,0 +1,271 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+/// The metrics of this instance, served by `/metrics` in the prometheus text format
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// in seconds
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+/// in seconds, the files are encrypted in memory so it's way faster than a request
+const CRYPTO_BUCKETS: [f64; 9] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    /// method, route, status
+    http_requests: CounterVec,
+    /// method, route
+    http_latency: HistogramVec<11>,
+    uploaded_bytes: AtomicU64,
+    downloaded_bytes: AtomicU64,
+    /// stage: "publish" (the pub/sub backend failed) or "deliver" (the client stream was closed)
+    broadcast_failures: CounterVec,
+    gc_runs: AtomicU64,
+    /// op: "encrypt" or "decrypt"
+    crypto_duration: HistogramVec<9>,
+}
+
+/// The event streams (sse and websocket) connected to this instance, read from the broadcaster when rendering
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub connections: usize,
+    /// the pools with at least one connected device
+    pub pools: usize,
+}
+
+impl Metrics {
+    /// `route` is the matched pattern (e.g: "/v2/file/{file_id}"), not the path, so that ids don't make new series
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let route = escape(route);
+        self.http_requests.inc(format!(
+            "method=\"{method}\",route=\"{route}\",status=\"{status}\""
+        ));
+        self.http_latency.observe(
+            format!("method=\"{method}\",route=\"{route}\""),
+            elapsed,
+            &LATENCY_BUCKETS,
+        );
+    }
+
+    pub fn uploaded(&self, bytes: usize) {
+        self.uploaded_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn downloaded(&self, bytes: usize) {
+        self.downloaded_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn broadcast_failed(&self, stage: &'static str) {
+        self.broadcast_failures.inc(format!("stage=\"{stage}\""));
+    }
+
+    pub fn gc_ran(&self) {
+        self.gc_runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// runs `f` (an encryption or a decryption) and records how long it took
+    pub fn time_crypto<T>(&self, op: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.crypto_duration
+            .observe(format!("op=\"{op}\""), start.elapsed(), &CRYPTO_BUCKETS);
+        result
+    }
+
+    /// the prometheus text exposition format
+    pub fn render(&self, connections: ConnectionStats) -> String {
+        let mut out = String::new();
+
+        self.http_requests.render(
+            &mut out,
+            "ilix_http_requests_total",
+            "the handled requests, by route and status",
+        );
+        self.http_latency.render(
+            &mut out,
+            "ilix_http_request_duration_seconds",
+            "time to the response head (the event streams bodies aren't counted), by route",
+            &LATENCY_BUCKETS,
+        );
+        render_single(
+            &mut out,
+            "ilix_uploaded_bytes_total",
+            "counter",
+            "size of the uploaded files, before compression and encryption",
+            self.uploaded_bytes.load(Ordering::Relaxed),
+        );
+        render_single(
+            &mut out,
+            "ilix_downloaded_bytes_total",
+            "counter",
+            "size of the downloaded files and thumbnails, once decrypted",
+            self.downloaded_bytes.load(Ordering::Relaxed),
+        );
+        render_single(
+            &mut out,
+            "ilix_sse_connections",
+            "gauge",
+            "the event streams (sse and websocket) connected to this instance",
+            connections.connections as u64,
+        );
+        render_single(
+            &mut out,
+            "ilix_sse_pools",
+            "gauge",
+            "the pools with at least one event stream connected to this instance",
+            connections.pools as u64,
+        );
+        self.broadcast_failures.render(
+            &mut out,
+            "ilix_broadcast_failures_total",
+            "events that couldn't be published, or delivered to a stream",
+        );
+        render_single(
+            &mut out,
+            "ilix_gc_runs_total",
+            "counter",
+            "runs of the event log cleanup",
+            self.gc_runs.load(Ordering::Relaxed),
+        );
+        self.crypto_duration.render(
+            &mut out,
+            "ilix_crypto_duration_seconds",
+            "time to encrypt or decrypt a file",
+            &CRYPTO_BUCKETS,
+        );
+        out
+    }
+}
+
+/// label values can't hold raw quotes, backslashes nor new lines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn render_single(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+    );
+}
+
+/// counters by labels (already rendered, e.g: `stage="publish"`)
+#[derive(Default)]
+struct CounterVec(Mutex<BTreeMap<String, u64>>);
+
+impl CounterVec {
+    fn inc(&self, labels: String) {
+        *self.0.lock().entry(labels).or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+        for (labels, value) in self.0.lock().iter() {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+struct Histogram<const N: usize> {
+    /// not cumulative, the cumulative counts are computed when rendering
+    buckets: [u64; N],
+    sum: f64,
+    count: u64,
+}
+
+/// histograms by labels, `N` is the number of buckets
+#[derive(Default)]
+struct HistogramVec<const N: usize>(Mutex<BTreeMap<String, Histogram<N>>>);
+
+impl<const N: usize> HistogramVec<N> {
+    fn observe(&self, labels: String, value: Duration, bounds: &[f64; N]) {
+        let value = value.as_secs_f64();
+        let mut histograms = self.0.lock();
+        let histogram = histograms.entry(labels).or_insert_with(|| Histogram {
+            buckets: [0; N],
+            sum: 0.,
+            count: 0,
+        });
+        if let Some(bucket) = bounds.iter().position(|bound| value <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str, bounds: &[f64; N]) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        for (labels, histogram) in self.0.lock().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in bounds.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"+Inf\"}} {}\n{name}_sum{{{labels}}} {}\n{name}_count{{{labels}}} {}",
+                histogram.count, histogram.sum, histogram.count
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ConnectionStats, Metrics};
+
+    #[test]
+    fn metrics_render_test() {
+        let metrics = Metrics::default();
+        metrics.observe_request("GET", "/v2/file/{file_id}", 200, Duration::from_millis(30));
+        metrics.observe_request("GET", "/v2/file/{file_id}", 200, Duration::from_secs(60));
+        metrics.observe_request("GET", "/v2/file/{file_id}", 404, Duration::from_millis(1));
+        metrics.uploaded(1000);
+        metrics.downloaded(10);
+        metrics.broadcast_failed("deliver");
+        metrics.gc_ran();
+        assert_eq!(metrics.time_crypto("encrypt", || 42), 42);
+
+        let text = metrics.render(ConnectionStats {
+            connections: 3,
+            pools: 2,
+        });
+        for line in [
+            "# TYPE ilix_http_requests_total counter",
+            "ilix_http_requests_total{method=\"GET\",route=\"/v2/file/{file_id}\",status=\"200\"} 2",
+            "ilix_http_requests_total{method=\"GET\",route=\"/v2/file/{file_id}\",status=\"404\"} 1",
+            "ilix_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v2/file/{file_id}\",le=\"0.005\"} 1",
+            "ilix_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v2/file/{file_id}\",le=\"0.05\"} 2",
+            "ilix_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v2/file/{file_id}\",le=\"10\"} 2",
+            "ilix_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v2/file/{file_id}\",le=\"+Inf\"} 3",
+            "ilix_http_request_duration_seconds_count{method=\"GET\",route=\"/v2/file/{file_id}\"} 3",
+            "ilix_uploaded_bytes_total 1000",
+            "ilix_downloaded_bytes_total 10",
+            "ilix_sse_connections 3",
+            "ilix_sse_pools 2",
+            "ilix_broadcast_failures_total{stage=\"deliver\"} 1",
+            "ilix_gc_runs_total 1",
+            "ilix_crypto_duration_seconds_count{op=\"encrypt\"} 1",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing line: {line}\n{text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,44 @@
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// detects the file mime type from its magic bytes.
+///
+/// When unknown, it falls back to the content type declared by the client, then to `text/plain` for valid utf8 datas
+/// and finally to `application/octet-stream`
+pub fn detect_mime_type(datas: &[u8], declared: Option<&str>) -> String {
+    if let Some(kind) = infer::get(datas) {
+        return kind.mime_type().to_string();
+    }
+
+    match declared {
+        Some(mime) if mime.parse::<mime::Mime>().is_ok() && mime != DEFAULT_MIME_TYPE => {
+            mime.to_string()
+        }
+        _ if std::str::from_utf8(datas).is_ok() => mime::TEXT_PLAIN_UTF_8.to_string(),
+        _ => DEFAULT_MIME_TYPE.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::detect_mime_type;
+
+    #[test]
+    fn detect_mime_type_test() {
+        let jpg = fs::read("./tests/Assets/test1.jpg").unwrap();
+        assert_eq!(detect_mime_type(&jpg, Some("text/plain")), "image/jpeg"); // magic bytes wins
+
+        let txt = fs::read("./tests/Assets/test2.txt").unwrap();
+        assert_eq!(detect_mime_type(&txt, None), "text/plain; charset=utf-8");
+        assert_eq!(
+            detect_mime_type(&txt, Some("text/markdown")),
+            "text/markdown"
+        );
+
+        assert_eq!(
+            detect_mime_type(&[0xff, 0xfe, 0x00, 0x9f], Some("not a mime")),
+            "application/octet-stream"
+        );
+    }
+}
@@ -1,35 +1,27 @@
-pub mod encryption;
-pub mod errors;
-pub mod keyphrase;
+pub mod filename;
+pub mod metrics;
+pub mod mime;
+pub mod pubsub;
 pub mod sse;
+pub mod thumbnail;
+pub mod uploads;
 
-use hex_string::HexString;
 use log::{debug, error, info, log_enabled, trace, warn, Level};
-use sha3::{Digest, Sha3_256};
-use std::env;
 
-/// return the 256 bytes **sha3 hash** of the `msg` param
-pub fn hash<T: Into<String>>(msg: T) -> String {
-    let mut hasher = Sha3_256::new();
-    hasher.update(msg.into().as_bytes());
-    let result_buf = hasher.finalize().to_vec();
-    HexString::from_bytes(&result_buf).as_string()
-}
-
-pub fn is_prod() -> bool {
-    match env::var("APP_MODE") {
-        Ok(mode) => mode == "prod",
-        Err(_) => false,
-    }
-}
+use crate::middlewares::request_id::current_request_id;
 
 /// helper function that return whether the string is empty (including if string is only composed of spaces) or not
 pub fn is_str_empty(str: &str) -> bool {
     str.trim().is_empty()
 }
 
+/// logs `msg`, prefixed by the request id when called while handling a request
 pub fn console_log(msg: &str, lvl: Level) {
     if log_enabled!(lvl) {
+        let msg = match current_request_id() {
+            Some(request_id) => format!("[{request_id}] {msg}"),
+            None => msg.to_string(),
+        };
         match lvl {
             Level::Error => error!("{msg}"),
             Level::Warn => warn!("{msg}"),
@@ -55,37 +47,7 @@ impl TrimObjectId for String {
 
 #[cfg(test)]
 mod tests {
-    use std::env;
-
-    use crate::utils::{hash, is_str_empty, TrimObjectId};
-
-    use super::is_prod;
-
-    #[test]
-    fn is_prod_test() {
-        env::set_var("APP_MODE", "dev");
-        assert!(!is_prod());
-
-        env::set_var("APP_MODE", "prod");
-        assert!(is_prod());
-    }
-
-    #[test]
-    fn hash_test() {
-        assert_eq!(
-            hash("sasaki and miyano"),
-            "ebbdf07f1121359452ec4ee91ade8f68e7bd750b018f7efa723e08486b09577e"
-        );
-
-        let mut result = "sasaki and miyano".to_string();
-        for _ in 0..10 {
-            result = hash(result);
-        }
-        assert_eq!(
-            result,
-            "aad2003ae46a3fcb907568a8f05d1486df27c1f8b79c7b77f5a5355d7d2e0a57"
-        )
-    }
+    use crate::utils::{is_str_empty, TrimObjectId};
 
     #[test]
     fn is_str_empty_test() {
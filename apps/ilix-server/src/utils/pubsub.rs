@@ -0,0 +1,328 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use futures_util::{stream::BoxStream, StreamExt};
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    change_stream::event::ResumeToken,
+    error::ErrorKind,
+    options::{ChangeStreamOptions, FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+    Client, IndexModel,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    events::SSEData,
+};
+
+use crate::{
+    config::{Config, Limits, PubSubBackend},
+    db::{DB_NAME, SSE_EVENTS_COLL, SSE_EVENT_IDS_COLL},
+    utils::{console_log, metrics::METRICS},
+};
+
+/// An event published by an instance, every instance (including the publisher) delivers it to its own connected clients
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PubSubMessage {
+    pub pool_id: String,
+    /// `None` means every connected device of the pool
+    pub devices_id: Option<Vec<String>>,
+    pub event_id: u64,
+    pub data: SSEData,
+    /// what the clients that didn't tell their schema version get instead of `data`: they only know the full state
+    /// events (`Pool`), not the incremental ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SSEData>,
+    /// whether the event is kept in the event log for replay
+    pub log: bool,
+}
+
+/// Fan-out of the broadcasted events between all the server instances
+#[async_trait]
+pub trait PubSub: Send + Sync {
+    async fn publish(&self, msg: PubSubMessage) -> Result<(), ServerError>;
+    /// the id of the next event to publish, shared by every instance so that a device gets the same ids whichever
+    /// instance it's connected to
+    async fn next_event_id(&self) -> Result<u64, ServerError>;
+    /// returns the messages published from now on by every instance, the stream ends when the backend connection is lost
+    async fn subscribe(&self) -> Result<BoxStream<'static, PubSubMessage>, ServerError>;
+}
+
+/// the backend picked by the config, see [`PubSubBackend`]
+pub async fn from_config(config: &Config, client: &Client) -> Result<Box<dyn PubSub>, ServerError> {
+    match config.pubsub_backend {
+        PubSubBackend::Mongodb => Ok(Box::new(
+            MongoPubSub::new(client.clone(), config.timeouts.pubsub_events_ttl).await?,
+        )),
+        PubSubBackend::Memory => Ok(Box::new(InProcessPubSub::new(
+            config.limits.pubsub_capacity,
+        ))),
+    }
+}
+
+/// Only reaches the subscribers of this process
+pub struct InProcessPubSub {
+    sender: broadcast::Sender<PubSubMessage>,
+    /// it starts at the launch time (in ms) so that ids keep growing across restarts
+    next_event_id: AtomicU64,
+}
+
+impl InProcessPubSub {
+    /// `capacity` is how many messages a slow subscriber can lag behind before losing some
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+            next_event_id: AtomicU64::new(now_millis()),
+        }
+    }
+}
+
+impl Default for InProcessPubSub {
+    fn default() -> Self {
+        Self::new(Limits::default().pubsub_capacity)
+    }
+}
+
+#[async_trait]
+impl PubSub for InProcessPubSub {
+    async fn publish(&self, msg: PubSubMessage) -> Result<(), ServerError> {
+        // it only fails when nobody is subscribed, thus there is nobody to deliver it to
+        let _ = self.sender.send(msg);
+        Ok(())
+    }
+
+    async fn next_event_id(&self) -> Result<u64, ServerError> {
+        Ok(self.next_event_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, PubSubMessage>, ServerError> {
+        let rx = self.sender.subscribe();
+        let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(msg) => return Some((msg, rx)),
+                    Err(broadcast::error::RecvError::Lagged(lost)) => {
+                        // too slow, the lost ones can't be delivered anymore
+                        METRICS.broadcast_failed("lagged");
+                        console_log(
+                            &format!("the pub/sub subscriber lagged behind, {lost} event(s) lost"),
+                            log::Level::Warn,
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(stream.boxed())
+    }
+}
+
+/// Shares the events between instances through a mongodb collection: messages are inserted in it and every instance
+/// watches its inserts with a change stream.
+///
+/// Change streams need a replica set (atlas clusters are replica sets)
+pub struct MongoPubSub {
+    client: Client,
+    /// the published messages are only needed while the instances read them, mongodb removes them after this delay
+    events_ttl: Duration,
+    /// the last event read, a new subscription resumes after it so that nothing published meanwhile is lost
+    resume_token: Arc<Mutex<Option<ResumeToken>>>,
+}
+
+/// the mongodb error code of `createIndexes` when an index with the same keys exists with other options
+const INDEX_OPTIONS_CONFLICT: i32 = 85;
+
+/// a `PubSubMessage` as stored in the events collection
+#[derive(Deserialize, Serialize)]
+struct MongoPubSubMessage {
+    #[serde(flatten)]
+    msg: PubSubMessage,
+    /// for the ttl index
+    created_at: DateTime,
+}
+
+impl MongoPubSub {
+    pub async fn new(client: Client, events_ttl: Duration) -> Result<Self, ServerError> {
+        let this = Self {
+            client,
+            events_ttl,
+            resume_token: Arc::default(),
+        };
+        this.create_ttl_index().await?;
+        Ok(this)
+    }
+
+    fn collection(&self) -> mongodb::Collection<MongoPubSubMessage> {
+        self.client.database(DB_NAME).collection(SSE_EVENTS_COLL)
+    }
+
+    /// if the index already exists with another ttl (`pubsub_events_ttl` changed), its ttl is updated with `collMod`
+    async fn create_ttl_index(&self) -> Result<(), ServerError> {
+        let options = IndexOptions::builder()
+            .expire_after(self.events_ttl)
+            .build();
+        let index = IndexModel::builder()
+            .keys(doc! { "created_at": 1 })
+            .options(options)
+            .build();
+        match self.collection().create_index(index, None).await {
+            Err(err) if matches!(*err.kind, ErrorKind::Command(ref cmd) if cmd.code == INDEX_OPTIONS_CONFLICT) =>
+            {
+                self.client
+                    .database(DB_NAME)
+                    .run_command(
+                        doc! {
+                            "collMod": SSE_EVENTS_COLL,
+                            "index": {
+                                "keyPattern": { "created_at": 1 },
+                                "expireAfterSeconds": self.events_ttl.as_secs() as i64,
+                            },
+                        },
+                        None,
+                    )
+                    .await
+                    .server_err(ServerErrors::MongoError)?;
+            }
+            created => {
+                created.server_err(ServerErrors::MongoError)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PubSub for MongoPubSub {
+    async fn publish(&self, msg: PubSubMessage) -> Result<(), ServerError> {
+        let msg = MongoPubSubMessage {
+            msg,
+            created_at: DateTime::now(),
+        };
+        self.collection()
+            .insert_one(msg, None)
+            .await
+            .server_err(ServerErrors::MongoError)?;
+        Ok(())
+    }
+
+    async fn next_event_id(&self) -> Result<u64, ServerError> {
+        // a single counter document, it starts at the first launch time (in ms) like the in-process ids
+        let counter = self
+            .client
+            .database(DB_NAME)
+            .collection::<Document>(SSE_EVENT_IDS_COLL)
+            .find_one_and_update(
+                doc! {"_id": "event_id"},
+                vec![doc! {"$set": {"seq": {"$add": [
+                    {"$ifNull": ["$seq", now_millis() as i64]},
+                    1_i64,
+                ]}}}],
+                FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .server_err(ServerErrors::MongoError)?
+            .ok_or(ServerErrors::MongoError)?;
+        let seq = counter
+            .get_i64("seq")
+            .server_err(ServerErrors::ParseError)?;
+        Ok(seq as u64)
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, PubSubMessage>, ServerError> {
+        let pipeline = [doc! {"$match": {"operationType": "insert"}}];
+        let resume_after = self.resume_token.lock().clone();
+        let change_stream = match resume_after {
+            Some(resume_after) => {
+                let options = ChangeStreamOptions::builder()
+                    .resume_after(Some(resume_after))
+                    .build();
+                match self.collection().watch(pipeline.clone(), options).await {
+                    Ok(change_stream) => Ok(change_stream),
+                    Err(err) => {
+                        // e.g: the events were already removed from the oplog
+                        METRICS.broadcast_failed("resume");
+                        console_log(
+                            &format!(
+                                "can't resume the pub/sub subscription, events may be lost: {err}"
+                            ),
+                            log::Level::Warn,
+                        );
+                        self.collection().watch(pipeline, None).await
+                    }
+                }
+            }
+            None => self.collection().watch(pipeline, None).await,
+        }
+        .server_err(ServerErrors::MongoError)?;
+
+        let resume_token = Arc::clone(&self.resume_token);
+        let stream = change_stream
+            .take_while(|event| futures_util::future::ready(event.is_ok()))
+            .filter_map(move |event| {
+                let resume_token = Arc::clone(&resume_token);
+                async move {
+                    let event = event.ok()?;
+                    *resume_token.lock() = Some(event.id);
+                    Some(event.full_document?.msg)
+                }
+            });
+        Ok(stream.boxed())
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use ilix_core::events::SSEData;
+
+    use super::{InProcessPubSub, PubSub, PubSubMessage};
+
+    #[tokio::test]
+    async fn in_process_pubsub_test() {
+        let pubsub = InProcessPubSub::default();
+        let msg = |event_id| PubSubMessage {
+            pool_id: "pool1".to_string(),
+            devices_id: None,
+            event_id,
+            data: SSEData::Logout,
+            snapshot: None,
+            log: false,
+        };
+
+        pubsub.publish(msg(0)).await.unwrap(); // nobody to receive it
+
+        let mut first = pubsub.subscribe().await.unwrap();
+        let mut second = pubsub.subscribe().await.unwrap();
+        pubsub.publish(msg(1)).await.unwrap();
+        assert_eq!(first.next().await.unwrap().event_id, 1);
+        assert_eq!(second.next().await.unwrap().event_id, 1);
+
+        // a lagging subscriber skips the lost messages and goes on with the next ones
+        let pubsub = InProcessPubSub::new(1);
+        let mut lagging = pubsub.subscribe().await.unwrap();
+        for event_id in 2..5 {
+            pubsub.publish(msg(event_id)).await.unwrap();
+        }
+        assert_eq!(lagging.next().await.unwrap().event_id, 4);
+    }
+}
@@ -1,130 +1,665 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 use actix_web::rt::time::interval;
-use actix_web_lab::sse::{self, ChannelStream, Event, Sse};
+use actix_web_lab::sse::{self, Event, Sse};
 use anyhow::Result;
-use futures_util::future;
+use futures_util::{Stream, StreamExt};
+use mongodb::bson::DateTime;
 use parking_lot::Mutex;
-use tokio::task;
+use tokio::sync::{mpsc, watch};
 
-use crate::db::models::{DevicesPool, FilePoolTransferExt};
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    events::{Presence, SSEData, SSE_SCHEMA_VERSION},
+    keyphrase::KeyPhrase,
+    models::DevicesPool,
+    storage::DevicePoolsCollection,
+};
 
-use super::{errors::ServerErrors, hash, keyphrase::KeyPhrase};
+use crate::{
+    config::{Config, Limits, Timeouts},
+    db::IlixDB,
+};
 
-#[derive(serde::Serialize, Clone)]
-pub enum SSEData {
-    Pool(DevicesPool),
-    Transfer(FilePoolTransferExt),
-    Logout,
-}
+use super::{
+    console_log,
+    metrics::{ConnectionStats, METRICS},
+    pubsub::{PubSub, PubSubMessage},
+};
 
-#[derive(serde::Serialize)]
-enum BroadcastMessage {
-    Ping,
+/// a message sent to a connected client, whatever its transport (sse or websocket)
+#[derive(serde::Serialize, Clone)]
+pub enum BroadcastMessage {
     Connected,
-    Data(SSEData),
+    /// (event_id, data), boxed since some events hold a whole pool
+    Data(u64, Box<SSEData>),
+    /// the last message before the stream is closed, the server is stopping and the client should reconnect after this
+    /// delay (to another instance, or this one once restarted)
+    Shutdown(Duration),
 }
 
 impl From<BroadcastMessage> for Event {
     fn from(msg: BroadcastMessage) -> Self {
         match msg {
-            BroadcastMessage::Ping => sse::Event::Comment("Ping".into()),
             BroadcastMessage::Connected => {
-                sse::Data::new("client connected").event("connected").into()
+                sse::Data::new_json(serde_json::json!({ "schema_version": SSE_SCHEMA_VERSION }))
+                    .unwrap_or(sse::Data::new("client connected"))
+                    .event("connected")
+                    .into()
             }
-            BroadcastMessage::Data(data) => {
-                let event_name = match data {
-                    SSEData::Pool(_) => "pool",
-                    SSEData::Transfer(_) => "transfer",
-                    SSEData::Logout => "logout",
-                };
+            BroadcastMessage::Data(id, data) => {
+                let event_name = data.event_name();
                 sse::Data::new_json(data)
                     .unwrap_or(sse::Data::new("Failed to stringify message"))
                     .event(event_name)
+                    .id(id.to_string())
+                    .into()
+            }
+            BroadcastMessage::Shutdown(retry) => {
+                sse::Data::new_json(serde_json::json!({ "retry_ms": retry.as_millis() as u64 }))
+                    .unwrap_or(sse::Data::new("server shutting down"))
+                    .event("shutdown")
                     .into()
             }
         }
     }
 }
 
+type ConnectionId = u64;
+
+/// a registered connection, whatever its transport
+#[derive(Clone)]
+struct Connection {
+    sender: mpsc::Sender<BroadcastMessage>,
+    /// whether the client told the schema version of the events it understands, the others only get the full state
+    /// events (see [`PubSubMessage::snapshot`])
+    versioned: bool,
+}
+
+/// Index of the connected clients, by pool and by device.
+///
+/// A device can have several connections at once (e.g: the app opened twice), and finding the recipients of a broadcast
+/// only costs the number of recipients, not the number of connected clients
+#[derive(Default)]
+struct ClientRegistry {
+    /// pool_id -> device_id -> connection_id -> connection
+    pools: HashMap<String, HashMap<String, HashMap<ConnectionId, Connection>>>,
+    /// connection_id -> (pool_id, device_id), to unregister a connection without scanning the pools
+    connections: HashMap<ConnectionId, (String, String)>,
+    next_connection_id: ConnectionId,
+}
+
+impl ClientRegistry {
+    /// returns the connection id, and whether it's the first connection of this device (it just came online)
+    fn register(
+        &mut self,
+        pool_id: &str,
+        device_id: &str,
+        sender: mpsc::Sender<BroadcastMessage>,
+        versioned: bool,
+    ) -> (ConnectionId, bool) {
+        let connection_id = self.next_connection_id;
+        self.next_connection_id += 1;
+
+        let device_connections = self
+            .pools
+            .entry(pool_id.to_string())
+            .or_default()
+            .entry(device_id.to_string())
+            .or_default();
+        let came_online = device_connections.is_empty();
+        device_connections.insert(connection_id, Connection { sender, versioned });
+
+        self.connections
+            .insert(connection_id, (pool_id.to_string(), device_id.to_string()));
+        (connection_id, came_online)
+    }
+
+    /// removes the connection, and the device/pool entries if it was their last connection.
+    ///
+    /// When it was the last connection of the device (it went offline), it returns its (pool_id, device_id)
+    fn unregister(&mut self, connection_id: ConnectionId) -> Option<(String, String)> {
+        let (pool_id, device_id) = self.connections.remove(&connection_id)?;
+        let devices = self.pools.get_mut(&pool_id)?;
+
+        let mut went_offline = false;
+        if let Some(device_connections) = devices.get_mut(&device_id) {
+            device_connections.remove(&connection_id);
+            if device_connections.is_empty() {
+                devices.remove(&device_id);
+                went_offline = true;
+            }
+        }
+        if devices.is_empty() {
+            self.pools.remove(&pool_id);
+        }
+
+        went_offline.then_some((pool_id, device_id))
+    }
+
+    /// returns the devices of this pool that have at least one connection
+    fn online_devices(&self, pool_id: &str) -> Vec<String> {
+        self.pools
+            .get(pool_id)
+            .map(|devices| devices.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// returns the connections of these devices in this pool, or of all its devices when `devices_id` is `None`
+    fn recipients(
+        &self,
+        pool_id: &str,
+        devices_id: Option<&[String]>,
+    ) -> Vec<(ConnectionId, Connection)> {
+        let Some(devices) = self.pools.get(pool_id) else {
+            return vec![];
+        };
+
+        let device_connections: Vec<_> = match devices_id {
+            Some(devices_id) => devices_id
+                .iter()
+                .filter_map(|device_id| devices.get(device_id))
+                .collect(),
+            None => devices.values().collect(),
+        };
+        device_connections
+            .into_iter()
+            .flat_map(|device_connections| {
+                device_connections
+                    .iter()
+                    .map(|(id, connection)| (*id, connection.clone()))
+            })
+            .collect()
+    }
+
+    /// the (pool_id, device_id) of every connected device
+    fn devices(&self) -> Vec<(String, String)> {
+        self.pools
+            .iter()
+            .flat_map(|(pool_id, devices)| {
+                devices
+                    .keys()
+                    .map(|device_id| (pool_id.clone(), device_id.clone()))
+            })
+            .collect()
+    }
+
+    /// the connections of every pool
+    fn all(&self) -> Vec<mpsc::Sender<BroadcastMessage>> {
+        self.pools
+            .values()
+            .flat_map(|devices| devices.values())
+            .flat_map(|device_connections| {
+                device_connections
+                    .values()
+                    .map(|connection| connection.sender.clone())
+            })
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.connections.len()
+    }
+}
+
+/// an event as it was sent to a device, see [`PubSubMessage`]
+struct LoggedEvent {
+    id: u64,
+    sent_at: Instant,
+    data: SSEData,
+    snapshot: Option<SSEData>,
+}
+
+/// Bounded log of the events sent to each device, so that a device can catch up the events it missed while disconnected
+struct EventLog {
+    /// client_id -> events, in the order of the pub/sub stream (the same on every instance)
+    events: HashMap<String, VecDeque<LoggedEvent>>,
+    /// per device
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl EventLog {
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            events: HashMap::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    fn push(&mut self, client_id: &str, event_id: u64, data: SSEData, snapshot: Option<SSEData>) {
+        let device_events = self.events.entry(client_id.to_string()).or_default();
+        if device_events.len() >= self.capacity {
+            device_events.pop_front();
+        }
+        device_events.push_back(LoggedEvent {
+            id: event_id,
+            sent_at: Instant::now(),
+            data,
+            snapshot,
+        });
+    }
+
+    /// Returns all the events of this client that came after `last_event_id`, as a `versioned` client gets them.
+    ///
+    /// The ids are taken before publishing, so two close events can be delivered out of id order: the events logged
+    /// after `last_event_id` are replayed, and only when it's no longer logged the ones with a greater id
+    fn since(&self, client_id: &str, last_event_id: u64, versioned: bool) -> Vec<(u64, SSEData)> {
+        let Some(device_events) = self.events.get(client_id) else {
+            return vec![];
+        };
+        let last_position = device_events
+            .iter()
+            .position(|event| event.id == last_event_id);
+
+        device_events
+            .iter()
+            .enumerate()
+            .filter(|(i, event)| match last_position {
+                Some(last_position) => *i > last_position,
+                None => event.id > last_event_id,
+            })
+            .filter(|(_, event)| event.sent_at.elapsed() < self.ttl)
+            .map(|(_, event)| {
+                let data = match (&event.snapshot, versioned) {
+                    (Some(snapshot), false) => snapshot,
+                    _ => &event.data,
+                };
+                (event.id, data.clone())
+            })
+            .collect()
+    }
+
+    /// removes the expired events
+    fn prune(&mut self) {
+        let ttl = self.ttl;
+        self.events.retain(|_, device_events| {
+            device_events.retain(|event| event.sent_at.elapsed() < ttl);
+            !device_events.is_empty()
+        });
+    }
+}
+
+/// A connection registered to the broadcaster, whatever its transport.
+///
+/// When the client disconnects, the subscription is dropped, which unregisters the connection from the broadcaster
+pub struct Subscription {
+    rx: mpsc::Receiver<BroadcastMessage>,
+    connection_id: ConnectionId,
+    broadcaster: Weak<Broadcaster>,
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Option<BroadcastMessage> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let Some(broadcaster) = self.broadcaster.upgrade() else {
+            return;
+        };
+
+        let went_offline = broadcaster
+            .inner
+            .lock()
+            .clients
+            .unregister(self.connection_id);
+        if let Some((pool_id, device_id)) = went_offline {
+            broadcaster.presence_changed(pool_id, device_id, false);
+        }
+    }
+}
+
+/// The sse stream of one connection, actix drops it when the client disconnects
+pub struct ClientStream(Subscription);
+
+impl Stream for ClientStream {
+    type Item = Result<Event, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0
+            .rx
+            .poll_recv(cx)
+            .map(|msg| msg.map(|msg| Ok(msg.into())))
+    }
+}
+
 /// handles all the **SSE** implementation logic
+///
+/// Broadcasts go through the pub/sub backend, so that they reach the clients connected to any server instance
 pub struct Broadcaster {
     inner: Mutex<BroadcasterInner>,
+    /// to store when the devices were last seen
+    db: IlixDB,
+    pubsub: Box<dyn PubSub>,
+    limits: Limits,
+    timeouts: Timeouts,
+    /// true once [`Broadcaster::shutdown`] was called, the background loops stop when it changes
+    shutdown: watch::Sender<bool>,
 }
 
-#[derive(Debug, Clone, Default)]
 struct BroadcasterInner {
-    /// Vec<(device_id, sse_channel)>
-    clients: Vec<(String, sse::Sender)>,
+    clients: ClientRegistry,
+    event_log: EventLog,
 }
 
 impl Broadcaster {
-    /// Constructs new broadcaster and spawns ping loop.
-    pub fn create() -> Arc<Self> {
+    /// Constructs new broadcaster, subscribes to the pub/sub backend and spawns the event log cleanup loop.
+    pub async fn create(
+        db: IlixDB,
+        pubsub: Box<dyn PubSub>,
+        config: &Config,
+    ) -> Result<Arc<Self>, ServerError> {
+        // subscribed before anything can be published, otherwise the first events could be lost
+        let messages = pubsub.subscribe().await?;
         let this = Arc::new(Broadcaster {
-            inner: Mutex::new(BroadcasterInner::default()),
+            inner: Mutex::new(BroadcasterInner {
+                clients: ClientRegistry::default(),
+                event_log: EventLog::new(
+                    config.limits.event_log_capacity,
+                    config.timeouts.event_log_ttl,
+                ),
+            }),
+            db,
+            pubsub,
+            limits: config.limits.clone(),
+            timeouts: config.timeouts.clone(),
+            shutdown: watch::channel(false).0,
         });
 
-        Self::spawn_ping(Arc::clone(&this));
-        this
+        Self::spawn_cleanup(Arc::clone(&this));
+        Self::spawn_presence_refresh(Arc::clone(&this));
+        Self::spawn_listener(Arc::clone(&this), messages);
+        Ok(this)
     }
 
-    /// Pings clients every 30 seconds to see if they are alive and remove them from the broadcast
-    /// list if not.
-    fn spawn_ping(this: Arc<Self>) {
+    /// Delivers the messages published by every instance to the clients connected to this one.
+    ///
+    /// If the backend connection is lost, it subscribes again until it succeeds
+    fn spawn_listener(
+        this: Arc<Self>,
+        mut messages: futures_util::stream::BoxStream<'static, PubSubMessage>,
+    ) {
+        let mut stopped = this.shutdown.subscribe();
         actix_web::rt::spawn(async move {
-            let mut interval = interval(Duration::from_secs(30));
+            let listen = async {
+                loop {
+                    while let Some(msg) = messages.next().await {
+                        this.deliver(msg);
+                    }
+
+                    console_log("lost the pub/sub subscription", log::Level::Warn);
+                    loop {
+                        actix_web::rt::time::sleep(this.timeouts.pubsub_retry_delay).await;
+                        match this.pubsub.subscribe().await {
+                            Ok(stream) => {
+                                messages = stream;
+                                break;
+                            }
+                            Err(err) => console_log(
+                                &format!("failed to subscribe to pub/sub: {err}"),
+                                log::Level::Warn,
+                            ),
+                        }
+                    }
+                }
+            };
+            tokio::select! {
+                _ = listen => {}
+                _ = stopped.wait_for(|stopped| *stopped) => {}
+            }
+        });
+    }
+
+    /// Removes the expired events from the event log periodically.
+    ///
+    /// Disconnected clients don't need to be pinged, their stream unregisters itself when it is dropped
+    fn spawn_cleanup(this: Arc<Self>) {
+        let mut stopped = this.shutdown.subscribe();
+        actix_web::rt::spawn(async move {
+            let mut interval = interval(this.timeouts.event_log_cleanup);
 
             loop {
-                interval.tick().await;
-                this.remove_stale_clients().await;
+                tokio::select! {
+                    _ = interval.tick() => {
+                        this.inner.lock().event_log.prune();
+                        METRICS.gc_ran();
+                    }
+                    _ = stopped.wait_for(|stopped| *stopped) => break,
+                }
             }
         });
     }
 
-    /// Removes all non-responsive clients from broadcast list.
-    async fn remove_stale_clients(&self) {
-        let clients = self.inner.lock().clients.clone();
+    /// Marks the devices connected to this instance online again before their presence expires, so that every
+    /// instance sees them online (see [`Broadcaster::online_devices`])
+    fn spawn_presence_refresh(this: Arc<Self>) {
+        let mut stopped = this.shutdown.subscribe();
+        actix_web::rt::spawn(async move {
+            let mut interval = interval(this.timeouts.presence_refresh);
 
-        let tasks = clients.into_iter().map(|client| {
-            task::spawn(async move {
-                client.1.send(BroadcastMessage::Ping).await.ok()?;
-                Some(client)
-            })
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let devices = this.inner.lock().clients.devices();
+                        if devices.is_empty() {
+                            continue;
+                        }
+                        if let Err(err) = this.db.refresh_presence(&devices, this.presence_ttl()).await {
+                            console_log(
+                                &format!("failed to refresh the presence: {err}"),
+                                log::Level::Warn,
+                            );
+                        }
+                    }
+                    _ = stopped.wait_for(|stopped| *stopped) => break,
+                }
+            }
         });
+    }
 
-        let mut ok_clients = Vec::new();
-        for ok_client in (future::join_all(tasks).await)
-            .into_iter()
-            .flatten()
-            .flatten()
-        {
-            ok_clients.push(ok_client)
+    /// a presence missing one refresh is still online
+    fn presence_ttl(&self) -> Duration {
+        self.timeouts.presence_refresh * 3
+    }
+
+    /// Tells every connected client that the server is stopping (with a reconnection delay), closes their streams and
+    /// stops the background loops. The new subscriptions are refused from now on
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+        // the streams end once their senders are dropped, after they read the final message
+        let clients = std::mem::take(&mut self.inner.lock().clients);
+        let shutdown = BroadcastMessage::Shutdown(self.timeouts.reconnect_delay);
+        for sender in clients.all() {
+            // a client too slow to read it just sees its stream closing
+            let _ = sender.try_send(shutdown.clone());
         }
+    }
 
-        self.inner.lock().clients = ok_clients;
+    /// helper function to make the key of a device in the event log.
+    fn make_client_id(pool_id: &str, device_id: &str) -> String {
+        format!("{pool_id}:{device_id}")
     }
 
-    /// helper function to simplified the creation of client id. It hashes the given parameters
-    fn make_client_id(device_id: &str, pool_kp: &KeyPhrase) -> Result<String, ServerErrors> {
-        Ok(hash(format!("{}:{}", device_id, pool_kp.hash()?)))
+    /// returns the events sent to this device after `last_event_id`, that are still in the event log (see
+    /// [`Broadcaster::subscribe`] for `versioned`)
+    pub fn missed_events(
+        &self,
+        device_id: &str,
+        pool_kp: &KeyPhrase,
+        last_event_id: u64,
+        versioned: bool,
+    ) -> Result<Vec<(u64, SSEData)>, ServerError> {
+        let client_id = Self::make_client_id(&pool_kp.hash(&self.db.hash_params), device_id);
+        Ok(self
+            .inner
+            .lock()
+            .event_log
+            .since(&client_id, last_event_id, versioned))
+    }
+
+    /// Registers a client connection with broadcaster.
+    ///
+    /// If the client was already connected before, `last_event_id` is the last event it received, every events it
+    /// missed since are replayed. A `versioned` client told the schema version of the events it understands, it gets
+    /// the incremental events, the others get the full state instead
+    pub async fn subscribe(
+        self: &Arc<Self>,
+        device_id: &str,
+        pool_kp: &KeyPhrase,
+        last_event_id: Option<u64>,
+        versioned: bool,
+    ) -> Result<Subscription, ServerError> {
+        let pool_id = pool_kp.hash(&self.db.hash_params);
+        let (rx, connection_id, came_online) = {
+            // the missed events are taken and the client registered under the same lock: an event delivered meanwhile
+            // is either in the log already (so replayed) or delivered to the client once registered, never lost.
+            // Also checked with the lock held, so that a client can't register once the shutdown took the registry
+            let mut inner = self.inner.lock();
+            if self.is_shutting_down() {
+                return Err(ServerErrors::ShuttingDown.into());
+            }
+            let missed_events = match last_event_id {
+                Some(last_event_id) => inner.event_log.since(
+                    &Self::make_client_id(&pool_id, device_id),
+                    last_event_id,
+                    versioned,
+                ),
+                None => vec![],
+            };
+
+            // the channel must be able to hold all the replayed events, since nobody reads it yet
+            let (tx, rx) = mpsc::channel(self.limits.sse_channel_size + missed_events.len());
+            tx.try_send(BroadcastMessage::Connected)
+                .server_err(ServerErrors::SseFailedToSend)?;
+            for (event_id, data) in missed_events {
+                tx.try_send(BroadcastMessage::Data(event_id, Box::new(data)))
+                    .server_err(ServerErrors::SseFailedToSend)?;
+            }
+            let (connection_id, came_online) =
+                inner.clients.register(&pool_id, device_id, tx, versioned);
+            (rx, connection_id, came_online)
+        };
+        if came_online {
+            self.presence_changed(pool_id, device_id.to_string(), true);
+        }
+
+        Ok(Subscription {
+            rx,
+            connection_id,
+            broadcaster: Arc::downgrade(self),
+        })
     }
 
     /// Registers client with broadcaster, returning an SSE response body.
+    ///
+    /// `last_event_id` comes from the `Last-Event-ID` header, see [`Broadcaster::subscribe`]
     pub async fn new_client(
-        &self,
+        self: &Arc<Self>,
         device_id: &str,
         pool_kp: &KeyPhrase,
-    ) -> Result<Sse<ChannelStream>, ServerErrors> {
-        let (tx, rx) = sse::channel(10);
+        last_event_id: Option<u64>,
+        versioned: bool,
+    ) -> Result<Sse<ClientStream>, ServerError> {
+        let subscription = self
+            .subscribe(device_id, pool_kp, last_event_id, versioned)
+            .await?;
+        Ok(Sse::from_stream(ClientStream(subscription))
+            .with_keep_alive(self.timeouts.sse_keep_alive)
+            .with_retry_duration(self.timeouts.reconnect_delay))
+    }
 
-        tx.send(BroadcastMessage::Connected).await.unwrap();
+    /// returns the devices of this pool that are connected to the event stream (sse or websocket) of **this instance**
+    pub fn connected_devices(&self, pool_kp: &KeyPhrase) -> Vec<String> {
         self.inner
             .lock()
             .clients
-            .push((Self::make_client_id(device_id, pool_kp)?, tx));
+            .online_devices(&pool_kp.hash(&self.db.hash_params))
+    }
+
+    /// Returns the devices of this pool that are connected to the event stream of any instance.
+    ///
+    /// The other instances are known by the presence they store, a device that just disconnected from one of them can
+    /// still be reported online until its presence expires
+    pub async fn online_devices(&self, pool_kp: &KeyPhrase) -> Result<Vec<String>, ServerError> {
+        let mut online = self
+            .db
+            .online_devices(&pool_kp.hash(&self.db.hash_params))
+            .await?;
+        for device_id in self.connected_devices(pool_kp) {
+            if !online.contains(&device_id) {
+                online.push(device_id);
+            }
+        }
+        Ok(online)
+    }
+
+    /// the event streams connected to **this instance**
+    pub fn connection_stats(&self) -> ConnectionStats {
+        let inner = self.inner.lock();
+        ConnectionStats {
+            connections: inner.clients.len(),
+            pools: inner.clients.pools.len(),
+        }
+    }
 
-        Ok(rx)
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Stores when the device was last seen and its presence, and tells the other connected devices of the pool.
+    ///
+    /// It's spawned in background since it's also called when a stream is dropped
+    fn presence_changed(self: &Arc<Self>, pool_id: String, device_id: String, online: bool) {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return; // the server is shutting down
+        }
+
+        let this = Arc::clone(self);
+        tokio::spawn(async move {
+            let last_seen = DateTime::now().timestamp_millis();
+            if let Err(err) = this
+                .db
+                .update_last_seen(&pool_id, &device_id, last_seen)
+                .await
+            {
+                console_log(
+                    &format!("failed to update last seen: {err}"),
+                    log::Level::Warn,
+                );
+            }
+            let stored = match online {
+                true => {
+                    let device = [(pool_id.clone(), device_id.clone())];
+                    this.db.refresh_presence(&device, this.presence_ttl()).await
+                }
+                false => this.db.remove_presence(&pool_id, &device_id).await,
+            };
+            if let Err(err) = stored {
+                console_log(
+                    &format!("failed to store the presence: {err}"),
+                    log::Level::Warn,
+                );
+            }
+
+            let presence = SSEData::Presence(Presence {
+                device_id,
+                online,
+                last_seen,
+            });
+            // presence is ephemeral, the replay of old presence events is useless
+            let _ = this.publish(pool_id, None, presence, None, false).await;
+        });
     }
 
     /// Broadcasts `msg` to specified clients of the same pool.
@@ -133,24 +668,538 @@ impl Broadcaster {
         device_id: &[String],
         pool_kp: &KeyPhrase,
         msg: SSEData,
-    ) -> Result<(), ServerErrors> {
-        let clients = self.inner.lock().clients.clone();
-        let to_clients_ids = device_id
-            .iter()
-            .filter_map(|did| Self::make_client_id(did, pool_kp).ok())
-            .collect::<Vec<_>>();
-        if to_clients_ids.len() != device_id.len() {
-            return Err(ServerErrors::HashError);
+    ) -> Result<(), ServerError> {
+        self.publish(
+            pool_kp.hash(&self.db.hash_params),
+            Some(device_id.to_vec()),
+            msg,
+            None,
+            true,
+        )
+        .await
+    }
+
+    /// Broadcasts an incremental change of the pool to these devices, the clients that didn't tell their schema
+    /// version get the updated pool instead
+    pub async fn broadcast_pool_change(
+        &self,
+        device_id: &[String],
+        pool_kp: &KeyPhrase,
+        change: SSEData,
+        pool: DevicesPool,
+    ) -> Result<(), ServerError> {
+        self.publish(
+            pool_kp.hash(&self.db.hash_params),
+            Some(device_id.to_vec()),
+            change,
+            Some(SSEData::Pool(pool)),
+            true,
+        )
+        .await
+    }
+
+    /// Publishes `data` (or `snapshot`, see [`PubSubMessage::snapshot`]) to every instance, if `log` the message is
+    /// kept in the event log for replay.
+    ///
+    /// The id is given by the pub/sub backend, so every instance logs and delivers the event with the same id
+    async fn publish(
+        &self,
+        pool_id: String,
+        devices_id: Option<Vec<String>>,
+        data: SSEData,
+        snapshot: Option<SSEData>,
+        log: bool,
+    ) -> Result<(), ServerError> {
+        let published = async {
+            let event_id = self.pubsub.next_event_id().await?;
+            self.pubsub
+                .publish(PubSubMessage {
+                    pool_id,
+                    devices_id,
+                    event_id,
+                    data,
+                    snapshot,
+                    log,
+                })
+                .await
+        }
+        .await;
+        if published.is_err() {
+            METRICS.broadcast_failed("publish");
         }
+        published
+    }
 
-        let sent_futures = clients
-            .iter()
-            .filter(|(client_id, _)| to_clients_ids.contains(client_id))
-            .map(|(_, sender)| sender.send(BroadcastMessage::Data(msg.clone())));
-        for res in future::join_all(sent_futures).await {
-            res.map_err(|_| ServerErrors::SseFailedToSend)?
+    /// Sends a published message to the recipients connected to this instance.
+    ///
+    /// It never waits for a client: one whose channel is full is disconnected, so that it doesn't hold up the others.
+    /// It reconnects with its `Last-Event-ID` and the missed events are replayed from the event log
+    fn deliver(self: &Arc<Self>, msg: PubSubMessage) {
+        let PubSubMessage {
+            pool_id,
+            devices_id,
+            event_id,
+            data,
+            snapshot,
+            log,
+        } = msg;
+
+        let recipients = {
+            // logged even for disconnected devices, they'll get it when reconnecting (maybe on this instance)
+            let mut inner = self.inner.lock();
+            if log {
+                for did in devices_id.iter().flatten() {
+                    inner.event_log.push(
+                        &Self::make_client_id(&pool_id, did),
+                        event_id,
+                        data.clone(),
+                        snapshot.clone(),
+                    );
+                }
+            }
+            inner.clients.recipients(&pool_id, devices_id.as_deref())
+        };
+
+        let mut too_slow = vec![];
+        for (connection_id, connection) in recipients {
+            let data = match (&snapshot, connection.versioned) {
+                (Some(snapshot), false) => snapshot,
+                _ => &data,
+            };
+            let msg = BroadcastMessage::Data(event_id, Box::new(data.clone()));
+            match connection.sender.try_send(msg) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    METRICS.broadcast_failed("slow_client");
+                    too_slow.push(connection_id);
+                }
+                // the stream is closed, it unregisters itself when dropped
+                Err(mpsc::error::TrySendError::Closed(_)) => METRICS.broadcast_failed("deliver"),
+            }
+        }
+        if too_slow.is_empty() {
+            return;
+        }
+
+        // dropping its sender ends the stream once the client read what's already buffered
+        let went_offline: Vec<_> = {
+            let mut inner = self.inner.lock();
+            too_slow
+                .into_iter()
+                .filter_map(|connection_id| inner.clients.unregister(connection_id))
+                .collect()
+        };
+        for (pool_id, device_id) in went_offline {
+            console_log(
+                &format!("disconnected {device_id}, too slow to read its events"),
+                log::Level::Warn,
+            );
+            self.presence_changed(pool_id, device_id, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use async_trait::async_trait;
+    use futures_util::stream::BoxStream;
+    use tokio::sync::mpsc;
+
+    use ilix_core::{
+        errors::{ServerError, ServerErrors},
+        events::{PoolRenamed, SSEData},
+        keyphrase::KeyPhrase,
+        models::DevicesPool,
+    };
+
+    use crate::{
+        config::{Config, Timeouts},
+        db::IlixDB,
+        utils::pubsub::{InProcessPubSub, PubSub, PubSubMessage},
+    };
+
+    use super::{BroadcastMessage, Broadcaster, ClientRegistry, EventLog};
+
+    const EVENT_LOG_CAPACITY: usize = 100;
+
+    /// stand-in for a backend shared by several instances (like mongodb or redis)
+    struct SharedBus(Arc<InProcessPubSub>);
+
+    #[async_trait]
+    impl PubSub for SharedBus {
+        async fn publish(&self, msg: PubSubMessage) -> Result<(), ServerError> {
+            self.0.publish(msg).await
+        }
+
+        async fn next_event_id(&self) -> Result<u64, ServerError> {
+            self.0.next_event_id().await
+        }
+
+        async fn subscribe(&self) -> Result<BoxStream<'static, PubSubMessage>, ServerError> {
+            self.0.subscribe().await
+        }
+    }
+
+    #[actix_web::test]
+    async fn broadcast_across_instances_test() {
+        // the client connects lazily, nothing reaches mongodb in this test except the last seen updates (which fail)
+        let db = IlixDB::test().await;
+        let config = Config::default();
+        let bus = Arc::new(InProcessPubSub::default());
+        let instance_a =
+            Broadcaster::create(db.clone(), Box::new(SharedBus(Arc::clone(&bus))), &config)
+                .await
+                .unwrap();
+        let instance_b = Broadcaster::create(db, Box::new(SharedBus(bus)), &config)
+            .await
+            .unwrap();
+
+        let kp = KeyPhrase::new(10).unwrap();
+        let mut sasaki = instance_b
+            .subscribe("sasaki", &kp, None, true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            sasaki.recv().await,
+            Some(BroadcastMessage::Connected)
+        ));
+
+        instance_a
+            .broadcast_to(&["sasaki".to_string()], &kp, SSEData::Logout)
+            .await
+            .unwrap();
+        let Some(BroadcastMessage::Data(event_id, data)) = sasaki.recv().await else {
+            panic!("sasaki should have received the event published on the other instance");
+        };
+        assert!(matches!(*data, SSEData::Logout));
+
+        // both instances logged it, the device can reconnect to any of them
+        for instance in [&instance_a, &instance_b] {
+            let missed = instance
+                .missed_events("sasaki", &kp, event_id - 1, true)
+                .unwrap();
+            assert_eq!(missed.len(), 1);
+        }
+    }
+
+    #[actix_web::test]
+    async fn event_ids_across_instances_test() {
+        let db = IlixDB::test().await;
+        let config = Config::default();
+        let bus = Arc::new(InProcessPubSub::default());
+        let instance_a =
+            Broadcaster::create(db.clone(), Box::new(SharedBus(Arc::clone(&bus))), &config)
+                .await
+                .unwrap();
+        let instance_b = Broadcaster::create(db, Box::new(SharedBus(bus)), &config)
+            .await
+            .unwrap();
+
+        let kp = KeyPhrase::new(10).unwrap();
+        let mut sasaki = instance_a
+            .subscribe("sasaki", &kp, None, true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            sasaki.recv().await,
+            Some(BroadcastMessage::Connected)
+        ));
+
+        // one event published by each instance
+        let recipients = ["sasaki".to_string()];
+        instance_a
+            .broadcast_to(&recipients, &kp, SSEData::Logout)
+            .await
+            .unwrap();
+        instance_b
+            .broadcast_to(&recipients, &kp, SSEData::Logout)
+            .await
+            .unwrap();
+        let mut received = vec![];
+        for _ in 0..2 {
+            let Some(BroadcastMessage::Data(event_id, _)) = sasaki.recv().await else {
+                panic!("sasaki should have received both events");
+            };
+            received.push(event_id);
+        }
+        assert_ne!(received[0], received[1]);
+
+        // reconnecting to any instance after the first event replays the second one, with the same id
+        for instance in [&instance_a, &instance_b] {
+            let missed = instance
+                .missed_events("sasaki", &kp, received[0], true)
+                .unwrap();
+            assert_eq!(
+                missed.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+                [received[1]]
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn pool_change_test() {
+        let db = IlixDB::test().await;
+        let sse = Broadcaster::create(db, Box::<InProcessPubSub>::default(), &Config::default())
+            .await
+            .unwrap();
+
+        let kp = KeyPhrase::new(10).unwrap();
+        let mut sasaki = sse.subscribe("sasaki", &kp, None, true).await.unwrap();
+        // an older client, it didn't tell its schema version
+        let mut miyano = sse.subscribe("miyano", &kp, None, false).await.unwrap();
+        for client in [&mut sasaki, &mut miyano] {
+            assert!(matches!(
+                client.recv().await,
+                Some(BroadcastMessage::Connected)
+            ));
+        }
+
+        let pool = DevicesPool {
+            pool_name: "sasamiya".to_string(),
+            devices_id: vec!["sasaki".to_string(), "miyano".to_string()],
+            devices_id_to_name: Default::default(),
+            devices_last_seen: Default::default(),
+            devices_online: vec![],
+            hashed_key_phrase: String::new(),
+            schema_version: DevicesPool::SCHEMA_VERSION,
+            created_at: 0,
+            version: 1,
+        };
+        let renamed = SSEData::PoolRenamed(PoolRenamed {
+            pool_name: "sasamiya".to_string(),
+        });
+        sse.broadcast_pool_change(&pool.devices_id.clone(), &kp, renamed, pool)
+            .await
+            .unwrap();
+
+        // a single event each, with the same id
+        let Some(BroadcastMessage::Data(event_id, data)) = sasaki.recv().await else {
+            panic!("sasaki didn't get the change");
+        };
+        assert!(matches!(*data, SSEData::PoolRenamed(_)));
+        let Some(BroadcastMessage::Data(miyano_event_id, data)) = miyano.recv().await else {
+            panic!("miyano didn't get the change");
+        };
+        assert!(matches!(*data, SSEData::Pool(_)));
+        assert_eq!(event_id, miyano_event_id);
+        assert!(sasaki.rx.try_recv().is_err() && miyano.rx.try_recv().is_err());
+
+        // and the same when replayed
+        let missed = sse
+            .missed_events("miyano", &kp, event_id - 1, false)
+            .unwrap();
+        assert!(matches!(missed[..], [(_, SSEData::Pool(_))]));
+        let missed = sse
+            .missed_events("sasaki", &kp, event_id - 1, true)
+            .unwrap();
+        assert!(matches!(missed[..], [(_, SSEData::PoolRenamed(_))]));
+    }
+
+    #[actix_web::test]
+    async fn slow_client_test() {
+        let db = IlixDB::test().await;
+        let mut config = Config::default();
+        config.limits.sse_channel_size = 1;
+        let sse = Broadcaster::create(db, Box::<InProcessPubSub>::default(), &config)
+            .await
+            .unwrap();
+
+        let kp = KeyPhrase::new(10).unwrap();
+        // never reads, its channel is already full with the connected message
+        let mut sasaki = sse.subscribe("sasaki", &kp, None, true).await.unwrap();
+        let mut miyano = sse.subscribe("miyano", &kp, None, true).await.unwrap();
+        assert!(matches!(
+            miyano.recv().await,
+            Some(BroadcastMessage::Connected)
+        ));
+
+        sse.broadcast_to(
+            &["sasaki".to_string(), "miyano".to_string()],
+            &kp,
+            SSEData::Logout,
+        )
+        .await
+        .unwrap();
+        let Some(BroadcastMessage::Data(event_id, _)) = miyano.recv().await else {
+            panic!("miyano shouldn't wait for sasaki");
+        };
+
+        // disconnected, it gets the event back when reconnecting
+        assert!(matches!(
+            sasaki.recv().await,
+            Some(BroadcastMessage::Connected)
+        ));
+        assert!(sasaki.recv().await.is_none());
+        assert_eq!(sse.connected_devices(&kp), ["miyano"]);
+        assert_eq!(
+            sse.missed_events("sasaki", &kp, event_id - 1, true)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[actix_web::test]
+    async fn shutdown_test() {
+        let db = IlixDB::test().await;
+        let config = Config::default();
+        let sse = Broadcaster::create(db, Box::<InProcessPubSub>::default(), &config)
+            .await
+            .unwrap();
+
+        let kp = KeyPhrase::new(10).unwrap();
+        let mut sasaki = sse.subscribe("sasaki", &kp, None, true).await.unwrap();
+        assert!(matches!(
+            sasaki.recv().await,
+            Some(BroadcastMessage::Connected)
+        ));
+
+        sse.shutdown();
+        let Some(BroadcastMessage::Shutdown(retry)) = sasaki.recv().await else {
+            panic!("sasaki should have been told to reconnect");
+        };
+        assert_eq!(retry, config.timeouts.reconnect_delay);
+        assert!(sasaki.recv().await.is_none()); // closed
+        assert!(sse.connected_devices(&kp).is_empty());
+
+        let Err(err) = sse.subscribe("miyano", &kp, None, true).await else {
+            panic!("no subscription once shutting down");
+        };
+        assert_eq!(err, ServerErrors::ShuttingDown);
+    }
+
+    #[test]
+    fn event_log_test() {
+        let mut log = EventLog::new(EVENT_LOG_CAPACITY, Timeouts::default().event_log_ttl);
+        for id in 0..5 {
+            log.push("sasaki", id, SSEData::Logout, None);
+        }
+        log.push("miyano", 5, SSEData::Logout, None);
+
+        let missed = log.since("sasaki", 2, true);
+        assert_eq!(missed.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [3, 4]);
+        assert!(log.since("sasaki", 4, true).is_empty());
+        assert!(log.since("hirano", 0, true).is_empty());
+
+        // bounded
+        for id in 10..(10 + EVENT_LOG_CAPACITY as u64) {
+            log.push("sasaki", id, SSEData::Logout, None);
         }
+        let missed = log.since("sasaki", 0, true);
+        assert_eq!(missed.len(), EVENT_LOG_CAPACITY);
+        assert_eq!(missed[0].0, 10);
+
+        // replayed in the order they were logged, not by id
+        log.push("hirano", 21, SSEData::Logout, None);
+        log.push("hirano", 20, SSEData::Logout, None);
+        let missed = log.since("hirano", 21, true);
+        assert_eq!(missed.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [20]);
+
+        log.prune(); // nothing expired
+        assert_eq!(log.since("miyano", 0, true).len(), 1);
+    }
+
+    #[test]
+    fn client_registry_test() {
+        let mut registry = ClientRegistry::default();
+        let (tx, _rx) = mpsc::channel(1);
+
+        let (sasaki_phone, came_online) = registry.register("pool1", "sasaki", tx.clone(), true);
+        assert!(came_online);
+        let (sasaki_tablet, came_online) = registry.register("pool1", "sasaki", tx.clone(), true);
+        assert!(!came_online); // already online with its phone
+        registry.register("pool1", "miyano", tx.clone(), true);
+        registry.register("pool2", "sasaki", tx.clone(), true); // same device id, other pool
+        assert_eq!(registry.len(), 4);
 
-        Ok(())
+        let mut online = registry.online_devices("pool1");
+        online.sort();
+        assert_eq!(online, ["miyano", "sasaki"]);
+        // the whole pool
+        assert_eq!(registry.recipients("pool1", None).len(), 3);
+
+        // once per device, whatever its connections count (for the presence refresh)
+        let mut devices = registry.devices();
+        devices.sort();
+        assert_eq!(
+            devices,
+            [
+                ("pool1", "miyano"),
+                ("pool1", "sasaki"),
+                ("pool2", "sasaki")
+            ]
+            .map(|(pool_id, device_id)| (pool_id.to_string(), device_id.to_string()))
+        );
+
+        let devices = ["sasaki".to_string(), "hirano".to_string()];
+        let recipients = registry.recipients("pool1", Some(&devices));
+        let mut ids = recipients.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, [sasaki_phone, sasaki_tablet]);
+        assert!(registry.recipients("pool3", Some(&devices)).is_empty());
+
+        assert_eq!(registry.unregister(sasaki_phone), None); // still online with its tablet
+        assert_eq!(registry.unregister(sasaki_phone), None); // already unregistered
+        assert_eq!(registry.recipients("pool1", Some(&devices)).len(), 1);
+
+        assert_eq!(
+            registry.unregister(sasaki_tablet),
+            Some(("pool1".to_string(), "sasaki".to_string()))
+        );
+        assert!(registry.recipients("pool1", Some(&devices)).is_empty());
+        assert_eq!(registry.len(), 2);
+        assert!(!registry.pools["pool1"].contains_key("sasaki")); // no empty entries left
+    }
+
+    /// Only the `ClientRegistry` bookkeeping of 50k registered connections (register, recipients lookup, unregister),
+    /// their senders stay idle. There are no sockets nor sse streams, it doesn't measure what idle http connections
+    /// cost to actix or to the os.
+    ///
+    /// run with `cargo test --release -- --ignored --nocapture client_registry_lookup_bench`
+    #[test]
+    #[ignore]
+    fn client_registry_lookup_bench() {
+        const POOLS: usize = 10_000;
+        const DEVICES_PER_POOL: usize = 5;
+
+        let mut registry = ClientRegistry::default();
+        let mut receivers = Vec::with_capacity(POOLS * DEVICES_PER_POOL);
+        let start = Instant::now();
+        for pool in 0..POOLS {
+            for device in 0..DEVICES_PER_POOL {
+                let (tx, rx) = mpsc::channel(1);
+                registry.register(&format!("pool{pool}"), &format!("device{device}"), tx, true);
+                receivers.push(rx);
+            }
+        }
+        println!(
+            "registered {} connections in {:?}",
+            registry.len(),
+            start.elapsed()
+        );
+
+        let devices = ["device0".to_string(), "device1".to_string()];
+        let start = Instant::now();
+        for pool in 0..POOLS {
+            assert_eq!(
+                registry
+                    .recipients(&format!("pool{pool}"), Some(&devices))
+                    .len(),
+                2
+            );
+        }
+        println!(
+            "looked up the recipients of {POOLS} broadcasts in {:?}",
+            start.elapsed()
+        );
+
+        let start = Instant::now();
+        for connection_id in 0..(POOLS * DEVICES_PER_POOL) as u64 {
+            registry.unregister(connection_id);
+        }
+        assert_eq!(registry.len(), 0);
+        println!("unregistered every connections in {:?}", start.elapsed());
     }
 }
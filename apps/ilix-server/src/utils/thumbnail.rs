@@ -0,0 +1,65 @@
+use std::io::Cursor;
+
+use image::{codecs::jpeg::JpegEncoder, ImageFormat};
+
+use ilix_core::errors::{ServerError, ServerErrorContext, ServerErrors};
+
+/// thumbnails fit in a `THUMBNAIL_SIZE`x`THUMBNAIL_SIZE` square, the aspect ratio is preserved
+const THUMBNAIL_SIZE: u32 = 256;
+const THUMBNAIL_QUALITY: u8 = 70;
+pub const THUMBNAIL_MIME_TYPE: &str = "image/jpeg";
+
+/// returns the image format we are able to decode for this mime type.
+///
+/// Videos aren't here, we'd need ffmpeg to extract a frame, so they get no thumbnail
+fn supported_format(mime_type: &str) -> Option<ImageFormat> {
+    match mime_type {
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/png" => Some(ImageFormat::Png),
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        "image/bmp" => Some(ImageFormat::Bmp),
+        _ => None,
+    }
+}
+
+/// Creates a small jpeg preview of the image, returns `None` if the file isn't an image we can decode.
+///
+/// Decoding a big image takes a while, call it from a blocking task (`spawn_blocking`), not on the async runtime
+pub fn make_thumbnail(datas: &[u8], mime_type: &str) -> Option<Result<Vec<u8>, ServerError>> {
+    let format = supported_format(mime_type)?;
+
+    let thumbnail = || {
+        let img = image::load_from_memory_with_format(datas, format)
+            .server_err(ServerErrors::ThumbnailError)?
+            .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .into_rgb8(); // jpeg has no alpha channel
+
+        let mut thumbnail_buf = Cursor::new(vec![]);
+        JpegEncoder::new_with_quality(&mut thumbnail_buf, THUMBNAIL_QUALITY)
+            .encode_image(&img)
+            .server_err(ServerErrors::ThumbnailError)?;
+        Ok(thumbnail_buf.into_inner())
+    };
+    Some(thumbnail())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{make_thumbnail, THUMBNAIL_SIZE};
+
+    #[test]
+    fn make_thumbnail_test() {
+        let jpg = fs::read("./tests/Assets/test1.jpg").unwrap();
+        let thumbnail = make_thumbnail(&jpg, "image/jpeg").unwrap().unwrap();
+        assert!(thumbnail.len() < jpg.len());
+
+        let img = image::load_from_memory(&thumbnail).unwrap();
+        assert!(img.width() <= THUMBNAIL_SIZE && img.height() <= THUMBNAIL_SIZE);
+
+        assert!(make_thumbnail(b"sasaki", "text/plain").is_none());
+        assert!(make_thumbnail(b"sasaki", "image/png").unwrap().is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ilix_core::{
+    errors::{ServerError, ServerErrorContext, ServerErrors},
+    keyphrase::KeyPhrase,
+    storage::UploadedFile,
+};
+use mongodb::bson::oid::ObjectId;
+use parking_lot::Mutex;
+
+use crate::db::{cleanups::Cleanup, IlixDB};
+
+use super::console_log;
+
+type UploadId = u64;
+
+/// The uploads whose files aren't attached to a transfer yet.
+///
+/// If the request is dropped midway (the client left, or the server stopped before it finished) its files would be
+/// orphans, so they're deleted then, or when the server stopped at the latest (see [`Uploads::rollback_all`]). If the
+/// server crashed, its [`Cleanup::Upload`] entry is replayed
+#[derive(Clone, Default)]
+pub struct Uploads(Arc<Mutex<UploadsInner>>);
+
+#[derive(Default)]
+struct UploadsInner {
+    next_id: UploadId,
+    pending: HashMap<UploadId, PendingFiles>,
+}
+
+/// the cleanup entry of an upload, logged before its files are stored
+#[derive(Clone, Copy)]
+struct LoggedUpload {
+    cleanup_id: ObjectId,
+    upload_id: ObjectId,
+}
+
+impl LoggedUpload {
+    /// deletes its files, and drops the entry if it succeeded. It returns how many were deleted
+    async fn rollback(self, db: &IlixDB) -> Result<u64, ServerError> {
+        db.rollback_upload(self.cleanup_id, self.upload_id).await
+    }
+}
+
+#[derive(Default)]
+struct PendingFiles {
+    /// `None` until the upload starts storing files
+    logged: Option<LoggedUpload>,
+    /// `None` while they're uploading
+    files_id: Option<Vec<String>>,
+    /// the request was dropped while they were uploading, they're deleted as soon as they're uploaded
+    abandoned: bool,
+}
+
+impl Uploads {
+    pub fn start(&self, db: &IlixDB) -> PendingUpload {
+        let mut inner = self.0.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.pending.insert(id, PendingFiles::default());
+
+        PendingUpload {
+            id,
+            uploads: self.clone(),
+            db: db.clone(),
+            done: false,
+        }
+    }
+
+    /// the number of uploads not attached to a transfer yet
+    pub fn len(&self) -> usize {
+        self.0.lock().pending.len()
+    }
+
+    /// Deletes the files of the uploads that never made it to a transfer, it's called once the server stopped. The
+    /// ones still uploading are left to the cleanups replay.
+    ///
+    /// It returns the number of deleted files
+    pub async fn rollback_all(&self, db: &IlixDB) -> usize {
+        let uploaded = std::mem::take(&mut self.0.lock().pending)
+            .into_values()
+            .filter(|pending| pending.files_id.as_ref().is_some_and(|ids| !ids.is_empty()))
+            .filter_map(|pending| pending.logged)
+            .collect::<Vec<_>>();
+
+        let mut deleted = 0;
+        for logged in uploaded {
+            match logged.rollback(db).await {
+                Ok(count) => deleted += count as usize,
+                Err(err) => console_log(
+                    &format!(
+                        "failed to roll back an unfinished upload, it'll be replayed: {}",
+                        err.chain()
+                    ),
+                    log::Level::Error,
+                ),
+            }
+        }
+        deleted
+    }
+
+    /// stores the cleanup entry of the upload
+    fn logged(&self, id: UploadId, logged: LoggedUpload) {
+        if let Some(pending) = self.0.lock().pending.get_mut(&id) {
+            pending.logged = Some(logged);
+        }
+    }
+
+    /// stores the ids of the uploaded files, returns true if the request was dropped in the meantime
+    fn uploaded(&self, id: UploadId, files_id: &[String]) -> bool {
+        let mut inner = self.0.lock();
+        match inner.pending.get_mut(&id) {
+            Some(pending) if !pending.abandoned => {
+                pending.files_id = Some(files_id.to_vec());
+                false
+            }
+            _ => {
+                inner.pending.remove(&id);
+                true
+            }
+        }
+    }
+
+    fn remove(&self, id: UploadId) -> Option<PendingFiles> {
+        self.0.lock().pending.remove(&id)
+    }
+}
+
+/// An upload tracked by [`Uploads`], it must end with [`PendingUpload::commit`] or [`PendingUpload::rollback`].
+///
+/// If it's dropped before, its files are deleted
+pub struct PendingUpload {
+    id: UploadId,
+    uploads: Uploads,
+    db: IlixDB,
+    done: bool,
+}
+
+impl PendingUpload {
+    /// [`IlixDB::upload_files`] in a task of its own, so that the files ids are known even if the request is
+    /// dropped while they're uploading. A [`Cleanup::Upload`] is logged first, in case the server crashes
+    pub async fn add_files(
+        &self,
+        files: Vec<UploadedFile>,
+        key_phrase: &KeyPhrase,
+    ) -> Result<(Vec<String>, HashMap<String, String>), ServerError> {
+        let upload_id = ObjectId::new();
+        let cleanup_id = self.db.log_cleanup(&Cleanup::Upload { upload_id }).await?;
+        let logged = LoggedUpload {
+            cleanup_id,
+            upload_id,
+        };
+        self.uploads.logged(self.id, logged);
+
+        let (id, uploads, db, key_phrase) = (
+            self.id,
+            self.uploads.clone(),
+            self.db.clone(),
+            key_phrase.clone(),
+        );
+        tokio::spawn(async move {
+            let added = db.upload_files(files, &key_phrase, upload_id).await;
+            let rollback = match &added {
+                Ok((files_id, _)) => uploads.uploaded(id, files_id),
+                Err(_) => uploads.remove(id).is_some(),
+            };
+            // kept for the replay if it fails
+            if rollback {
+                let _ = logged.rollback(&db).await;
+            }
+            added
+        })
+        .await
+        .server_err(ServerErrors::MongoError)?
+    }
+
+    /// the files are attached to a transfer, they're kept
+    pub async fn commit(mut self) {
+        self.done = true;
+        if let Some(logged) = self.uploads.remove(self.id).and_then(|p| p.logged) {
+            // if it's left, the replay sees that the transfer points to them
+            self.db.drop_cleanup(logged.cleanup_id).await;
+        }
+    }
+
+    /// deletes the uploaded files, e.g: the transfer couldn't be created
+    pub async fn rollback(mut self) {
+        self.done = true;
+        if let Some(logged) = self.uploads.remove(self.id).and_then(|p| p.logged) {
+            let _ = logged.rollback(&self.db).await;
+        }
+    }
+}
+
+impl Drop for PendingUpload {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let logged = {
+            let mut inner = self.uploads.0.lock();
+            let Some(pending) = inner.pending.get_mut(&self.id) else {
+                return;
+            };
+            match (&pending.files_id, pending.logged) {
+                (_, None) => {
+                    inner.pending.remove(&self.id); // nothing was stored
+                    return;
+                }
+                (None, Some(_)) => {
+                    pending.abandoned = true; // deleted by the uploading task
+                    return;
+                }
+                (Some(_), Some(logged)) => logged,
+            }
+        };
+        if tokio::runtime::Handle::try_current().is_err() {
+            return; // the server is shutting down, they're left to `rollback_all`
+        }
+
+        // removed only once deleted, in case the task doesn't get to run because the server is stopping
+        let (id, uploads, db) = (self.id, self.uploads.clone(), self.db.clone());
+        tokio::spawn(async move {
+            if logged.rollback(&db).await.is_ok() {
+                uploads.remove(id);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+
+    use crate::db::IlixDB;
+
+    use super::{LoggedUpload, Uploads};
+
+    #[actix_web::test]
+    async fn uploads_test() {
+        // the client connects lazily, nothing reaches mongodb in this test
+        let db = IlixDB::test().await;
+        let uploads = Uploads::default();
+
+        let sasaki = uploads.start(&db);
+        let miyano = uploads.start(&db);
+        assert_eq!(uploads.len(), 2);
+        sasaki.commit().await;
+        assert_eq!(uploads.len(), 1);
+
+        // dropped while uploading, the uploading task cleans up once it's done
+        let logged = LoggedUpload {
+            cleanup_id: ObjectId::new(),
+            upload_id: ObjectId::new(),
+        };
+        uploads.logged(miyano.id, logged);
+        drop(miyano);
+        assert_eq!(uploads.len(), 1);
+        assert!(uploads.uploaded(1, &["file".to_string()]));
+        assert_eq!(uploads.len(), 0);
+
+        // dropped before storing anything
+        drop(uploads.start(&db));
+        assert_eq!(uploads.len(), 0);
+
+        // uploaded, then forgotten, e.g: the server stopped before the transfer was created
+        let hirano = uploads.start(&db);
+        assert!(!uploads.uploaded(hirano.id, &[]));
+        std::mem::forget(hirano);
+        assert_eq!(uploads.rollback_all(&db).await, 0); // no files to delete
+        assert_eq!(uploads.len(), 0);
+    }
+}